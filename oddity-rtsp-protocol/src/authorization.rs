@@ -0,0 +1,184 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::Error;
+
+/// Credentials sent by the client in the `Authorization` header (RFC 2617).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Authorization {
+    Basic {
+        username: String,
+        password: String,
+    },
+    Digest {
+        username: String,
+        realm: String,
+        nonce: String,
+        uri: String,
+        response: String,
+    },
+}
+
+impl FromStr for Authorization {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || Error::AuthorizationMalformed {
+            value: s.to_string(),
+        };
+
+        let (scheme, credentials) = s.trim().split_once(' ').ok_or_else(malformed)?;
+        if scheme.eq_ignore_ascii_case("Basic") {
+            let credentials = base64::decode(credentials.trim()).map_err(|_| malformed())?;
+            let credentials = String::from_utf8(credentials).map_err(|_| malformed())?;
+            let (username, password) = credentials.split_once(':').ok_or_else(malformed)?;
+            Ok(Authorization::Basic {
+                username: username.to_string(),
+                password: password.to_string(),
+            })
+        } else if scheme.eq_ignore_ascii_case("Digest") {
+            let parameters = parse_parameters(credentials).ok_or_else(malformed)?;
+            let parameter = |name: &str| {
+                parameters
+                    .iter()
+                    .find(|(var, _)| var.eq_ignore_ascii_case(name))
+                    .map(|(_, val)| val.to_string())
+                    .ok_or_else(malformed)
+            };
+            Ok(Authorization::Digest {
+                username: parameter("username")?,
+                realm: parameter("realm")?,
+                nonce: parameter("nonce")?,
+                uri: parameter("uri")?,
+                response: parameter("response")?,
+            })
+        } else {
+            Err(Error::AuthorizationSchemeUnknown {
+                value: s.to_string(),
+            })
+        }
+    }
+}
+
+/// Parse comma-separated `var=val` pairs, where the value may be a quoted
+/// string that contains commas itself.
+fn parse_parameters(s: &str) -> Option<Vec<(&str, &str)>> {
+    let mut parameters = Vec::new();
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let (var, after) = rest.split_once('=')?;
+        let after = after.trim_start();
+        let (val, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => match after.find(',') {
+                Some(end) => (&after[..end], &after[end..]),
+                None => (after, ""),
+            },
+        };
+        parameters.push((var.trim(), val.trim()));
+        rest = after.trim_start().trim_start_matches(',').trim_start();
+    }
+    Some(parameters)
+}
+
+/// Challenge sent by the server in the `WWW-Authenticate` header of a `401
+/// Unauthorized` response (RFC 2617).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Challenge {
+    Basic {
+        realm: String,
+    },
+    /// If `stale` is set, the client used a nonce that expired and can retry
+    /// with the new one without asking the user for credentials again.
+    Digest {
+        realm: String,
+        nonce: String,
+        stale: bool,
+    },
+}
+
+impl fmt::Display for Challenge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Challenge::Basic { realm } => write!(f, "Basic realm=\"{}\"", realm),
+            Challenge::Digest {
+                realm,
+                nonce,
+                stale,
+            } => {
+                write!(f, "Digest realm=\"{}\", nonce=\"{}\"", realm, nonce)?;
+                if *stale {
+                    write!(f, ", stale=TRUE")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Authorization, Challenge, Error};
+
+    #[test]
+    fn parse_basic() {
+        assert_eq!(
+            "Basic YWxpY2U6c2VjcmV0".parse::<Authorization>().unwrap(),
+            Authorization::Basic {
+                username: "alice".to_string(),
+                password: "secret".to_string(),
+            },
+        );
+    }
+
+    #[test]
+    fn parse_digest() {
+        assert_eq!(
+            "Digest username=\"alice\", realm=\"oddity\", nonce=\"0123abcd\", \
+             uri=\"rtsp://localhost/example,1\", response=\"6629fae49393a05397450978507c4ef1\""
+                .parse::<Authorization>()
+                .unwrap(),
+            Authorization::Digest {
+                username: "alice".to_string(),
+                realm: "oddity".to_string(),
+                nonce: "0123abcd".to_string(),
+                uri: "rtsp://localhost/example,1".to_string(),
+                response: "6629fae49393a05397450978507c4ef1".to_string(),
+            },
+        );
+    }
+
+    #[test]
+    fn parse_digest_missing_response() {
+        assert!(matches!(
+            "Digest username=\"alice\", realm=\"oddity\", nonce=\"0123abcd\", uri=\"/\""
+                .parse::<Authorization>(),
+            Err(Error::AuthorizationMalformed { .. }),
+        ));
+    }
+
+    #[test]
+    fn parse_scheme_unknown() {
+        assert!(matches!(
+            "Bearer abcdef".parse::<Authorization>(),
+            Err(Error::AuthorizationSchemeUnknown { .. }),
+        ));
+    }
+
+    #[test]
+    fn format_digest_challenge() {
+        assert_eq!(
+            Challenge::Digest {
+                realm: "oddity".to_string(),
+                nonce: "0123abcd".to_string(),
+                stale: true,
+            }
+            .to_string(),
+            "Digest realm=\"oddity\", nonce=\"0123abcd\", stale=TRUE",
+        );
+    }
+}
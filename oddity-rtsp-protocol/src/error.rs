@@ -111,6 +111,10 @@ pub enum Error {
     RtpInfoParameterInvalid { value: String },
     /// RTP Info contains unexpected extra parameter.
     RtpInfoParameterUnexpected { value: String },
+    /// Authorization header uses a scheme other than `Basic` or `Digest`.
+    AuthorizationSchemeUnknown { value: String },
+    /// Authorization header credentials are malformed or incomplete.
+    AuthorizationMalformed { value: String },
     /// Underlying socket was shut down. This is not really an error and
     /// consumers are expected to handle it gracefully.
     Shutdown,
@@ -213,6 +217,12 @@ impl fmt::Display for Error {
             Error::RtpInfoParameterUnexpected { value } => {
                 write!(f, "rtp info contains unexpected parameter: {}", &value)
             }
+            Error::AuthorizationSchemeUnknown { value } => {
+                write!(f, "authorization scheme unknown: {}", &value)
+            }
+            Error::AuthorizationMalformed { value } => {
+                write!(f, "authorization malformed: {}", &value)
+            }
             Error::Shutdown => write!(f, "underlying socket was shut down"),
             Error::Io(err) => write!(f, "{}", err),
         }
@@ -1,3 +1,4 @@
+mod authorization;
 mod buffer;
 mod error;
 mod interleaved;
@@ -14,6 +15,7 @@ mod transport;
 #[cfg(feature = "tokio-codec")]
 mod tokio;
 
+pub use authorization::{Authorization, Challenge};
 pub use error::{Error, Result};
 pub use interleaved::{MaybeInterleaved, RequestMaybeInterleaved, ResponseMaybeInterleaved};
 pub use io::{AsClient, AsServer, Target};
@@ -362,6 +362,68 @@ CSeq: 1
         assert_eq!(request.headers.get("CSeq"), Some(&"1".to_string()));
     }
 
+    #[test]
+    fn parse_options_request_session_with_timeout() {
+        let request = br###"OPTIONS rtsp://example.com/media.mp4 RTSP/1.0
+CSeq: 2
+Session: 12345678;timeout=60
+
+"###;
+
+        let request = RequestParser::new()
+            .parse_and_into_request(request.as_slice())
+            .unwrap();
+        assert_eq!(request.method, Method::Options);
+        assert_eq!(
+            request.headers.get("Session"),
+            Some(&"12345678;timeout=60".to_string())
+        );
+        assert_eq!(request.session(), Some("12345678"));
+    }
+
+    #[test]
+    fn parse_get_parameter_request() {
+        let request = b"GET_PARAMETER rtsp://example.com/media.mp4 RTSP/1.0\r\n\
+CSeq: 3\r\n\
+Session: 12345678\r\n\
+Content-Type: text/parameters\r\n\
+Content-Length: 23\r\n\
+\r\n\
+packets_sent\r\n\
+bitrate\r\n";
+
+        let request = RequestParser::new()
+            .parse_and_into_request(request.as_slice())
+            .unwrap();
+        assert_eq!(request.method, Method::GetParameter);
+        assert_eq!(request.content_type(), Some("text/parameters"));
+        assert_eq!(
+            request.parameters().unwrap(),
+            vec![("packets_sent", None), ("bitrate", None)]
+        );
+    }
+
+    #[test]
+    fn parse_set_parameter_request() {
+        let request = b"SET_PARAMETER rtsp://example.com/media.mp4 RTSP/1.0\r\n\
+CSeq: 4\r\n\
+Session: 12345678\r\n\
+Content-Type: text/parameters\r\n\
+Content-Length: 24\r\n\
+\r\n\
+timeout: 30\r\n\
+label:cam\r\n";
+
+        let request = RequestParser::new()
+            .parse_and_into_request(request.as_slice())
+            .unwrap();
+        assert_eq!(request.method, Method::SetParameter);
+        assert_eq!(
+            request.parameters().unwrap(),
+            vec![("timeout", Some("30")), ("label", Some("cam"))]
+        );
+    }
+
     #[test]
     fn parse_options_response() {
         let response = br###"RTSP/1.0 200 OK
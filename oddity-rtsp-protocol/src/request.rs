@@ -1,6 +1,7 @@
 use std::fmt;
 
 use super::{
+    authorization::Authorization,
     message::{Bytes, Headers, Message, Method, Uri, Version},
     range::Range,
     transport::Transport,
@@ -50,8 +51,37 @@ impl Request {
             .unwrap_or_default()
     }
 
+    /// Session identifier, without any parameters such as the timeout (RFC
+    /// 2326 Section 12.37).
     pub fn session(&self) -> Option<&str> {
-        self.headers.get("Session").map(|val| val.as_str())
+        self.headers
+            .get("Session")
+            .map(|val| val.split(';').next().unwrap_or_default().trim())
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.get("Content-Type").map(|val| val.as_str())
+    }
+
+    /// Parameters in the body of a GET_PARAMETER or SET_PARAMETER request with
+    /// content type `text/parameters`. Every line holds the name of a parameter,
+    /// optionally followed by a colon and its value (RFC 2326 Section 10.8 and
+    /// 10.9).
+    pub fn parameters(&self) -> Result<Vec<(&str, Option<&str>)>, Error> {
+        let body = match self.body.as_ref() {
+            Some(body) => std::str::from_utf8(body).map_err(|_| Error::Encoding)?,
+            None => return Ok(Vec::new()),
+        };
+
+        Ok(body
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| match line.split_once(':') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (line, None),
+            })
+            .collect())
     }
 
     pub fn transport(&self) -> Result<Vec<Transport>, Error> {
@@ -68,6 +98,10 @@ impl Request {
     pub fn range(&self) -> Option<Result<Range, Error>> {
         self.headers.get("Range").map(|value| value.parse())
     }
+
+    pub fn authorization(&self) -> Option<Result<Authorization, Error>> {
+        self.headers.get("Authorization").map(|value| value.parse())
+    }
 }
 
 impl fmt::Display for Request {
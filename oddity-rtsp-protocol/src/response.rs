@@ -52,6 +52,13 @@ impl Response {
             _ => StatusCategory::Unknown,
         }
     }
+
+    /// Session identifier, without any parameters such as the timeout.
+    pub fn session(&self) -> Option<&str> {
+        self.headers
+            .get("Session")
+            .map(|val| val.split(';').next().unwrap_or_default().trim())
+    }
 }
 
 impl fmt::Display for Response {
@@ -159,6 +166,17 @@ impl ResponseBuilder {
         self.with_body(Bytes::from(contents), "application/sdp")
     }
 
+    pub fn with_parameters(
+        self,
+        parameters: impl IntoIterator<Item = (impl fmt::Display, impl fmt::Display)>,
+    ) -> ResponseBuilder {
+        let contents = parameters
+            .into_iter()
+            .map(|(name, value)| format!("{}: {}\r\n", name, value))
+            .collect::<String>();
+        self.with_body(Bytes::from(contents), "text/parameters")
+    }
+
     pub fn build(self) -> Response {
         self.response
     }
@@ -237,6 +237,28 @@ Public: DESCRIBE, SETUP, TEARDOWN, PLAY, PAUSE\r\n\
         assert_eq!(response_serialized, response_bytes);
     }
 
+    #[test]
+    fn serialize_get_parameter_response_with_parameters() {
+        let response_bytes = Bytes::from(
+            b"RTSP/1.0 200 OK\r\n\
+Content-Length: 29\r\n\
+Content-Type: text/parameters\r\n\
+\r\n\
+packets_sent: 10\r\n\
+uptime: 5\r\n\
+"
+            .as_slice(),
+        );
+
+        let response = Response::ok()
+            .with_parameters([("packets_sent", 10), ("uptime", 5)])
+            .build();
+
+        let mut response_serialized = BytesMut::new();
+        response.serialize(&mut response_serialized).unwrap();
+        assert_eq!(response_serialized, response_bytes);
+    }
+
     #[test]
     fn serialize_options_response_error() {
         let response_bytes = Bytes::from(
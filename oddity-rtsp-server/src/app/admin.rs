@@ -0,0 +1,434 @@
+//! HTTP API for managing sources while the server is running:
+//!
+//! * `GET /sources` lists the sources and their status.
+//! * `POST /sources` adds a source, described by a JSON object with the same
+//!   fields as a media item in the configuration file.
+//! * `PUT /sources/<path>` replaces the file or URL of a source.
+//! * `DELETE /sources/<path>` removes a source and tears down its sessions.
+
+use std::convert::Infallible;
+use std::error;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::RwLock;
+
+use video_rs::Url;
+
+use crate::app::config::Item;
+use crate::app::AppContext;
+use crate::media::MediaDescriptor;
+use crate::runtime::task_manager::{Task, TaskContext};
+use crate::runtime::Runtime;
+use crate::source::source_manager::{RegisterSourceError, SourceInfo};
+use crate::source::{self, SourcePathRef};
+
+type Context = Arc<RwLock<AppContext>>;
+
+pub struct AdminServer {
+    worker: Task,
+}
+
+impl AdminServer {
+    pub async fn start(
+        host: IpAddr,
+        port: u16,
+        context: Context,
+        runtime: Arc<Runtime>,
+    ) -> Result<Self, hyper::Error> {
+        tracing::trace!(%host, port, "starting admin server");
+        let builder = match hyper::Server::try_bind(&SocketAddr::new(host, port)) {
+            Ok(builder) => builder,
+            Err(err) => {
+                tracing::error!(%err, %host, port, "failed to listen for admin connections");
+                return Err(err);
+            }
+        };
+        tracing::info!(%host, port, "admin server listening for incoming connections");
+
+        let worker = runtime
+            .task()
+            .spawn(move |task_context| Self::run(builder, context, task_context))
+            .await;
+        tracing::trace!(%host, port, "started admin server");
+
+        Ok(Self { worker })
+    }
+
+    pub async fn stop(&mut self) {
+        tracing::trace!("sending stop signal to admin server");
+        self.worker.stop().await;
+        tracing::trace!("admin server stopped");
+    }
+
+    async fn run(
+        builder: hyper::server::Builder<AddrIncoming>,
+        context: Context,
+        mut task_context: TaskContext,
+    ) {
+        let make_service = make_service_fn(move |_| {
+            let context = context.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| handle(request, context.clone())))
+            }
+        });
+
+        if let Err(err) = builder
+            .serve(make_service)
+            .with_graceful_shutdown(task_context.wait_for_stop())
+            .await
+        {
+            tracing::error!(%err, "admin server failed");
+        }
+        tracing::trace!("admin server worker stopping");
+    }
+}
+
+async fn handle(request: Request<Body>, context: Context) -> Result<Response<Body>, Infallible> {
+    let method = request.method().clone();
+    let uri_path = request.uri().path().to_string();
+    tracing::debug!(%method, uri_path, "admin request");
+
+    let source_path = uri_path
+        .strip_prefix("/sources")
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+        .map(|rest| rest.trim_end_matches('/'));
+    let response = match (method, source_path) {
+        (Method::GET, Some("")) => list(&context).await,
+        (Method::POST, Some("")) => add(request, &context).await,
+        (Method::PUT, Some(path)) if !path.is_empty() => replace(path, request, &context).await,
+        (Method::DELETE, Some(path)) if !path.is_empty() => remove(path, &context).await,
+        (_, Some(_)) => Err(AdminError::MethodNotAllowed),
+        (_, None) => Err(AdminError::NotFound),
+    };
+
+    Ok(response.unwrap_or_else(|err| {
+        tracing::debug!(%err, uri_path, "admin request failed");
+        reply_json(
+            err.status(),
+            &ErrorView {
+                error: err.to_string(),
+            },
+        )
+    }))
+}
+
+async fn list(context: &Context) -> Result<Response<Body>, AdminError> {
+    let sources = context
+        .read()
+        .await
+        .source_manager
+        .list()
+        .await
+        .into_iter()
+        .map(SourceView::from)
+        .collect::<Vec<_>>();
+    Ok(reply_json(StatusCode::OK, &sources))
+}
+
+async fn add(request: Request<Body>, context: &Context) -> Result<Response<Body>, AdminError> {
+    let item: Item = read_json(request).await?;
+    if item.is_publish_point() {
+        return Err(AdminError::NotSupported(
+            "publish points can only be added in the configuration file",
+        ));
+    }
+    let invalid = |err: Box<dyn error::Error>| AdminError::BodyInvalid(err.to_string());
+    let descriptor = item.as_media_descriptor().map_err(invalid)?;
+    let fallbacks = item.as_fallback_descriptors().map_err(invalid)?;
+    let failback_interval = item.as_failback_interval().map_err(invalid)?;
+    let multicast_group = item.as_multicast_group().map_err(invalid)?;
+    let vod = item.is_vod().map_err(invalid)?;
+    let reconnect = item.as_reconnect_policy().map_err(invalid)?;
+    let on_demand = item.as_on_demand().map_err(invalid)?;
+    let path = source::normalize_path(item.path.clone());
+
+    let context = context.read().await;
+    let _registration = context.registration.lock().await;
+    if context.source_manager.info(&path).await.is_some()
+        || context.publish_manager.is_registered(&path).await
+    {
+        return Err(AdminError::Register(RegisterSourceError::AlreadyRegistered));
+    }
+
+    // The restriction goes first, so that there is no moment at which the
+    // source is accessible to anyone.
+    if !item.users.is_empty() {
+        if let Some(user) = item
+            .users
+            .iter()
+            .find(|user| !context.authenticator.has_user(user))
+        {
+            return Err(AdminError::BodyInvalid(format!("unknown user: {}", user)));
+        }
+        context
+            .authenticator
+            .restrict(path.clone(), item.users.iter().cloned())
+            .await;
+    }
+
+    tracing::info!(%item, "adding source");
+    if let Err(err) = context
+        .source_manager
+        .register_and_start(
+            item.name.as_str(),
+            path.clone(),
+            descriptor,
+            fallbacks,
+            failback_interval,
+            multicast_group,
+            vod,
+            reconnect,
+            item.as_stall_timeout(),
+            on_demand,
+        )
+        .await
+    {
+        // If someone else registered the path after all, the restriction is
+        // theirs now.
+        let is_ours = !matches!(err, RegisterSourceError::AlreadyRegistered);
+        if !item.users.is_empty() && is_ours {
+            context.authenticator.unrestrict(&path).await;
+        }
+        return Err(AdminError::Register(err));
+    }
+
+    let info = context
+        .source_manager
+        .info(&path)
+        .await
+        .ok_or(AdminError::NotFound)?;
+    Ok(reply_json(StatusCode::CREATED, &SourceView::from(info)))
+}
+
+#[derive(Deserialize)]
+struct Replacement {
+    source: String,
+}
+
+async fn replace(
+    path: &SourcePathRef,
+    request: Request<Body>,
+    context: &Context,
+) -> Result<Response<Body>, AdminError> {
+    let replacement: Replacement = read_json(request).await?;
+
+    let context = context.read().await;
+    let info = context
+        .source_manager
+        .info(path)
+        .await
+        .ok_or(AdminError::NotFound)?;
+    let descriptor = match &info.descriptor {
+        MediaDescriptor::File(_) => MediaDescriptor::File(PathBuf::from(replacement.source)),
+        MediaDescriptor::Stream(_) => MediaDescriptor::Stream(
+            replacement
+                .source
+                .parse::<Url>()
+                .map_err(|err| AdminError::BodyInvalid(err.to_string()))?,
+        ),
+        MediaDescriptor::Published(..) => {
+            return Err(AdminError::NotSupported(
+                "published sources cannot be replaced",
+            ))
+        }
+    };
+
+    // Sessions cannot switch over to another source, so their clients have to
+    // set up new ones.
+    tracing::info!(path, source = %descriptor, "replacing source");
+    context.session_manager.teardown_all(path).await;
+    context.source_manager.remove(path).await;
+    if let Err(err) = context
+        .source_manager
+        .register_and_start(
+            info.name.as_str(),
+            info.path.clone(),
+            descriptor,
+            info.fallbacks.clone(),
+            info.failback_interval,
+            info.multicast_group,
+            info.vod,
+            info.reconnect,
+            info.stall_timeout,
+            info.on_demand,
+        )
+        .await
+    {
+        // A typo in the replacement should not make the path disappear.
+        tracing::error!(%err, path, "failed to start replacement, restoring source");
+        if let Err(err) = context
+            .source_manager
+            .register_and_start(
+                info.name.as_str(),
+                info.path.clone(),
+                info.descriptor.clone(),
+                info.fallbacks.clone(),
+                info.failback_interval,
+                info.multicast_group,
+                info.vod,
+                info.reconnect,
+                info.stall_timeout,
+                info.on_demand,
+            )
+            .await
+        {
+            tracing::error!(%err, path, "failed to restore source");
+        }
+        return Err(AdminError::Register(err));
+    }
+
+    let info = context
+        .source_manager
+        .info(path)
+        .await
+        .ok_or(AdminError::NotFound)?;
+    Ok(reply_json(StatusCode::OK, &SourceView::from(info)))
+}
+
+async fn remove(path: &SourcePathRef, context: &Context) -> Result<Response<Body>, AdminError> {
+    let context = context.read().await;
+    let info = context
+        .source_manager
+        .info(path)
+        .await
+        .ok_or(AdminError::NotFound)?;
+    if info.descriptor.is_published() {
+        return Err(AdminError::NotSupported(
+            "published sources end when the publisher stops",
+        ));
+    }
+
+    // Tearing down the sessions first means they end cleanly instead of
+    // finding out that their source broke.
+    let sessions = context.session_manager.teardown_all(path).await;
+    context.source_manager.remove(path).await;
+    context.authenticator.unrestrict(path).await;
+    tracing::info!(path, sessions, "removed source");
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    Ok(response)
+}
+
+async fn read_json<T: DeserializeOwned>(request: Request<Body>) -> Result<T, AdminError> {
+    let body = hyper::body::to_bytes(request.into_body())
+        .await
+        .map_err(|err| AdminError::BodyInvalid(err.to_string()))?;
+    serde_json::from_slice(&body).map_err(|err| AdminError::BodyInvalid(err.to_string()))
+}
+
+fn reply_json(status: StatusCode, value: &impl Serialize) -> Response<Body> {
+    let mut response = Response::new(Body::from(serde_json::to_vec(value).unwrap_or_default()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+#[derive(Serialize)]
+struct SourceView {
+    name: String,
+    path: String,
+    kind: &'static str,
+    source: String,
+    fallbacks: Vec<FallbackView>,
+    status: String,
+    vod: bool,
+    on_demand: bool,
+    multicast: Option<String>,
+}
+
+#[derive(Serialize)]
+struct FallbackView {
+    kind: &'static str,
+    source: String,
+}
+
+/// Kind and source of a descriptor, as they appear in the configuration.
+fn kind_and_source(descriptor: MediaDescriptor) -> (&'static str, String) {
+    match descriptor {
+        MediaDescriptor::File(path) => ("file", path.display().to_string()),
+        MediaDescriptor::Stream(url) => ("stream", url.to_string()),
+        MediaDescriptor::Published(path, _) => ("published", path.display().to_string()),
+    }
+}
+
+impl From<SourceInfo> for SourceView {
+    fn from(info: SourceInfo) -> Self {
+        let (kind, source) = kind_and_source(info.descriptor);
+        Self {
+            name: info.name,
+            path: info.path,
+            kind,
+            source,
+            fallbacks: info
+                .fallbacks
+                .into_iter()
+                .map(|fallback| {
+                    let (kind, source) = kind_and_source(fallback);
+                    FallbackView { kind, source }
+                })
+                .collect(),
+            status: info.status.to_string(),
+            vod: info.vod,
+            on_demand: info.on_demand.is_some(),
+            multicast: info
+                .multicast_group
+                .map(|group| group.rtp_addr().to_string()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorView {
+    error: String,
+}
+
+#[derive(Debug)]
+pub enum AdminError {
+    NotFound,
+    MethodNotAllowed,
+    BodyInvalid(String),
+    NotSupported(&'static str),
+    Register(RegisterSourceError),
+}
+
+impl AdminError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AdminError::NotFound => StatusCode::NOT_FOUND,
+            AdminError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            AdminError::BodyInvalid(_) => StatusCode::BAD_REQUEST,
+            AdminError::NotSupported(_) => StatusCode::BAD_REQUEST,
+            AdminError::Register(RegisterSourceError::AlreadyRegistered) => StatusCode::CONFLICT,
+            // The source itself could not be opened or described.
+            AdminError::Register(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdminError::NotFound => write!(f, "not found"),
+            AdminError::MethodNotAllowed => write!(f, "method not allowed"),
+            AdminError::BodyInvalid(message) => write!(f, "body invalid: {}", message),
+            AdminError::NotSupported(message) => write!(f, "not supported: {}", message),
+            AdminError::Register(err) => write!(f, "failed to register source: {}", err),
+        }
+    }
+}
+
+impl error::Error for AdminError {}
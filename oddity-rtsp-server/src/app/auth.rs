@@ -0,0 +1,459 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::Instant;
+
+use oddity_rtsp_protocol::{Authorization, Challenge, Request, Uri};
+
+use crate::app::config::AuthScheme;
+use crate::source::{self, SourcePath, SourcePathRef};
+
+/// Realm that users authenticate for.
+const REALM: &str = "oddity";
+
+/// Clients reuse the nonce for every request after the first challenge, so
+/// it has to last for a while.
+const NONCE_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// Every unauthenticated request gets a fresh nonce, so we limit how many
+/// we remember.
+const MAX_NONCES: usize = 1024;
+
+/// Outcome of checking the credentials of a request.
+pub enum Access {
+    Granted,
+    /// The client did not provide valid credentials, and needs to try again
+    /// with the given challenge.
+    Challenge(Challenge),
+    /// The client is authenticated, but the user does not have access.
+    Forbidden,
+}
+
+/// Checks credentials of requests against the configured users and the
+/// users that have access to each path.
+pub struct Authenticator {
+    scheme: AuthScheme,
+    passwords: HashMap<String, String>,
+    rules: RwLock<HashMap<SourcePath, HashSet<String>>>,
+    nonces: Mutex<HashMap<String, Instant>>,
+}
+
+impl Authenticator {
+    pub fn new(scheme: AuthScheme, users: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self {
+            scheme,
+            passwords: users.into_iter().collect(),
+            rules: RwLock::new(HashMap::new()),
+            nonces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn has_user(&self, name: &str) -> bool {
+        self.passwords.contains_key(name)
+    }
+
+    /// Only allow the given users access to the path and its tracks.
+    pub async fn restrict(&self, path: SourcePath, users: impl IntoIterator<Item = String>) {
+        let path = source::normalize_path(path);
+        self.rules
+            .write()
+            .await
+            .insert(path, users.into_iter().collect());
+    }
+
+    /// Remove the restriction on the path, if there is one.
+    pub async fn unrestrict(&self, path: &SourcePathRef) {
+        self.rules.write().await.remove(path);
+    }
+
+    pub async fn check(&self, request: &Request) -> Access {
+        // The most specific rule wins if paths are nested.
+        let allowed_users = match self
+            .rules
+            .read()
+            .await
+            .iter()
+            .filter(|(path, _)| source::is_path_below(request.path(), path))
+            .max_by_key(|(path, _)| path.len())
+        {
+            Some((_, allowed_users)) => allowed_users.clone(),
+            None => return Access::Granted,
+        };
+
+        let authorization = match request.authorization() {
+            Some(Ok(authorization)) => authorization,
+            Some(Err(err)) => {
+                tracing::debug!(%err, "ignoring invalid authorization");
+                return Access::Challenge(self.challenge(false).await);
+            }
+            None => return Access::Challenge(self.challenge(false).await),
+        };
+
+        let username = match self.authenticate(request, &authorization).await {
+            Ok(username) => username,
+            Err(stale) => return Access::Challenge(self.challenge(stale).await),
+        };
+
+        if allowed_users.contains(username) {
+            tracing::trace!(username, path = request.path(), "access granted");
+            Access::Granted
+        } else {
+            tracing::debug!(username, path = request.path(), "access forbidden");
+            Access::Forbidden
+        }
+    }
+
+    /// Verify the credentials and produce the name of the user. On failure,
+    /// returns whether or not that was only because the nonce expired.
+    async fn authenticate<'a>(
+        &self,
+        request: &Request,
+        authorization: &'a Authorization,
+    ) -> Result<&'a str, bool> {
+        match (self.scheme, authorization) {
+            (AuthScheme::Basic, Authorization::Basic { username, password }) => {
+                if self.passwords.get(username) == Some(password) {
+                    Ok(username)
+                } else {
+                    tracing::debug!(username, "wrong username or password");
+                    Err(false)
+                }
+            }
+            (
+                AuthScheme::Digest,
+                Authorization::Digest {
+                    username,
+                    realm,
+                    nonce,
+                    uri,
+                    response,
+                },
+            ) => {
+                match self.nonces.lock().await.get(nonce) {
+                    Some(issued) if issued.elapsed() < NONCE_LIFETIME => {}
+                    Some(_) => return Err(true),
+                    None => return Err(false),
+                };
+
+                let password = match self.passwords.get(username) {
+                    Some(password) => password,
+                    None => {
+                        tracing::debug!(username, "unknown user");
+                        return Err(false);
+                    }
+                };
+
+                // The credentials are only valid for the resource they were
+                // computed for.
+                let is_same_path = uri
+                    .parse::<Uri>()
+                    .map(|uri| uri.path().trim_end_matches('/') == request.path())
+                    .unwrap_or(false);
+                if realm != REALM || !is_same_path {
+                    return Err(false);
+                }
+
+                // RFC 2617 Section 3.2.2.1 without `qop`, since we do not offer it.
+                let ha1 = md5_hex(&format!("{}:{}:{}", username, realm, password));
+                let ha2 = md5_hex(&format!("{}:{}", request.method, uri));
+                let expected = md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2));
+                if response.eq_ignore_ascii_case(&expected) {
+                    Ok(username)
+                } else {
+                    tracing::debug!(username, "wrong username or password");
+                    Err(false)
+                }
+            }
+            _ => {
+                tracing::debug!("client used authentication scheme we did not ask for");
+                Err(false)
+            }
+        }
+    }
+
+    async fn challenge(&self, stale: bool) -> Challenge {
+        match self.scheme {
+            AuthScheme::Basic => Challenge::Basic {
+                realm: REALM.to_string(),
+            },
+            AuthScheme::Digest => {
+                let nonce = format!("{:032x}", rand::random::<u128>());
+                let mut nonces = self.nonces.lock().await;
+                nonces.retain(|_, issued| issued.elapsed() < NONCE_LIFETIME);
+                if nonces.len() >= MAX_NONCES {
+                    if let Some(oldest) = nonces
+                        .iter()
+                        .min_by_key(|(_, issued)| **issued)
+                        .map(|(nonce, _)| nonce.clone())
+                    {
+                        nonces.remove(&oldest);
+                    }
+                }
+                nonces.insert(nonce.clone(), Instant::now());
+                Challenge::Digest {
+                    realm: REALM.to_string(),
+                    nonce,
+                    stale,
+                }
+            }
+        }
+    }
+}
+
+fn md5_hex(data: &str) -> String {
+    format!("{:x}", md5::compute(data))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use tokio::time::advance;
+
+    use oddity_rtsp_protocol::{Challenge, Headers, Method, Request, Version};
+
+    use crate::app::config::AuthScheme;
+
+    use super::{md5_hex, Access, Authenticator, NONCE_LIFETIME, REALM};
+
+    async fn authenticator(scheme: AuthScheme) -> Authenticator {
+        let authenticator = Authenticator::new(
+            scheme,
+            [
+                ("alice".to_string(), "secret".to_string()),
+                ("bob".to_string(), "hunter2".to_string()),
+            ],
+        );
+        authenticator
+            .restrict("/cams".to_string(), ["alice".to_string()])
+            .await;
+        authenticator
+            .restrict("cams/lobby".to_string(), ["bob".to_string()])
+            .await;
+        authenticator
+    }
+
+    fn request(uri: &str, authorization: Option<String>) -> Request {
+        let mut headers = Headers::new();
+        if let Some(authorization) = authorization {
+            headers.insert("Authorization".to_string(), authorization);
+        }
+        Request {
+            method: Method::Describe,
+            uri: uri.parse().unwrap(),
+            version: Version::V1,
+            headers,
+            body: None,
+        }
+    }
+
+    fn basic(username: &str, password: &str) -> String {
+        let credentials = match (username, password) {
+            ("alice", "secret") => "YWxpY2U6c2VjcmV0",
+            ("alice", "secret2") => "YWxpY2U6c2VjcmV0Mg==",
+            ("mallory", "secret") => "bWFsbG9yeTpzZWNyZXQ=",
+            ("bob", "hunter2") => "Ym9iOmh1bnRlcjI=",
+            _ => unimplemented!(),
+        };
+        format!("Basic {}", credentials)
+    }
+
+    fn digest(username: &str, password: &str, nonce: &str, uri: &str) -> String {
+        let ha1 = md5_hex(&format!("{}:{}:{}", username, REALM, password));
+        let ha2 = md5_hex(&format!("DESCRIBE:{}", uri));
+        let response = md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2));
+        format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", response=\"{}\"",
+            username, REALM, nonce, uri, response
+        )
+    }
+
+    async fn nonce(authenticator: &Authenticator) -> String {
+        match authenticator
+            .check(&request("rtsp://localhost/cams", None))
+            .await
+        {
+            Access::Challenge(Challenge::Digest { nonce, stale, .. }) => {
+                assert!(!stale);
+                nonce
+            }
+            _ => panic!("expected digest challenge"),
+        }
+    }
+
+    #[tokio::test]
+    async fn basic_compares_password() {
+        let authenticator = authenticator(AuthScheme::Basic).await;
+        let uri = "rtsp://localhost/cams";
+        assert!(matches!(
+            authenticator
+                .check(&request(uri, Some(basic("alice", "secret"))))
+                .await,
+            Access::Granted,
+        ));
+        assert!(matches!(
+            authenticator
+                .check(&request(uri, Some(basic("alice", "secret2"))))
+                .await,
+            Access::Challenge(Challenge::Basic { .. }),
+        ));
+        assert!(matches!(
+            authenticator
+                .check(&request(uri, Some(basic("mallory", "secret"))))
+                .await,
+            Access::Challenge(Challenge::Basic { .. }),
+        ));
+    }
+
+    #[tokio::test]
+    async fn basic_rejects_digest_credentials() {
+        let authenticator = authenticator(AuthScheme::Basic).await;
+        let uri = "rtsp://localhost/cams";
+        assert!(matches!(
+            authenticator
+                .check(&request(uri, Some(digest("alice", "secret", "0123", uri))))
+                .await,
+            Access::Challenge(Challenge::Basic { .. }),
+        ));
+    }
+
+    #[tokio::test]
+    async fn digest_verifies_response() {
+        let authenticator = authenticator(AuthScheme::Digest).await;
+        let nonce = nonce(&authenticator).await;
+        let uri = "rtsp://localhost/cams";
+        assert!(matches!(
+            authenticator
+                .check(&request(uri, Some(digest("alice", "secret", &nonce, uri))))
+                .await,
+            Access::Granted,
+        ));
+        assert!(matches!(
+            authenticator
+                .check(&request(uri, Some(digest("alice", "secret2", &nonce, uri))))
+                .await,
+            Access::Challenge(Challenge::Digest { stale: false, .. }),
+        ));
+        // Authenticated, but not allowed.
+        assert!(matches!(
+            authenticator
+                .check(&request(uri, Some(digest("bob", "hunter2", &nonce, uri))))
+                .await,
+            Access::Forbidden,
+        ));
+    }
+
+    #[tokio::test]
+    async fn digest_rejects_unknown_nonce() {
+        let authenticator = authenticator(AuthScheme::Digest).await;
+        let uri = "rtsp://localhost/cams";
+        assert!(matches!(
+            authenticator
+                .check(&request(
+                    uri,
+                    Some(digest("alice", "secret", "0123abcd", uri))
+                ))
+                .await,
+            Access::Challenge(Challenge::Digest { stale: false, .. }),
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn digest_nonce_expires() {
+        let authenticator = authenticator(AuthScheme::Digest).await;
+        let nonce = nonce(&authenticator).await;
+        let uri = "rtsp://localhost/cams";
+        let authorization = digest("alice", "secret", &nonce, uri);
+        advance(NONCE_LIFETIME - std::time::Duration::from_secs(1)).await;
+        assert!(matches!(
+            authenticator
+                .check(&request(uri, Some(authorization.clone())))
+                .await,
+            Access::Granted,
+        ));
+        advance(std::time::Duration::from_secs(1)).await;
+        // The credentials are still right, so the client only needs to retry
+        // with a new nonce.
+        match authenticator
+            .check(&request(uri, Some(authorization)))
+            .await
+        {
+            Access::Challenge(Challenge::Digest {
+                nonce: new_nonce,
+                stale: true,
+                ..
+            }) => assert_ne!(new_nonce, nonce),
+            _ => panic!("expected stale digest challenge"),
+        }
+    }
+
+    #[tokio::test]
+    async fn digest_uri_must_match_path() {
+        let authenticator = authenticator(AuthScheme::Digest).await;
+        let nonce = nonce(&authenticator).await;
+        let uri = "rtsp://localhost/cams/";
+        for digest_uri in ["rtsp://localhost/cams", "rtsp://localhost/cams/", "/cams"] {
+            assert!(matches!(
+                authenticator
+                    .check(&request(
+                        uri,
+                        Some(digest("alice", "secret", &nonce, digest_uri))
+                    ))
+                    .await,
+                Access::Granted,
+            ));
+        }
+        for digest_uri in [
+            "rtsp://localhost/cams/lobby",
+            "rtsp://localhost/other/cams",
+            "rtsp://localhost/",
+            "not a uri",
+        ] {
+            assert!(matches!(
+                authenticator
+                    .check(&request(
+                        uri,
+                        Some(digest("alice", "secret", &nonce, digest_uri))
+                    ))
+                    .await,
+                Access::Challenge(Challenge::Digest { stale: false, .. }),
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn most_specific_rule_wins() {
+        let authenticator = authenticator(AuthScheme::Basic).await;
+        let check = |uri: &'static str, username: &'static str, password: &'static str| {
+            let authenticator = &authenticator;
+            async move {
+                authenticator
+                    .check(&request(uri, Some(basic(username, password))))
+                    .await
+            }
+        };
+        assert!(matches!(
+            check("rtsp://localhost/cams/yard", "alice", "secret").await,
+            Access::Granted,
+        ));
+        assert!(matches!(
+            check("rtsp://localhost/cams/yard", "bob", "hunter2").await,
+            Access::Forbidden,
+        ));
+        assert!(matches!(
+            check("rtsp://localhost/cams/lobby/trackID=0", "bob", "hunter2").await,
+            Access::Granted,
+        ));
+        assert!(matches!(
+            check("rtsp://localhost/cams/lobby", "alice", "secret").await,
+            Access::Forbidden,
+        ));
+        // Not below `/cams`, and not restricted at all.
+        assert!(matches!(
+            authenticator
+                .check(&request("rtsp://localhost/camsx", None))
+                .await,
+            Access::Granted,
+        ));
+    }
+}
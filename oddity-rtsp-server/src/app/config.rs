@@ -1,40 +1,382 @@
 use std::error::Error;
 use std::fmt;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use serde::Deserialize;
 
 use config::{Config, ConfigError};
 
 use crate::media::MediaDescriptor;
+use crate::net::send_queue::SlowClientPolicy;
+use crate::net::udp::MulticastGroup;
+use crate::source::reconnect::ReconnectPolicy;
+use crate::source::Source;
 
 #[derive(Debug, Deserialize)]
 pub struct AppConfig {
     pub server: Server,
+    #[serde(default)]
+    pub users: Vec<User>,
     pub media: Vec<Item>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, PartialEq)]
 pub struct Server {
     pub host: String,
     pub port: u16,
+    /// Number of seconds after which a session without any activity from its
+    /// client is torn down.
+    #[serde(default = "Server::default_session_timeout")]
+    pub session_timeout: u64,
+    /// Scheme used to authenticate users for media items that require it.
+    #[serde(default)]
+    pub auth: AuthScheme,
+    /// Also accept RTSP over TLS if set.
+    pub tls: Option<Tls>,
+    /// Limits the media queued for clients that receive it interleaved.
+    #[serde(default)]
+    pub send_queue: SendQueue,
+    /// Serve the HTTP API for managing sources at runtime if set. Anyone
+    /// that can reach it can change the sources.
+    pub admin: Option<HttpListener>,
+    /// Serve Prometheus metrics on `/metrics` if set.
+    pub metrics: Option<HttpListener>,
 }
 
-#[derive(Debug, Deserialize)]
+impl Server {
+    /// RFC 2326 Section 12.37 specifies a default timeout of 60 seconds.
+    fn default_session_timeout() -> u64 {
+        60
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Tls {
+    #[serde(default = "Tls::default_port")]
+    pub port: u16,
+    /// PEM encoded certificate chain.
+    pub cert: PathBuf,
+    /// PEM encoded private key.
+    pub key: PathBuf,
+}
+
+impl Tls {
+    /// Port registered with IANA for `rtsps`.
+    fn default_port() -> u16 {
+        322
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct SendQueue {
+    /// Number of bytes of media that can be queued for a single connection.
+    #[serde(default = "SendQueue::default_size")]
+    pub size: usize,
+    /// What to do when the queue is full.
+    #[serde(default)]
+    pub policy: SlowClientPolicy,
+}
+
+impl SendQueue {
+    /// A few seconds of video at typical bitrates.
+    fn default_size() -> usize {
+        4 * 1024 * 1024
+    }
+}
+
+impl Default for SendQueue {
+    fn default() -> Self {
+        Self {
+            size: Self::default_size(),
+            policy: SlowClientPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct HttpListener {
+    /// HTTP endpoints are not meant for clients, so they only listen on the
+    /// loopback interface unless configured otherwise.
+    #[serde(default = "HttpListener::default_host")]
+    pub host: String,
+    pub port: u16,
+}
+
+impl HttpListener {
+    fn default_host() -> String {
+        "127.0.0.1".to_string()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthScheme {
+    /// Sends the password in plain text, only use it if clients do not
+    /// support digest authentication.
+    Basic,
+    #[default]
+    Digest,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct User {
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct Item {
     pub name: String,
     pub path: String,
+    pub kind: MediaKind,
+    /// Publish points have no source, publishers push the stream instead.
+    #[serde(default)]
+    pub source: String,
+    /// Played in order while `source` is unavailable, until it recovers.
+    #[serde(default)]
+    pub fallbacks: Vec<Fallback>,
+    pub multicast: Option<Multicast>,
+    #[serde(default)]
+    pub vod: bool,
+    /// Only connect to the source while someone is watching.
+    #[serde(default)]
+    pub on_demand: bool,
+    /// Number of seconds after which an on-demand source disconnects once
+    /// nobody is watching anymore.
+    #[serde(default = "Item::default_idle_timeout")]
+    pub idle_timeout: u64,
+    /// How to reconnect when the source breaks.
+    #[serde(default)]
+    pub reconnect: Reconnect,
+    /// Number of seconds between checks of whether the source recovered,
+    /// while one of its fallbacks plays instead.
+    #[serde(default = "Item::default_failback_interval")]
+    pub failback_interval: u64,
+    /// Number of seconds without progress after which the stream of the
+    /// source is restarted. Zero turns this off.
+    #[serde(default = "Item::default_stall_timeout")]
+    pub stall_timeout: u64,
+    /// Users that have access to the item. If there are none, anyone does.
+    #[serde(default)]
+    pub users: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Fallback {
     pub kind: MediaKind,
     pub source: String,
 }
 
+/// Reconnect settings of an item. Anything left out is taken from
+/// [`ReconnectPolicy::default`].
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct Reconnect {
+    /// Number of seconds to wait before the first retry.
+    pub initial_delay: Option<u64>,
+    /// Number of seconds the delay between retries grows to at most.
+    pub max_delay: Option<u64>,
+    /// Factor the delay is multiplied by after every failed attempt.
+    pub backoff: Option<f64>,
+    /// Fraction by which each delay is randomly made shorter or longer.
+    pub jitter: Option<f64>,
+    /// Number of failed attempts after which the source gives up until
+    /// someone asks for it again. If not set, it never gives up.
+    pub max_attempts: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Multicast {
+    pub group: String,
+    pub port: u16,
+    #[serde(default = "Multicast::default_ttl")]
+    pub ttl: u32,
+}
+
+impl Multicast {
+    fn default_ttl() -> u32 {
+        16
+    }
+}
+
 impl Item {
+    /// Long enough that a client that reconnects right away does not have to
+    /// wait for the source to connect again.
+    fn default_idle_timeout() -> u64 {
+        10
+    }
+
+    fn default_failback_interval() -> u64 {
+        Source::DEFAULT_FAILBACK_INTERVAL.as_secs()
+    }
+
+    /// Well above the keyframe interval of most cameras, so that a short
+    /// hiccup does not cost every viewer a restart.
+    fn default_stall_timeout() -> u64 {
+        10
+    }
+
     pub fn as_media_descriptor(&self) -> Result<MediaDescriptor, Box<dyn Error>> {
         Ok(match self.kind {
             MediaKind::File => MediaDescriptor::File(PathBuf::from(self.source.to_string())),
             MediaKind::Stream => MediaDescriptor::Stream(self.source.parse()?),
+            MediaKind::Publish => {
+                return Err(format!("publish point does not have a source: {}", self).into())
+            }
         })
     }
+
+    /// Descriptors of the fallbacks, in order. Sessions of video-on-demand
+    /// items read the file by themselves, so there is nothing to fall back
+    /// to while they play.
+    pub fn as_fallback_descriptors(&self) -> Result<Vec<MediaDescriptor>, Box<dyn Error>> {
+        if self.fallbacks.is_empty() {
+            return Ok(Vec::new());
+        }
+        if self.is_publish_point() {
+            return Err(format!("publish point cannot have fallbacks: {}", self).into());
+        }
+        if self.vod {
+            return Err(format!("video-on-demand cannot have fallbacks: {}", self).into());
+        }
+        self.fallbacks
+            .iter()
+            .map(|fallback| {
+                Ok(match fallback.kind {
+                    MediaKind::File => MediaDescriptor::File(PathBuf::from(&fallback.source)),
+                    MediaKind::Stream => MediaDescriptor::Stream(fallback.source.parse()?),
+                    MediaKind::Publish => {
+                        return Err(format!("fallback cannot be a publish point: {}", self).into())
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Whether or not the item is served as video-on-demand. Only files can be
+    /// served this way, and since every session reads the file by itself, it
+    /// cannot be combined with multicast.
+    pub fn is_vod(&self) -> Result<bool, Box<dyn Error>> {
+        if self.vod {
+            if !matches!(self.kind, MediaKind::File) {
+                return Err(format!("video-on-demand requires a file source: {}", self).into());
+            }
+            if self.multicast.is_some() {
+                return Err(format!("video-on-demand cannot be multicast: {}", self).into());
+            }
+        }
+        Ok(self.vod)
+    }
+
+    /// Idle timeout of the source if it is on-demand. Sessions of
+    /// video-on-demand items read the file by themselves, so there is nothing
+    /// to connect on demand.
+    pub fn as_failback_interval(&self) -> Result<Duration, Box<dyn Error>> {
+        if self.failback_interval == 0 {
+            return Err(format!("failback interval must be at least 1 second: {}", self).into());
+        }
+        Ok(Duration::from_secs(self.failback_interval))
+    }
+
+    pub fn as_on_demand(&self) -> Result<Option<Duration>, Box<dyn Error>> {
+        if !self.on_demand {
+            return Ok(None);
+        }
+        if self.is_publish_point() {
+            return Err(format!("publish point cannot be on-demand: {}", self).into());
+        }
+        if self.vod {
+            return Err(format!("video-on-demand cannot be on-demand: {}", self).into());
+        }
+        Ok(Some(Duration::from_secs(self.idle_timeout)))
+    }
+
+    pub fn as_reconnect_policy(&self) -> Result<ReconnectPolicy, Box<dyn Error>> {
+        let default = ReconnectPolicy::default();
+        let policy = ReconnectPolicy {
+            initial_delay: self
+                .reconnect
+                .initial_delay
+                .map(Duration::from_secs)
+                .unwrap_or(default.initial_delay),
+            max_delay: self
+                .reconnect
+                .max_delay
+                .map(Duration::from_secs)
+                .unwrap_or(default.max_delay),
+            backoff: self.reconnect.backoff.unwrap_or(default.backoff),
+            jitter: self.reconnect.jitter.unwrap_or(default.jitter),
+            max_attempts: self.reconnect.max_attempts.or(default.max_attempts),
+        };
+        if !(1.0..).contains(&policy.backoff) {
+            return Err(format!("reconnect backoff must be at least 1: {}", self).into());
+        }
+        if !(0.0..=1.0).contains(&policy.jitter) {
+            return Err(format!("reconnect jitter must be between 0 and 1: {}", self).into());
+        }
+        if policy.initial_delay > policy.max_delay {
+            return Err(format!(
+                "reconnect initial delay cannot be longer than max delay: {}",
+                self
+            )
+            .into());
+        }
+        if policy.max_attempts == Some(0) {
+            return Err(format!("reconnect max attempts must be at least 1: {}", self).into());
+        }
+        Ok(policy)
+    }
+
+    pub fn as_stall_timeout(&self) -> Option<Duration> {
+        if self.stall_timeout > 0 {
+            Some(Duration::from_secs(self.stall_timeout))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_publish_point(&self) -> bool {
+        matches!(self.kind, MediaKind::Publish)
+    }
+
+    /// Whether or not the other item is served the same way, apart from who
+    /// has access to it.
+    pub fn is_same_media(&self, other: &Item) -> bool {
+        self.name == other.name
+            && self.kind == other.kind
+            && self.source == other.source
+            && self.fallbacks == other.fallbacks
+            && self.failback_interval == other.failback_interval
+            && self.multicast == other.multicast
+            && self.vod == other.vod
+            && self.on_demand == other.on_demand
+            && self.idle_timeout == other.idle_timeout
+            && self.reconnect == other.reconnect
+            && self.stall_timeout == other.stall_timeout
+    }
+
+    pub fn as_multicast_group(&self) -> Result<Option<MulticastGroup>, Box<dyn Error>> {
+        match self.multicast.as_ref() {
+            Some(multicast) => {
+                let addr: IpAddr = multicast.group.parse()?;
+                if !addr.is_multicast() {
+                    return Err(format!("not a multicast address: {}", addr).into());
+                }
+                // RTCP is sent on the port after the RTP port, and RFC 3550
+                // requires the RTP port to be even.
+                if multicast.port % 2 != 0 || multicast.port == u16::MAX {
+                    return Err(format!("multicast port must be even: {}", multicast.port).into());
+                }
+                Ok(Some(MulticastGroup {
+                    addr,
+                    port: multicast.port,
+                    ttl: multicast.ttl,
+                }))
+            }
+            None => Ok(None),
+        }
+    }
 }
 
 impl fmt::Display for Item {
@@ -47,11 +389,12 @@ impl fmt::Display for Item {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum MediaKind {
     File,
     Stream,
+    Publish,
 }
 
 impl fmt::Display for MediaKind {
@@ -59,6 +402,7 @@ impl fmt::Display for MediaKind {
         match self {
             MediaKind::File => write!(f, "file"),
             MediaKind::Stream => write!(f, "live stream"),
+            MediaKind::Publish => write!(f, "publish point"),
         }
     }
 }
@@ -69,7 +413,14 @@ impl Default for AppConfig {
             server: Server {
                 host: "127.0.0.1".to_string(),
                 port: 554,
+                session_timeout: Server::default_session_timeout(),
+                auth: AuthScheme::default(),
+                tls: None,
+                send_queue: SendQueue::default(),
+                admin: None,
+                metrics: None,
             },
+            users: Vec::new(),
             media: Vec::new(),
         }
     }
@@ -0,0 +1,96 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use tokio::fs;
+use tokio::select;
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+/// Notices when the configuration file should be read again: when the
+/// process receives `SIGHUP`, or when the file was modified.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    interval: Interval,
+    hangup: Hangup,
+}
+
+impl ConfigWatcher {
+    /// How often the modification time of the file is checked.
+    const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+    pub async fn new(path: &Path) -> io::Result<Self> {
+        let mut interval = time::interval(Self::POLL_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Ok(Self {
+            path: path.to_path_buf(),
+            modified: Self::modified(path).await,
+            interval,
+            hangup: hangup()?,
+        })
+    }
+
+    /// Wait until the configuration file should be read again.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancel safe.
+    pub async fn changed(&mut self) {
+        loop {
+            select! {
+              // CANCEL SAFETY: `Signal::recv` is cancel safe.
+              _ = recv_hangup(&mut self.hangup) => {
+                tracing::info!(path = %self.path.display(), "received hangup, reloading config");
+                self.modified = Self::modified(&self.path).await;
+                return;
+              },
+              // CANCEL SAFETY: `Interval::tick` is cancel safe.
+              _ = self.interval.tick() => {
+                let modified = Self::modified(&self.path).await;
+                if modified.is_some() && modified != self.modified {
+                  tracing::info!(path = %self.path.display(), "config file changed, reloading");
+                  self.modified = modified;
+                  return;
+                }
+              },
+            }
+        }
+    }
+
+    /// Modification time of the file. Missing files are ignored, since some
+    /// editors replace the file by removing it first.
+    async fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+}
+
+#[cfg(unix)]
+type Hangup = tokio::signal::unix::Signal;
+
+#[cfg(unix)]
+fn hangup() -> io::Result<Hangup> {
+    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+}
+
+#[cfg(unix)]
+async fn recv_hangup(hangup: &mut Hangup) {
+    hangup.recv().await;
+}
+
+/// There is no `SIGHUP` on other platforms, only changes to the file are
+/// noticed there.
+#[cfg(not(unix))]
+type Hangup = ();
+
+#[cfg(not(unix))]
+fn hangup() -> io::Result<Hangup> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn recv_hangup(_hangup: &mut Hangup) {
+    futures::future::pending::<()>().await;
+}
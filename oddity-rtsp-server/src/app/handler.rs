@@ -1,14 +1,29 @@
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::sync::{RwLock, RwLockReadGuard};
 
-use oddity_rtsp_protocol::{Error, Method, Range, Request, Response, RtpInfo, Status, Transport};
+use oddity_rtsp_protocol::{
+    Challenge, Error, Lower, Method, Range, Request, Response, RtpInfo, Status, Transport,
+};
 
+use crate::app::auth::Access;
+use crate::app::parameters::{Parameter, ParameterValue};
 use crate::app::AppContext;
-use crate::net::connection::ResponseSenderTx;
+use crate::media::sdp::SdpError;
+use crate::media::track;
+use crate::net::connection::ConnectionInfo;
+use crate::net::send_queue::ResponseSenderTx;
+use crate::publish::{Announcement, PublishError};
 use crate::session::session_manager::RegisterSessionError;
 use crate::session::setup::{SessionSetup, SessionSetupError};
-use crate::session::{PlaySessionError, SessionId};
+use crate::session::transport;
+use crate::session::{
+    AddTrackError, PauseSessionError, PlaySessionError, SessionId, TeardownSessionError,
+};
+use crate::source::reconnect::ReconnectPolicy;
+use crate::source::Source;
 
 /// Identifies the server by its product name and version. We use
 /// the built-in `concat` and `env` macros to construct this string
@@ -25,7 +40,12 @@ impl AppHandler {
         Self { context }
     }
 
-    pub async fn handle(&self, request: &Request, responder: &ResponseSenderTx) -> Response {
+    pub async fn handle(
+        &self,
+        request: &Request,
+        connection: &ConnectionInfo,
+        responder: &ResponseSenderTx,
+    ) -> Response {
         tracing::trace!(%request, "handling request");
 
         // Check the Require header and make sure all requested options are
@@ -34,6 +54,31 @@ impl AppHandler {
             return reply_option_not_supported(request);
         }
 
+        // Requests that describe, publish or play media, or read or change its
+        // parameters, require the client to have access to it. The other
+        // requests either do not touch the media or require a session that the
+        // client could only get with access.
+        if is_request_authentication_required(request) {
+            match self.use_context().await.authenticator.check(request).await {
+                Access::Granted => {}
+                Access::Challenge(challenge) => return reply_unauthorized(request, challenge),
+                Access::Forbidden => return reply_forbidden(request),
+            }
+        }
+
+        // Any request within a session is a sign of life from the client, and
+        // postpones the session timeout.
+        let session_alive = match request.session() {
+            Some(session_id) => {
+                self.use_context()
+                    .await
+                    .session_manager
+                    .keep_alive(&session_id.into())
+                    .await
+            }
+            None => false,
+        };
+
         match request.method {
             /* Stateless */
             Method::Options => {
@@ -42,23 +87,67 @@ impl AppHandler {
             }
             Method::Announce => {
                 tracing::trace!("handling ANNOUNCE request");
-                reply_method_not_supported(request)
+                if !is_request_content_type_sdp(request) {
+                    return reply_unsupported_media_type(request);
+                }
+
+                let announcement = match request
+                    .body
+                    .as_ref()
+                    .and_then(|body| std::str::from_utf8(body).ok())
+                    .map(Announcement::parse)
+                {
+                    Some(Ok(announcement)) => announcement,
+                    Some(Err(_)) | None => return reply_bad_request(request),
+                };
+
+                match self
+                    .use_context()
+                    .await
+                    .publish_manager
+                    .announce(request.path(), announcement)
+                    .await
+                {
+                    Ok(()) => {
+                        tracing::trace!(path = request.path(), "accepted announcement");
+                        reply_to_announce(request)
+                    }
+                    Err(err) => reply_publish_error(request, err),
+                }
             }
             Method::Describe => {
                 tracing::trace!("handling DESCRIBE request");
                 if is_request_one_of_content_types_supported(request) {
-                    tracing::trace!(path = request.path(), "querying SDP file for source");
-                    match self
-                        .use_context()
-                        .await
-                        .source_manager
-                        .describe(request.path())
-                        .await
+                    let context = self.use_context().await;
+                    if let Some(retry_after) =
+                        context.source_manager.retry_after(request.path()).await
                     {
+                        return reply_service_unavailable(request, retry_after);
+                    }
+                    tracing::trace!(path = request.path(), "querying SDP file for source");
+                    match context.source_manager.describe(request.path()).await {
                         Some(Ok(sdp_contents)) => {
                             tracing::trace!(path=request.path(), %sdp_contents, "have SDP");
                             reply_to_describe_with_media_sdp(request, sdp_contents.to_string())
                         }
+                        Some(Err(SdpError::SourceUnavailable)) => {
+                            tracing::debug!(%request, "source unavailable for SDP");
+                            // The source went down while it was being described,
+                            // so the client has to wait for it to reconnect.
+                            let retry_after =
+                                match context.source_manager.retry_after(request.path()).await {
+                                    Some(retry_after) => Some(retry_after),
+                                    None => context
+                                        .source_manager
+                                        .info(request.path())
+                                        .await
+                                        .map(|info| info.reconnect.initial_delay),
+                                };
+                            reply_service_unavailable(
+                                request,
+                                retry_after.unwrap_or(ReconnectPolicy::default().initial_delay),
+                            )
+                        }
                         Some(Err(err)) => {
                             tracing::error!(%request, %err, "failed to query SDP of media source");
                             reply_internal_server_error(request)
@@ -76,23 +165,168 @@ impl AppHandler {
             }
             Method::GetParameter => {
                 tracing::trace!("handling GET_PARAMETER request");
-                reply_method_not_supported(request)
+                let names = match request.parameters() {
+                    Ok(names) => names,
+                    Err(_) => return reply_bad_request(request),
+                };
+
+                if names.is_empty() {
+                    // Clients use GET_PARAMETER without a body to keep their session
+                    // alive, which has already been taken care of.
+                    return if request.session().is_some() && !session_alive {
+                        reply_session_not_found(request)
+                    } else {
+                        reply_to_get_parameter(request, Vec::new())
+                    };
+                }
+
+                if !is_request_content_type_parameters(request) {
+                    return reply_unsupported_media_type(request);
+                }
+
+                let parameters = match names
+                    .iter()
+                    .map(|(name, _)| name.parse::<Parameter>())
+                    .collect::<Result<Vec<_>, _>>()
+                {
+                    Ok(parameters) => parameters,
+                    Err(()) => return reply_invalid_parameter(request),
+                };
+
+                let session_info = match request.session() {
+                    Some(session_id) => {
+                        match self
+                            .use_context()
+                            .await
+                            .session_manager
+                            .info(&session_id.into())
+                            .await
+                        {
+                            Some(session_info) => Some(session_info),
+                            None => return reply_session_not_found(request),
+                        }
+                    }
+                    None => None,
+                };
+
+                let mut values = Vec::with_capacity(parameters.len());
+                for parameter in parameters {
+                    let value = match (parameter, session_info.as_ref()) {
+                        (Parameter::SourceStatus, session_info) => {
+                            let path = match session_info {
+                                Some(session_info) => session_info.path.as_str(),
+                                None => track::split_path(request.path()).0,
+                            };
+                            match self.use_context().await.source_manager.status(path).await {
+                                Some(status) => status.to_string(),
+                                None => return reply_not_found(request),
+                            }
+                        }
+                        // All other parameters are about the session.
+                        (_, None) => return reply_session_not_found(request),
+                        (Parameter::Bitrate, Some(session_info)) => {
+                            session_info.stats.bitrate().to_string()
+                        }
+                        (Parameter::Uptime, Some(session_info)) => {
+                            session_info.stats.uptime().as_secs().to_string()
+                        }
+                        (Parameter::PacketsSent, Some(session_info)) => {
+                            session_info.stats.packets_sent().to_string()
+                        }
+                        (Parameter::BytesSent, Some(session_info)) => {
+                            session_info.stats.bytes_sent().to_string()
+                        }
+                        (Parameter::PacketsLost, Some(session_info)) => session_info
+                            .stats
+                            .quality()
+                            .map(|quality| quality.packets_lost.to_string())
+                            .unwrap_or_default(),
+                        (Parameter::FractionLost, Some(session_info)) => session_info
+                            .stats
+                            .quality()
+                            .map(|quality| format!("{:.3}", quality.fraction_lost as f64 / 256.0))
+                            .unwrap_or_default(),
+                        (Parameter::Jitter, Some(session_info)) => session_info
+                            .stats
+                            .quality()
+                            .map(|quality| quality.jitter.to_string())
+                            .unwrap_or_default(),
+                        (Parameter::RoundTripTime, Some(session_info)) => session_info
+                            .stats
+                            .quality()
+                            .and_then(|quality| quality.round_trip_time)
+                            .map(|rtt| rtt.as_millis().to_string())
+                            .unwrap_or_default(),
+                        (Parameter::Timeout, Some(session_info)) => {
+                            session_info.timeout.as_secs().to_string()
+                        }
+                        (Parameter::Label, Some(session_info)) => {
+                            session_info.label.clone().unwrap_or_default()
+                        }
+                    };
+                    values.push((parameter, value));
+                }
+
+                reply_to_get_parameter(request, values)
             }
             Method::SetParameter => {
                 tracing::trace!("handling SET_PARAMETER request");
-                reply_method_not_supported(request)
+                let session_id: SessionId = match request.session() {
+                    Some(session_id) if session_alive => session_id.into(),
+                    _ => return reply_session_not_found(request),
+                };
+
+                let parameters = match request.parameters() {
+                    Ok(parameters) => parameters,
+                    Err(_) => return reply_bad_request(request),
+                };
+
+                if !parameters.is_empty() && !is_request_content_type_parameters(request) {
+                    return reply_unsupported_media_type(request);
+                }
+
+                // All parameters are checked before changing any of them, so that the
+                // request either succeeds or has no effect at all.
+                let mut values = Vec::with_capacity(parameters.len());
+                for (name, value) in parameters {
+                    let parameter = match name.parse::<Parameter>() {
+                        Ok(parameter) => parameter,
+                        Err(()) => return reply_invalid_parameter(request),
+                    };
+
+                    if !parameter.is_writable() {
+                        return reply_parameter_is_read_only(request);
+                    }
+
+                    match value.and_then(|value| ParameterValue::parse(parameter, value)) {
+                        Some(value) => values.push(value),
+                        None => return reply_invalid_parameter(request),
+                    }
+                }
+
+                let context = self.use_context().await;
+                for value in values {
+                    let found = match value {
+                        ParameterValue::Timeout(timeout) => {
+                            context
+                                .session_manager
+                                .set_timeout(&session_id, timeout)
+                                .await
+                        }
+                        ParameterValue::Label(label) => {
+                            context.session_manager.set_label(&session_id, label).await
+                        }
+                    };
+                    if !found {
+                        return reply_session_not_found(request);
+                    }
+                }
+
+                reply_to_set_parameter(request)
             }
             /* Stateful */
             Method::Setup => {
                 tracing::trace!("handling SETUP request");
-                if request.session().is_some() {
-                    // RFC specification allows negatively responding to SETUP request with Session
-                    // IDs by responding with 459 Aggregate Operation Not Allowed. By handling this
-                    // here we don't have to deal with clients trying to change transport parameters
-                    // on media items that are already playing.
-                    return reply_aggregate_operation_not_allowed(request);
-                }
-
                 let transport = match request.transport() {
                     Ok(transport) => transport,
                     Err(_) => {
@@ -103,11 +337,61 @@ impl AppHandler {
                 };
                 tracing::trace!(path = request.path(), ?transport, "resolved transport");
 
+                // Publishers set up the media they are about to push with mode set to
+                // RECORD. We only accept that media interleaved on the connection.
+                if transport.iter().any(transport::is_record) {
+                    let transport = match transport.iter().find(|transport| {
+                        transport::is_record(transport)
+                            && transport::is_supported(transport)
+                            && transport::lower_protocol(transport) == Lower::Tcp
+                    }) {
+                        Some(transport) => transport,
+                        None => return reply_unsupported_transport(request),
+                    };
+                    let session_id = request.session().map(SessionId::from);
+                    return match self
+                        .use_context()
+                        .await
+                        .publish_manager
+                        .setup(
+                            session_id.as_ref(),
+                            request.path(),
+                            &request.uri().to_string(),
+                            transport,
+                        )
+                        .await
+                    {
+                        Ok((session_id, transport)) => {
+                            tracing::trace!(path = request.path(), %session_id, "set up publication");
+                            reply_to_setup(request, &session_id, None, &transport)
+                        }
+                        Err(err) => reply_publish_error(request, err),
+                    };
+                }
+
+                // The client sets up a single track at a time. Without a track in the
+                // path, the first track (video) is meant.
+                let (path, track) = track::split_path(request.path());
+                let track = track.unwrap_or(0);
+                tracing::trace!(path, track, "resolved track");
+
+                // A source that is not live would leave the client waiting for media
+                // information that may not come for a while.
+                if let Some(retry_after) = self
+                    .use_context()
+                    .await
+                    .source_manager
+                    .retry_after(path)
+                    .await
+                {
+                    return reply_service_unavailable(request, retry_after);
+                }
+
                 let mut source_delegate = match self
                     .use_context()
                     .await
                     .source_manager
-                    .subscribe(request.path())
+                    .subscribe(path)
                     .await
                 {
                     Some(source_delegate) => source_delegate,
@@ -124,22 +408,46 @@ impl AppHandler {
                             path = request.path(),
                             "failed to query media info from source",
                         );
-                        return reply_internal_server_error(request);
+                        // The source went down in between.
+                        let retry_after = self
+                            .use_context()
+                            .await
+                            .source_manager
+                            .retry_after(path)
+                            .await
+                            .unwrap_or(ReconnectPolicy::default().initial_delay);
+                        return reply_service_unavailable(request, retry_after);
                     }
                 };
 
+                let multicast_group = source_delegate.multicast_group().cloned();
                 let session_setup = match SessionSetup::from_rtsp_candidate_transports(
                     transport,
                     media_info,
+                    track,
+                    request.uri().to_string(),
+                    source_delegate.is_vod(),
+                    multicast_group.as_ref(),
+                    connection,
                     responder.clone(),
                 )
                 .await
                 {
                     Ok(session_setup) => session_setup,
+                    Err(SessionSetupError::TrackNotFound) => {
+                        return reply_not_found(request);
+                    }
                     Err(SessionSetupError::TransportNotSupported)
                     | Err(SessionSetupError::DestinationInvalid) => {
                         return reply_unsupported_transport(request);
                     }
+                    Err(SessionSetupError::Io(err)) => {
+                        tracing::error!(
+                          %request, %err,
+                          "failed to setup sockets for session",
+                        );
+                        return reply_internal_server_error(request);
+                    }
                     Err(SessionSetupError::Media(err)) => {
                         tracing::error!(
                           %request, %err,
@@ -151,6 +459,41 @@ impl AppHandler {
                 tracing::trace!(path = request.path(), "setup session");
 
                 let transport = session_setup.rtsp_transport.clone();
+                let session_timeout = self.use_context().await.session_manager.timeout();
+                if let Some(session_id) = request.session() {
+                    let session_id: SessionId = session_id.into();
+                    return match self
+                        .use_context()
+                        .await
+                        .session_manager
+                        .add_track(&session_id, path, session_setup)
+                        .await
+                    {
+                        Some(Ok(())) => {
+                            tracing::trace!(path, track, %session_id, "added track to session");
+                            reply_to_setup(request, &session_id, Some(session_timeout), &transport)
+                        }
+                        // RFC specification allows negatively responding to SETUP request with
+                        // Session IDs by responding with 459 Aggregate Operation Not Allowed. By
+                        // handling this here we don't have to deal with clients trying to change
+                        // transport parameters on tracks that are already playing.
+                        Some(Err(AddTrackError::TrackAlreadySetup))
+                        | Some(Err(AddTrackError::SourceMismatch)) => {
+                            reply_aggregate_operation_not_allowed(request)
+                        }
+                        Some(Err(AddTrackError::TransportMismatch)) => {
+                            reply_unsupported_transport(request)
+                        }
+                        Some(Err(AddTrackError::ControlBroken)) => {
+                            tracing::error!(
+              %request,
+              "session control channel unexpectedly broke");
+                            reply_internal_server_error(request)
+                        }
+                        None => reply_session_not_found(request),
+                    };
+                }
+
                 match self
                     .use_context()
                     .await
@@ -161,7 +504,7 @@ impl AppHandler {
                     // Session was successfully registered!
                     Ok(session_id) => {
                         tracing::trace!(path=request.path(), %session_id, "registered session");
-                        reply_to_setup(request, &session_id, &transport)
+                        reply_to_setup(request, &session_id, Some(session_timeout), &transport)
                     }
                     // In the highly unlikely case that the randomly generated session was already
                     // in use before.
@@ -194,29 +537,41 @@ impl AppHandler {
                     None => None,
                 };
 
+                let (path, track) = track::split_path(request.path());
                 if let Some(session_id) = request.session() {
                     match self
                         .use_context()
                         .await
                         .session_manager
-                        .play(&session_id.into(), range.clone())
+                        .play(&session_id.into(), path, track, range.clone())
                         .await
                     {
-                        Some(Ok(stream_state)) => {
+                        Some(Ok(stream_states)) => {
                             // Either just echo back the range the client requested, since
                             // we accepted it it will be correct or just generate a generic
                             // `now-` range.
                             let range = range.unwrap_or_else(Range::new_for_live);
-                            // Construct RTP-Info based on the request URI, and the stream
-                            // state, which includes the last RTP sequence number, and the
-                            // current RTP timestamp.
-                            let rtp_info = RtpInfo::new_with_timing(
-                                &request.uri().to_string(),
-                                stream_state.rtp_seq,
-                                stream_state.rtp_timestamp,
-                            );
+                            // Construct RTP-Info based on the control URL of each track, and
+                            // its stream state, which includes the last RTP sequence number,
+                            // and the current RTP timestamp.
+                            let rtp_info = stream_states
+                                .iter()
+                                .map(|stream_state| {
+                                    RtpInfo::new_with_timing(
+                                        &stream_state.control,
+                                        stream_state.stream_state.rtp_seq,
+                                        stream_state.stream_state.rtp_timestamp,
+                                    )
+                                })
+                                .collect();
                             reply_to_play(request, range, rtp_info)
                         }
+                        Some(Err(PlaySessionError::SourceMismatch)) => {
+                            reply_session_not_found(request)
+                        }
+                        Some(Err(PlaySessionError::OnlyAggregateOperationAllowed)) => {
+                            reply_only_aggregate_operation_allowed(request)
+                        }
                         Some(Err(PlaySessionError::RangeNotSupported)) => {
                             tracing::error!(
                 %request,
@@ -237,25 +592,115 @@ impl AppHandler {
             }
             Method::Pause => {
                 tracing::trace!("handling PAUSE request");
-                reply_method_not_supported(request)
+                // Note that we ignore the Range header, if any. The session is always
+                // paused immediately, which RFC 2326 allows.
+                let (path, track) = track::split_path(request.path());
+                if let Some(session_id) = request.session() {
+                    match self
+                        .use_context()
+                        .await
+                        .session_manager
+                        .pause(&session_id.into(), path, track)
+                        .await
+                    {
+                        Some(Ok(())) => reply_to_pause(request),
+                        Some(Err(PauseSessionError::SourceMismatch)) => {
+                            reply_session_not_found(request)
+                        }
+                        Some(Err(PauseSessionError::OnlyAggregateOperationAllowed)) => {
+                            reply_only_aggregate_operation_allowed(request)
+                        }
+                        Some(Err(PauseSessionError::ControlBroken)) => {
+                            tracing::error!(
+                %request,
+                "session control channel unexpectedly broke");
+                            reply_internal_server_error(request)
+                        }
+                        None => reply_session_not_found(request),
+                    }
+                } else {
+                    reply_session_not_found(request)
+                }
             }
             Method::Record => {
                 tracing::trace!("handling RECORD request");
-                reply_method_not_supported(request)
+                // Note that we ignore the Range header, if any. Published streams are
+                // always live, so recording starts immediately.
+                let session_id: SessionId = match request.session() {
+                    Some(session_id) => session_id.into(),
+                    None => return reply_session_not_found(request),
+                };
+
+                let context = self.use_context().await;
+                let published_source = match context.publish_manager.record(&session_id).await {
+                    Some(Ok(published_source)) => published_source,
+                    Some(Err(err)) => return reply_publish_error(request, err),
+                    None => return reply_session_not_found(request),
+                };
+
+                tracing::info!(
+                    name = published_source.name,
+                    path = published_source.path,
+                    %session_id,
+                    "registering published source",
+                );
+                match context
+                    .source_manager
+                    .register_and_start(
+                        &published_source.name,
+                        published_source.path,
+                        published_source.descriptor,
+                        Vec::new(),
+                        Source::DEFAULT_FAILBACK_INTERVAL,
+                        published_source.multicast_group,
+                        false,
+                        ReconnectPolicy::default(),
+                        None,
+                        None,
+                    )
+                    .await
+                {
+                    Ok(()) => reply_to_record(request),
+                    Err(err) => {
+                        tracing::error!(%request, %err, "failed to register published source");
+                        context.publish_manager.teardown(&session_id).await;
+                        reply_internal_server_error(request)
+                    }
+                }
             }
             Method::Teardown => {
                 tracing::trace!("handling TEARDOWN request");
+                // Tearing down on the presentation URL tears down the whole session, while
+                // tearing down on the URL of a track only removes that track.
+                let (path, track) = track::split_path(request.path());
                 if let Some(session_id) = request.session() {
-                    if self
+                    // Publishers can only tear down the publication as a whole.
+                    if self.teardown_publication(&session_id.into()).await {
+                        return reply_to_teardown(request);
+                    }
+
+                    match self
                         .use_context()
                         .await
                         .session_manager
-                        .teardown(&session_id.into())
+                        .teardown(&session_id.into(), path, track)
                         .await
                     {
-                        reply_to_teardown(request)
-                    } else {
-                        reply_session_not_found(request)
+                        Some(Ok(())) => reply_to_teardown(request),
+                        Some(Err(TeardownSessionError::SourceMismatch)) => {
+                            reply_session_not_found(request)
+                        }
+                        Some(Err(TeardownSessionError::TrackNotFound)) => reply_not_found(request),
+                        Some(Err(TeardownSessionError::OnlyAggregateOperationAllowed)) => {
+                            reply_only_aggregate_operation_allowed(request)
+                        }
+                        Some(Err(TeardownSessionError::ControlBroken)) => {
+                            tracing::error!(
+                %request,
+                "session control channel unexpectedly broke");
+                            reply_internal_server_error(request)
+                        }
+                        None => reply_session_not_found(request),
                     }
                 } else {
                     reply_session_not_found(request)
@@ -271,6 +716,58 @@ impl AppHandler {
         }
     }
 
+    /// Handle interleaved data sent by the client on a connection. Publishers
+    /// push their media this way. Other clients only send RTCP, which goes to
+    /// their session.
+    pub async fn handle_interleaved(
+        &self,
+        channel: u8,
+        payload: &[u8],
+        sessions: &HashSet<SessionId>,
+    ) {
+        tracing::trace!(%channel, "handling interleaved data");
+        let context = self.use_context().await;
+        for session_id in sessions {
+            if !context
+                .publish_manager
+                .forward(session_id, channel, payload)
+                .await
+            {
+                if let Some(Err(err)) = context
+                    .session_manager
+                    .receive_rtcp(session_id, channel, payload)
+                    .await
+                {
+                    tracing::debug!(%err, %session_id, "failed to pass on rtcp to session");
+                }
+            }
+        }
+    }
+
+    /// Handle the connection going away. A publisher cannot push anything
+    /// without its connection, so its publication ends right away.
+    pub async fn handle_disconnect(&self, sessions: &HashSet<SessionId>) {
+        for session_id in sessions {
+            self.teardown_publication(session_id).await;
+        }
+    }
+
+    /// End the publication of the session, if it is one, and remove its
+    /// source.
+    async fn teardown_publication(&self, session_id: &SessionId) -> bool {
+        let context = self.use_context().await;
+        match context.publish_manager.teardown(session_id).await {
+            Some(path) => {
+                if let Some(path) = path {
+                    tracing::info!(path, %session_id, "removing published source");
+                    context.source_manager.remove(&path).await;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
     #[inline]
     async fn use_context(&self) -> RwLockReadGuard<'_, AppContext> {
         self.context.read().await
@@ -283,40 +780,127 @@ fn is_request_require_supported(request: &Request) -> bool {
     request.require().is_none()
 }
 
+#[inline]
+fn is_request_authentication_required(request: &Request) -> bool {
+    matches!(
+        request.method,
+        Method::Describe
+            | Method::Announce
+            | Method::Setup
+            | Method::Play
+            | Method::Record
+            | Method::GetParameter
+            | Method::SetParameter
+    )
+}
+
 #[inline]
 fn is_request_one_of_content_types_supported(request: &Request) -> bool {
     // We only support SDP
     request.accept().contains(&"application/sdp")
 }
 
+#[inline]
+fn is_request_content_type_sdp(request: &Request) -> bool {
+    request.content_type() == Some("application/sdp")
+}
+
+#[inline]
+fn is_request_content_type_parameters(request: &Request) -> bool {
+    request.content_type() == Some("text/parameters")
+}
+
 #[inline]
 fn reply_to_options_with_supported_methods(request: &Request) -> Response {
     Response::ok()
         .with_cseq_of(request)
         .with_header("Server", SERVER)
-        .with_header("Public", "OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN")
+        .with_header(
+            "Public",
+            "OPTIONS, DESCRIBE, ANNOUNCE, SETUP, PLAY, PAUSE, RECORD, TEARDOWN, GET_PARAMETER, \
+             SET_PARAMETER",
+        )
         .build()
 }
 
 #[inline]
 fn reply_to_describe_with_media_sdp(request: &Request, sdp_contents: String) -> Response {
+    // The control URLs of the tracks in the SDP are relative to the base URL,
+    // which must end with a slash for them to resolve to a URL below it.
+    let content_base = format!("{}/", request.uri().to_string().trim_end_matches('/'));
     Response::ok()
         .with_cseq_of(request)
         .with_header("Server", SERVER)
+        .with_header("Content-Base", content_base)
         .with_sdp(sdp_contents)
         .build()
 }
 
 #[inline]
-fn reply_to_setup(request: &Request, session_id: &SessionId, transport: &Transport) -> Response {
+fn reply_to_announce(request: &Request) -> Response {
+    Response::ok()
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
+/// Publications do not time out, they last as long as their connection, so
+/// their session is advertised without a timeout.
+#[inline]
+fn reply_to_setup(
+    request: &Request,
+    session_id: &SessionId,
+    session_timeout: Option<Duration>,
+    transport: &Transport,
+) -> Response {
+    let session = match session_timeout {
+        Some(session_timeout) => format!("{};timeout={}", session_id, session_timeout.as_secs()),
+        None => session_id.to_string(),
+    };
     Response::ok()
         .with_cseq_of(request)
         .with_header("Server", SERVER)
-        .with_header("Session", session_id)
+        .with_header("Session", session)
         .with_header("Transport", transport)
         .build()
 }
 
+#[inline]
+fn reply_to_get_parameter(request: &Request, parameters: Vec<(Parameter, String)>) -> Response {
+    let response = Response::ok()
+        .with_cseq_of(request)
+        .with_header("Server", SERVER);
+    if parameters.is_empty() {
+        response.build()
+    } else {
+        response.with_parameters(parameters).build()
+    }
+}
+
+#[inline]
+fn reply_to_set_parameter(request: &Request) -> Response {
+    Response::ok()
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
+#[inline]
+fn reply_to_pause(request: &Request) -> Response {
+    Response::ok()
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
+#[inline]
+fn reply_to_record(request: &Request) -> Response {
+    Response::ok()
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
 #[inline]
 fn reply_to_teardown(request: &Request) -> Response {
     Response::ok()
@@ -326,10 +910,10 @@ fn reply_to_teardown(request: &Request) -> Response {
 }
 
 #[inline]
-fn reply_to_play(request: &Request, range: Range, rtp_info: RtpInfo) -> Response {
+fn reply_to_play(request: &Request, range: Range, rtp_info: Vec<RtpInfo>) -> Response {
     Response::ok()
         .with_cseq_of(request)
-        .with_rtp_info([rtp_info])
+        .with_rtp_info(rtp_info)
         .with_header("Server", SERVER)
         .with_header("Range", range)
         .build()
@@ -343,6 +927,29 @@ fn reply_bad_request(request: &Request) -> Response {
         .build()
 }
 
+#[inline]
+fn reply_unauthorized(request: &Request, challenge: Challenge) -> Response {
+    tracing::debug!(
+    %request,
+    "client needs to authenticate");
+    Response::error(Status::Unauthorized)
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .with_header("WWW-Authenticate", challenge)
+        .build()
+}
+
+#[inline]
+fn reply_forbidden(request: &Request) -> Response {
+    tracing::debug!(
+    %request,
+    "user does not have access to media item");
+    Response::error(Status::Forbidden)
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
 #[inline]
 fn reply_option_not_supported(request: &Request) -> Response {
     tracing::debug!(
@@ -355,24 +962,24 @@ fn reply_option_not_supported(request: &Request) -> Response {
 }
 
 #[inline]
-fn reply_method_not_supported(request: &Request) -> Response {
+fn reply_method_not_valid(request: &Request) -> Response {
     tracing::warn!(
     %request,
     method = %request.method,
-    "client sent unsupported request");
-    Response::error(Status::MethodNotAllowed)
+    "client tried server-only method in request to server; \
+    does client think it is server?");
+    Response::error(Status::MethodNotValidInThisState)
         .with_cseq_of(request)
         .with_header("Server", SERVER)
         .build()
 }
 
 #[inline]
-fn reply_method_not_valid(request: &Request) -> Response {
-    tracing::warn!(
+fn reply_method_not_valid_in_this_state(request: &Request) -> Response {
+    tracing::debug!(
     %request,
     method = %request.method,
-    "client tried server-only method in request to server; \
-    does client think it is server?");
+    "method not valid in current state of resource");
     Response::error(Status::MethodNotValidInThisState)
         .with_cseq_of(request)
         .with_header("Server", SERVER)
@@ -414,6 +1021,17 @@ fn reply_aggregate_operation_not_allowed(request: &Request) -> Response {
         .build()
 }
 
+#[inline]
+fn reply_only_aggregate_operation_allowed(request: &Request) -> Response {
+    tracing::debug!(
+    %request,
+    "refusing to do non-aggregate request");
+    Response::error(Status::OnlyAggregateOperationAllowed)
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
 #[inline]
 fn reply_unsupported_transport(request: &Request) -> Response {
     tracing::debug!(
@@ -425,6 +1043,40 @@ fn reply_unsupported_transport(request: &Request) -> Response {
         .build()
 }
 
+#[inline]
+fn reply_unsupported_media_type(request: &Request) -> Response {
+    tracing::debug!(
+    %request,
+    content_type = request.content_type(),
+    "server does not support content type of request body");
+    Response::error(Status::UnsupportedMediaType)
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
+#[inline]
+fn reply_invalid_parameter(request: &Request) -> Response {
+    tracing::debug!(
+    %request,
+    "invalid parameter");
+    Response::error(Status::InvalidParameter)
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
+#[inline]
+fn reply_parameter_is_read_only(request: &Request) -> Response {
+    tracing::debug!(
+    %request,
+    "parameter is read-only");
+    Response::error(Status::ParameterIsReadOnly)
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
 #[inline]
 fn reply_session_not_found(request: &Request) -> Response {
     tracing::debug!(
@@ -458,6 +1110,40 @@ fn reply_header_field_not_valid(request: &Request) -> Response {
         .build()
 }
 
+#[inline]
+fn reply_publish_error(request: &Request, err: PublishError) -> Response {
+    match err {
+        PublishError::PathNotFound | PublishError::MediaNotFound => reply_not_found(request),
+        PublishError::DescriptionInvalid => reply_bad_request(request),
+        PublishError::NotAnnounced
+        | PublishError::AlreadyPublishing
+        | PublishError::NothingSetup
+        | PublishError::AlreadyRecording => reply_method_not_valid_in_this_state(request),
+        // Like with play sessions, a publisher cannot add media of another path
+        // to its session.
+        PublishError::SourceMismatch => reply_aggregate_operation_not_allowed(request),
+        PublishError::TransportNotSupported | PublishError::ChannelInUse => {
+            reply_unsupported_transport(request)
+        }
+        PublishError::Io(err) => {
+            tracing::error!(%request, %err, "failed to set up publication");
+            reply_internal_server_error(request)
+        }
+    }
+}
+
+#[inline]
+fn reply_service_unavailable(request: &Request, retry_after: Duration) -> Response {
+    tracing::debug!(
+    %request,
+    "source unavailable");
+    Response::error(Status::ServiceUnavailable)
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .with_header("Retry-After", retry_after.as_secs().max(1))
+        .build()
+}
+
 #[inline]
 fn reply_internal_server_error(request: &Request) -> Response {
     Response::error(Status::InternalServerError)
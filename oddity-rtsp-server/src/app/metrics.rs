@@ -0,0 +1,271 @@
+//! Exports metrics on `/metrics` in the Prometheus text format. Counters come
+//! from [`crate::metrics`], everything that describes the current state is
+//! collected from the managers on every scrape.
+
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use tokio::sync::RwLock;
+
+use crate::app::AppContext;
+use crate::metrics::{self, PathMetrics};
+use crate::runtime::task_manager::{Task, TaskContext};
+use crate::runtime::Runtime;
+use crate::source::SourceStatus;
+
+type Context = Arc<RwLock<AppContext>>;
+
+const CONTENT_TYPE_METRICS: &str = "text/plain; version=0.0.4";
+
+pub struct MetricsServer {
+    worker: Task,
+}
+
+impl MetricsServer {
+    pub async fn start(
+        host: IpAddr,
+        port: u16,
+        context: Context,
+        runtime: Arc<Runtime>,
+    ) -> Result<Self, hyper::Error> {
+        tracing::trace!(%host, port, "starting metrics server");
+        let builder = match hyper::Server::try_bind(&SocketAddr::new(host, port)) {
+            Ok(builder) => builder,
+            Err(err) => {
+                tracing::error!(%err, %host, port, "failed to listen for metrics connections");
+                return Err(err);
+            }
+        };
+        tracing::info!(%host, port, "metrics server listening for incoming connections");
+
+        let worker = runtime
+            .task()
+            .spawn(move |task_context| Self::run(builder, context, task_context))
+            .await;
+        tracing::trace!(%host, port, "started metrics server");
+
+        Ok(Self { worker })
+    }
+
+    pub async fn stop(&mut self) {
+        tracing::trace!("sending stop signal to metrics server");
+        self.worker.stop().await;
+        tracing::trace!("metrics server stopped");
+    }
+
+    async fn run(
+        builder: hyper::server::Builder<AddrIncoming>,
+        context: Context,
+        mut task_context: TaskContext,
+    ) {
+        let make_service = make_service_fn(move |_| {
+            let context = context.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| handle(request, context.clone())))
+            }
+        });
+
+        if let Err(err) = builder
+            .serve(make_service)
+            .with_graceful_shutdown(task_context.wait_for_stop())
+            .await
+        {
+            tracing::error!(%err, "metrics server failed");
+        }
+        tracing::trace!("metrics server worker stopping");
+    }
+}
+
+async fn handle(request: Request<Body>, context: Context) -> Result<Response<Body>, Infallible> {
+    let mut response = Response::new(Body::empty());
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            *response.body_mut() = Body::from(render(&context).await);
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE_METRICS));
+        }
+        _ => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+        }
+    }
+    Ok(response)
+}
+
+async fn render(context: &Context) -> String {
+    let (sources, sessions) = {
+        let context = context.read().await;
+        (
+            context.source_manager.list().await,
+            context.session_manager.count_by_path().await,
+        )
+    };
+    let global = metrics::global();
+    let paths = global.paths();
+
+    let mut out = String::new();
+    family(
+        &mut out,
+        "oddity_connections_accepted_total",
+        "counter",
+        "Number of connections accepted.",
+    );
+    sample(
+        &mut out,
+        "oddity_connections_accepted_total",
+        None,
+        global.connections_accepted.get(),
+    );
+    family(
+        &mut out,
+        "oddity_connections_active",
+        "gauge",
+        "Number of connections currently open.",
+    );
+    sample(
+        &mut out,
+        "oddity_connections_active",
+        None,
+        global
+            .connections_accepted
+            .get()
+            .saturating_sub(global.connections_closed.get()),
+    );
+
+    family(
+        &mut out,
+        "oddity_send_queue_dropped_total",
+        "counter",
+        "Number of interleaved packets dropped because the client could not keep up.",
+    );
+    sample(
+        &mut out,
+        "oddity_send_queue_dropped_total",
+        None,
+        global.send_queue_dropped.get(),
+    );
+    family(
+        &mut out,
+        "oddity_slow_client_disconnects_total",
+        "counter",
+        "Number of connections closed because the client could not keep up.",
+    );
+    sample(
+        &mut out,
+        "oddity_slow_client_disconnects_total",
+        None,
+        global.slow_client_disconnects.get(),
+    );
+
+    family(
+        &mut out,
+        "oddity_sessions",
+        "gauge",
+        "Number of sessions currently set up, per path.",
+    );
+    let mut sessions = sessions.into_iter().collect::<Vec<_>>();
+    sessions.sort();
+    for (path, count) in sessions {
+        sample(&mut out, "oddity_sessions", Some(&path), count as u64);
+    }
+
+    family(
+        &mut out,
+        "oddity_source_up",
+        "gauge",
+        "Whether or not the source is live (1) or in any other state (0).",
+    );
+    for source in sources.iter() {
+        let up = source.status == SourceStatus::Live;
+        sample(&mut out, "oddity_source_up", Some(&source.path), up as u64);
+    }
+
+    path_counter(
+        &mut out,
+        &paths,
+        "oddity_source_restarts_total",
+        "Number of times the source recovered after its stream broke.",
+        |metrics| metrics.source_restarts.get(),
+    );
+    path_counter(
+        &mut out,
+        &paths,
+        "oddity_source_stalls_total",
+        "Number of times the source was restarted because it stopped making progress.",
+        |metrics| metrics.source_stalls.get(),
+    );
+    path_counter(
+        &mut out,
+        &paths,
+        "oddity_packets_sent_total",
+        "Number of RTP packets sent to clients.",
+        |metrics| metrics.packets_sent.get(),
+    );
+    path_counter(
+        &mut out,
+        &paths,
+        "oddity_bytes_sent_total",
+        "Number of RTP bytes sent to clients.",
+        |metrics| metrics.bytes_sent.get(),
+    );
+    path_counter(
+        &mut out,
+        &paths,
+        "oddity_broadcast_lagged_total",
+        "Number of times a session fell behind on the packets of its source.",
+        |metrics| metrics.lagged.get(),
+    );
+    path_counter(
+        &mut out,
+        &paths,
+        "oddity_mux_failures_total",
+        "Number of packets that could not be muxed.",
+        |metrics| metrics.mux_failures.get(),
+    );
+
+    out
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn path_counter(
+    out: &mut String,
+    paths: &[(String, Arc<PathMetrics>)],
+    name: &str,
+    help: &str,
+    value: impl Fn(&PathMetrics) -> u64,
+) {
+    family(out, name, "counter", help);
+    for (path, metrics) in paths {
+        sample(out, name, Some(path), value(metrics));
+    }
+}
+
+fn sample(out: &mut String, name: &str, path: Option<&str>, value: u64) {
+    match path {
+        Some(path) => {
+            let _ = writeln!(out, "{}{{path=\"{}\"}} {}", name, escape(path), value);
+        }
+        None => {
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+    }
+}
+
+/// Escape a label value as required by the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,17 +1,31 @@
+pub mod admin;
+pub mod auth;
 pub mod config;
+pub mod config_watcher;
 pub mod handler;
+pub mod metrics;
+pub mod parameters;
 
+use std::collections::{hash_map::Entry, HashMap};
 use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 
-use crate::app::config::AppConfig;
+use crate::app::admin::AdminServer;
+use crate::app::auth::Authenticator;
+use crate::app::config::{AppConfig, Item};
 use crate::app::handler::AppHandler;
-use crate::net::server::Server;
+use crate::app::metrics::MetricsServer;
+use crate::net::send_queue::SendQueueConfig;
+use crate::net::server::{Server, TlsListenerConfig};
+use crate::net::tls;
+use crate::publish::publish_manager::{PublishManager, RegisterPublishPointError};
 use crate::runtime::Runtime;
 use crate::session::session_manager::SessionManager;
-use crate::source::source_manager::SourceManager;
+use crate::source;
+use crate::source::source_manager::{RegisterSourceError, SourceManager};
 
 macro_rules! handle_err {
     ($rt:ident, $expr:expr) => {
@@ -26,7 +40,12 @@ macro_rules! handle_err {
 }
 
 pub struct App {
+    /// Configuration that is currently in effect. Its media items are the
+    /// ones that were registered successfully.
+    config: AppConfig,
     server: Server,
+    admin: Option<AdminServer>,
+    metrics: Option<MetricsServer>,
     context: Arc<RwLock<AppContext>>,
     runtime: Arc<Runtime>,
 }
@@ -35,7 +54,7 @@ impl App {
     pub async fn start(config: AppConfig) -> Result<App, Box<dyn Error>> {
         let runtime = Arc::new(Runtime::new());
 
-        let mut context = initialize_context(runtime.clone()).await;
+        let mut context = initialize_context(&config, runtime.clone()).await;
         handle_err!(
             runtime,
             register_sources_with_context(&config, &mut context,).await
@@ -46,18 +65,113 @@ impl App {
             runtime,
             initialize_server(&config, context.clone(), runtime.clone(),).await
         )?;
+        let admin = handle_err!(
+            runtime,
+            initialize_admin(&config, context.clone(), runtime.clone()).await
+        )?;
+        let metrics = handle_err!(
+            runtime,
+            initialize_metrics(&config, context.clone(), runtime.clone()).await
+        )?;
 
         Ok(Self {
+            config,
             server,
+            admin,
+            metrics,
             context,
             runtime,
         })
     }
 
+    /// Apply the media items of a changed configuration. Items are matched
+    /// by path: new items are registered, removed items unregistered, and
+    /// items that changed are registered again. Sessions of items that did
+    /// not change are left alone. Changed items that fail to register are
+    /// restored to what they were. New items that fail to register are left
+    /// out, and are retried on the next reload.
+    pub async fn reload(&mut self, config: AppConfig) {
+        if config.server != self.config.server || config.users != self.config.users {
+            tracing::warn!("changes to server settings and users take effect after restart");
+        }
+
+        let mut items = HashMap::new();
+        for item in config.media {
+            match items.entry(source::normalize_path(item.path.clone())) {
+                Entry::Vacant(entry) => {
+                    entry.insert(item);
+                }
+                Entry::Occupied(_) => {
+                    tracing::error!(%item, "ignoring media item with duplicate path");
+                }
+            }
+        }
+
+        let context = self.context.read().await;
+        let mut media = Vec::new();
+        for current in self.config.media.drain(..) {
+            let path = source::normalize_path(current.path.clone());
+            match items.remove(&path) {
+                Some(item) if item.is_same_media(&current) => {
+                    if item.users != current.users {
+                        tracing::info!(%item, "updating users of media item");
+                        if let Err(err) = restrict_item(&item, &context).await {
+                            tracing::error!(%err, %item, "failed to update users of media item");
+                            media.push(current);
+                            continue;
+                        }
+                    }
+                    media.push(item);
+                }
+                Some(item) => {
+                    unregister_item(&current, &context).await;
+                    match register_item(&item, &context).await {
+                        Ok(()) => media.push(item),
+                        Err(err) => {
+                            // A typo in the changed item should not make the path
+                            // disappear.
+                            tracing::error!(
+                                %err,
+                                %item,
+                                "failed to register changed media item, restoring media item"
+                            );
+                            match register_item(&current, &context).await {
+                                Ok(()) => media.push(current),
+                                Err(err) => {
+                                    tracing::error!(%err, item = %current, "failed to restore media item")
+                                }
+                            }
+                        }
+                    }
+                }
+                None => {
+                    unregister_item(&current, &context).await;
+                }
+            }
+        }
+
+        for (_, item) in items {
+            match register_item(&item, &context).await {
+                Ok(()) => media.push(item),
+                Err(err) => tracing::error!(%err, %item, "failed to register new media item"),
+            }
+        }
+
+        self.config = AppConfig { media, ..config };
+        tracing::info!(items = self.config.media.len(), "reloaded configuration");
+    }
+
     pub async fn stop(&mut self) {
+        if let Some(admin) = self.admin.as_mut() {
+            admin.stop().await;
+        }
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.stop().await;
+        }
         self.server.stop().await;
         self.context.write().await.session_manager.stop().await;
         self.context.write().await.source_manager.stop().await;
+        self.context.write().await.publish_manager.stop().await;
         self.runtime.stop().await;
     }
 }
@@ -68,9 +182,23 @@ async fn initialize_server(
     runtime: Arc<Runtime>,
 ) -> Result<Server, Box<dyn Error>> {
     let handler = AppHandler::new(context.clone());
+    let tls = match config.server.tls.as_ref() {
+        Some(tls) => Some(TlsListenerConfig {
+            port: tls.port,
+            acceptor: tls::make_acceptor(&tls.cert, &tls.key)
+                .map_err(|err| format!("failed to load tls certificate and key: {}", err))?,
+        }),
+        None => None,
+    };
+    let send_queue = SendQueueConfig {
+        size: config.server.send_queue.size,
+        policy: config.server.send_queue.policy,
+    };
     Server::start(
         config.server.host.parse()?,
         config.server.port,
+        tls,
+        send_queue,
         handler,
         runtime.clone(),
     )
@@ -78,10 +206,49 @@ async fn initialize_server(
     .map_err(|err| err.into())
 }
 
-async fn initialize_context(runtime: Arc<Runtime>) -> AppContext {
+async fn initialize_admin(
+    config: &AppConfig,
+    context: Arc<RwLock<AppContext>>,
+    runtime: Arc<Runtime>,
+) -> Result<Option<AdminServer>, Box<dyn Error>> {
+    match config.server.admin.as_ref() {
+        Some(admin) => Ok(Some(
+            AdminServer::start(admin.host.parse()?, admin.port, context, runtime).await?,
+        )),
+        None => Ok(None),
+    }
+}
+
+async fn initialize_metrics(
+    config: &AppConfig,
+    context: Arc<RwLock<AppContext>>,
+    runtime: Arc<Runtime>,
+) -> Result<Option<MetricsServer>, Box<dyn Error>> {
+    match config.server.metrics.as_ref() {
+        Some(metrics) => Ok(Some(
+            MetricsServer::start(metrics.host.parse()?, metrics.port, context, runtime).await?,
+        )),
+        None => Ok(None),
+    }
+}
+
+async fn initialize_context(config: &AppConfig, runtime: Arc<Runtime>) -> AppContext {
     AppContext {
         source_manager: SourceManager::start(runtime.clone()).await,
-        session_manager: SessionManager::start(runtime.clone()).await,
+        session_manager: SessionManager::start(
+            Duration::from_secs(config.server.session_timeout),
+            runtime.clone(),
+        )
+        .await,
+        publish_manager: PublishManager::new(),
+        authenticator: Authenticator::new(
+            config.server.auth,
+            config
+                .users
+                .iter()
+                .map(|user| (user.name.clone(), user.password.clone())),
+        ),
+        registration: Mutex::new(()),
     }
 }
 
@@ -91,21 +258,121 @@ async fn register_sources_with_context(
 ) -> Result<(), Box<dyn Error>> {
     tracing::trace!("registering sources");
     for item in config.media.iter() {
-        tracing::info!(%item, "registering source");
+        register_item(item, context).await?;
+    }
+    tracing::trace!("registered sources");
+    Ok(())
+}
+
+async fn register_item(item: &Item, context: &AppContext) -> Result<(), Box<dyn Error>> {
+    let path = source::normalize_path(item.path.clone());
+    let _registration = context.registration.lock().await;
+    // Whoever registered the path first decides who has access to it.
+    if context.source_manager.info(&path).await.is_some()
+        || context.publish_manager.is_registered(&path).await
+    {
+        return Err(format!("path already registered for media item: {}", item).into());
+    }
+
+    restrict_item(item, context).await?;
+    if let Err(err) = start_item(item, context).await {
+        if !is_already_registered(err.as_ref()) {
+            context.authenticator.unrestrict(&path).await;
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Whether or not the item could not be started because someone else
+/// registered its path, in which case the restriction of the path is theirs.
+fn is_already_registered(err: &(dyn Error + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<RegisterSourceError>(),
+        Some(RegisterSourceError::AlreadyRegistered)
+    ) || err.is::<RegisterPublishPointError>()
+}
+
+async fn start_item(item: &Item, context: &AppContext) -> Result<(), Box<dyn Error>> {
+    if item.is_publish_point() {
+        tracing::info!(%item, "registering publish point");
         context
-            .source_manager
-            .register_and_start(
+            .publish_manager
+            .register(
                 item.name.as_str(),
                 item.path.clone(),
-                item.as_media_descriptor()?,
+                item.as_multicast_group()?,
             )
             .await?;
+        return Ok(());
     }
-    tracing::trace!("registered sources");
+
+    tracing::info!(%item, "registering source");
+    context
+        .source_manager
+        .register_and_start(
+            item.name.as_str(),
+            item.path.clone(),
+            item.as_media_descriptor()?,
+            item.as_fallback_descriptors()?,
+            item.as_failback_interval()?,
+            item.as_multicast_group()?,
+            item.is_vod()?,
+            item.as_reconnect_policy()?,
+            item.as_stall_timeout(),
+            item.as_on_demand()?,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Stop serving the item, and tear down the sessions of anyone that was
+/// watching it.
+async fn unregister_item(item: &Item, context: &AppContext) {
+    let path = source::normalize_path(item.path.clone());
+    if item.is_publish_point() {
+        tracing::info!(%item, "unregistering publish point");
+        context.publish_manager.unregister(&path).await;
+    } else {
+        tracing::info!(%item, "unregistering source");
+    }
+    // A publish point only has a source while something is being published.
+    context.session_manager.teardown_all(&path).await;
+    context.source_manager.remove(&path).await;
+    context.authenticator.unrestrict(&path).await;
+}
+
+/// Only allow the users of the item access to it, or anyone if there are no
+/// users.
+async fn restrict_item(item: &Item, context: &AppContext) -> Result<(), Box<dyn Error>> {
+    if item.users.is_empty() {
+        context
+            .authenticator
+            .unrestrict(&source::normalize_path(item.path.clone()))
+            .await;
+        return Ok(());
+    }
+
+    if let Some(user) = item
+        .users
+        .iter()
+        .find(|user| !context.authenticator.has_user(user))
+    {
+        return Err(format!("unknown user {} for media item: {}", user, item).into());
+    }
+    context
+        .authenticator
+        .restrict(item.path.clone(), item.users.iter().cloned())
+        .await;
     Ok(())
 }
 
 pub struct AppContext {
     source_manager: SourceManager,
     session_manager: SessionManager,
+    publish_manager: PublishManager,
+    authenticator: Authenticator,
+    /// Held while a path is checked, restricted and registered, so that
+    /// nobody else can register it in between.
+    registration: Mutex<()>,
 }
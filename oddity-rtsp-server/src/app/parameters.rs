@@ -0,0 +1,93 @@
+//! Parameters that clients can query with GET_PARAMETER, and in some cases
+//! change with SET_PARAMETER. Except for the status of the source, all
+//! parameters belong to a session.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Session timeouts set by clients are limited to an hour, so that clients
+/// cannot keep sessions around forever.
+const MAX_SESSION_TIMEOUT_SECS: u64 = 3600;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parameter {
+    SourceStatus,
+    Bitrate,
+    Uptime,
+    PacketsSent,
+    BytesSent,
+    PacketsLost,
+    FractionLost,
+    Jitter,
+    RoundTripTime,
+    Timeout,
+    Label,
+}
+
+impl Parameter {
+    pub fn is_writable(&self) -> bool {
+        matches!(self, Parameter::Timeout | Parameter::Label)
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Parameter::SourceStatus => write!(f, "source_status"),
+            Parameter::Bitrate => write!(f, "bitrate"),
+            Parameter::Uptime => write!(f, "uptime"),
+            Parameter::PacketsSent => write!(f, "packets_sent"),
+            Parameter::BytesSent => write!(f, "bytes_sent"),
+            Parameter::PacketsLost => write!(f, "packets_lost"),
+            Parameter::FractionLost => write!(f, "fraction_lost"),
+            Parameter::Jitter => write!(f, "jitter"),
+            Parameter::RoundTripTime => write!(f, "rtt"),
+            Parameter::Timeout => write!(f, "timeout"),
+            Parameter::Label => write!(f, "label"),
+        }
+    }
+}
+
+impl FromStr for Parameter {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "source_status" => Ok(Parameter::SourceStatus),
+            "bitrate" => Ok(Parameter::Bitrate),
+            "uptime" => Ok(Parameter::Uptime),
+            "packets_sent" => Ok(Parameter::PacketsSent),
+            "bytes_sent" => Ok(Parameter::BytesSent),
+            "packets_lost" => Ok(Parameter::PacketsLost),
+            "fraction_lost" => Ok(Parameter::FractionLost),
+            "jitter" => Ok(Parameter::Jitter),
+            "rtt" => Ok(Parameter::RoundTripTime),
+            "timeout" => Ok(Parameter::Timeout),
+            "label" => Ok(Parameter::Label),
+            _ => Err(()),
+        }
+    }
+}
+
+/// New value for a writable parameter.
+pub enum ParameterValue {
+    Timeout(Duration),
+    Label(String),
+}
+
+impl ParameterValue {
+    /// Parse the value a client provided for the parameter. Returns `None` if
+    /// the parameter is not writable or the value is not valid for it.
+    pub fn parse(parameter: Parameter, value: &str) -> Option<Self> {
+        match parameter {
+            Parameter::Timeout => value
+                .parse::<u64>()
+                .ok()
+                .filter(|secs| (1..=MAX_SESSION_TIMEOUT_SECS).contains(secs))
+                .map(|secs| ParameterValue::Timeout(Duration::from_secs(secs))),
+            Parameter::Label if !value.is_empty() => Some(ParameterValue::Label(value.to_string())),
+            _ => None,
+        }
+    }
+}
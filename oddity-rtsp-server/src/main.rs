@@ -1,20 +1,24 @@
 mod app;
 mod media;
+mod metrics;
 mod net;
+mod publish;
 mod runtime;
 mod session;
 mod source;
 
 use std::env;
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
 
 use config::ConfigError;
 
 use app::config::AppConfig;
+use app::config_watcher::ConfigWatcher;
 use app::App;
 
+use tokio::select;
 use tokio::signal::ctrl_c;
 
 use video_rs as video;
@@ -36,15 +40,37 @@ async fn main() {
     on_error_exit!(initialize_tracing());
     on_error_exit!(initialize_media());
 
-    let config = on_error_exit!(initialize_and_read_config());
+    let config_file = config_file();
+    let config = on_error_exit!(read_config(&config_file));
     tracing::debug!(?config, "loaded config file");
+    let mut config_watcher = on_error_exit!(ConfigWatcher::new(&config_file).await);
 
     tracing::trace!("starting app");
     let mut app = on_error_exit!(App::start(config).await);
     tracing::trace!("started app");
 
     tracing::trace!("waiting for ctrl+C...");
-    on_error_exit!(ctrl_c().await);
+    loop {
+        select! {
+          // CANCEL SAFETY: Missing a signal between iterations is not a concern.
+          result = ctrl_c() => {
+            on_error_exit!(result);
+            break;
+          },
+          // CANCEL SAFETY: `ConfigWatcher::changed` is cancel safe.
+          _ = config_watcher.changed() => {
+            match read_config(&config_file) {
+              Ok(config) => {
+                tracing::debug!(?config, "reloaded config file");
+                app.reload(config).await;
+              },
+              Err(err) => {
+                tracing::error!(%err, "failed to reload config file, keeping current config");
+              },
+            }
+          },
+        }
+    }
 
     tracing::trace!("stopping app");
     app.stop().await;
@@ -61,9 +87,11 @@ fn initialize_media() -> Result<(), Box<dyn Error>> {
     video::init()
 }
 
-fn initialize_and_read_config() -> Result<AppConfig, ConfigError> {
-    let config_file = env::args().nth(1).unwrap_or("default.yaml".to_string());
-    let config_file = Path::new(&config_file);
+fn config_file() -> PathBuf {
+    PathBuf::from(env::args().nth(1).unwrap_or("default.yaml".to_string()))
+}
+
+fn read_config(config_file: &Path) -> Result<AppConfig, ConfigError> {
     tracing::trace!(config_file=%config_file.display(), "loading config");
 
     AppConfig::from_file(config_file)
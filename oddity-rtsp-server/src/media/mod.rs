@@ -1,21 +1,32 @@
+pub mod rtcp;
+pub mod rtp;
 pub mod sdp;
+pub mod track;
 pub mod video;
 
-use video_rs::StreamInfo;
-
-pub use video_rs::Packet;
-
 use std::fmt;
 use std::path::PathBuf;
 
 use video_rs::{Error, Locator, Reader, Url};
 
+use crate::media::video::stream::{self, StreamInfo};
+use crate::net::udp::Reservation;
+
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Clone)]
 pub enum MediaDescriptor {
     Stream(Url),
     File(PathBuf),
+    /// SDP file describing RTP that a publisher pushes to the server, see
+    /// [`crate::publish`], and the local ports it points at.
+    Published(PathBuf, Reservation),
+}
+
+impl MediaDescriptor {
+    pub fn is_published(&self) -> bool {
+        matches!(self, MediaDescriptor::Published(..))
+    }
 }
 
 impl fmt::Display for MediaDescriptor {
@@ -23,6 +34,7 @@ impl fmt::Display for MediaDescriptor {
         match self {
             MediaDescriptor::File(path) => write!(f, "file: {}", path.display()),
             MediaDescriptor::Stream(url) => write!(f, "stream: {}", url),
+            MediaDescriptor::Published(path, _) => write!(f, "published: {}", path.display()),
         }
     }
 }
@@ -32,21 +44,35 @@ impl From<MediaDescriptor> for Locator {
         match descriptor {
             MediaDescriptor::File(path) => Locator::Path(path),
             MediaDescriptor::Stream(url) => Locator::Url(url),
+            MediaDescriptor::Published(path, _) => Locator::Path(path),
         }
     }
 }
 
+/// Streams that are served for a media item. Each stream is served as a
+/// separate track, which is identified by its position in `streams`. The
+/// first stream is always the video stream.
 #[derive(Clone)]
 pub struct MediaInfo {
     pub streams: Vec<StreamInfo>,
 }
 
 impl MediaInfo {
-    pub fn from_reader_best_video_stream(reader: &Reader) -> Result<Self> {
+    /// Select the best video stream, and the best audio stream if there is
+    /// one.
+    pub fn from_reader_best_streams(reader: &Reader) -> Result<Self> {
         let best_video_stream_index = reader.best_video_stream_index()?;
-        Ok(Self {
-            streams: vec![reader.stream_info(best_video_stream_index)?],
-        })
+        let mut streams = vec![StreamInfo::from_reader(reader, best_video_stream_index)?];
+        if let Some(best_audio_stream_index) = stream::best_audio_stream_index(reader) {
+            streams.push(StreamInfo::from_reader(reader, best_audio_stream_index)?);
+        }
+        Ok(Self { streams })
+    }
+
+    pub fn contains_stream(&self, stream_index: usize) -> bool {
+        self.streams
+            .iter()
+            .any(|stream_info| stream_info.index == stream_index)
     }
 }
 
@@ -0,0 +1,321 @@
+//! Minimal RTCP support for the few packets the server creates itself, and
+//! the reports clients send back. The muxer takes care of sender reports.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// RTCP packet types (RFC 3550 Section 12.1).
+const PACKET_TYPE_SENDER_REPORT: u8 = 200;
+const PACKET_TYPE_RECEIVER_REPORT: u8 = 201;
+const PACKET_TYPE_BYE: u8 = 203;
+
+/// Size of the common header, and of the SSRC of the sender that follows it.
+const HEADER_SIZE: usize = 4;
+const SSRC_SIZE: usize = 4;
+/// Size of the sender info in a sender report.
+const SENDER_INFO_SIZE: usize = 20;
+/// Size of a single report block.
+const REPORT_BLOCK_SIZE: usize = 24;
+
+/// Seconds between the NTP epoch (1900) and the UNIX epoch (1970).
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+/// RTP version 2, in the two most significant bits of the first byte.
+const VERSION: u8 = 2 << 6;
+
+/// Rewrite a compound RTCP packet from a muxer for a client with its own
+/// SSRC and timestamps, see [`crate::media::rtp::Rewriter`]. Sender reports
+/// also get the number of packets and octets the client was sent.
+pub fn rewrite(compound: &mut [u8], ssrc: u32, timestamp_offset: u32, packets: u32, octets: u32) {
+    let mut offset = 0;
+    while compound.len() >= offset + HEADER_SIZE + SSRC_SIZE {
+        let packet_type = compound[offset + 1];
+        let len =
+            (u16::from_be_bytes([compound[offset + 2], compound[offset + 3]]) as usize + 1) * 4;
+        let packet = match compound.get_mut(offset..offset + len) {
+            Some(packet) => packet,
+            None => break,
+        };
+        // Every packet the muxer creates has the SSRC right after the header,
+        // in the case of SDES as part of the first chunk.
+        packet[4..8].copy_from_slice(&ssrc.to_be_bytes());
+        if packet_type == PACKET_TYPE_SENDER_REPORT
+            && packet.len() >= HEADER_SIZE + SSRC_SIZE + SENDER_INFO_SIZE
+        {
+            // The RTP timestamp follows the NTP timestamp in the sender info.
+            let timestamp = u32::from_be_bytes([packet[16], packet[17], packet[18], packet[19]])
+                .wrapping_add(timestamp_offset);
+            packet[16..20].copy_from_slice(&timestamp.to_be_bytes());
+            packet[20..24].copy_from_slice(&packets.to_be_bytes());
+            packet[24..28].copy_from_slice(&octets.to_be_bytes());
+        }
+        offset += len;
+    }
+}
+
+/// Create a compound RTCP packet that tells the receiver the stream with
+/// the given SSRC has ended.
+///
+/// RFC 3550 Section 6.1 requires every compound packet to start with a
+/// report, so an empty receiver report is put in front of the BYE packet.
+pub fn bye(ssrc: u32) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(16);
+    // Empty receiver report (no report blocks), length is one word.
+    packet.extend([VERSION, PACKET_TYPE_RECEIVER_REPORT, 0, 1]);
+    packet.extend(ssrc.to_be_bytes());
+    // BYE with a single SSRC, length is one word.
+    packet.extend([VERSION | 1, PACKET_TYPE_BYE, 0, 1]);
+    packet.extend(ssrc.to_be_bytes());
+    packet
+}
+
+/// Packet in a compound RTCP packet sent by a client, as far as the server
+/// cares about it.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClientPacket {
+    /// Report blocks of a sender or receiver report.
+    Report(Vec<ReportBlock>),
+    Bye,
+}
+
+/// Reception report about one of the streams we send (RFC 3550 Section
+/// 6.4.1).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReportBlock {
+    /// SSRC of the stream the report is about.
+    pub ssrc: u32,
+    /// Fraction of packets lost since the previous report, out of 256.
+    pub fraction_lost: u8,
+    /// Total number of packets lost. May be negative when there are
+    /// duplicates.
+    pub packets_lost: i32,
+    pub highest_seq: u32,
+    /// Interarrival jitter, in RTP timestamp units.
+    pub jitter: u32,
+    /// Middle 32 bits of the NTP timestamp of the last sender report.
+    pub last_sr: u32,
+    /// Delay since the last sender report, in units of 1/65536 seconds.
+    pub delay_since_last_sr: u32,
+}
+
+impl ReportBlock {
+    /// Round-trip time to the client, if it has received a sender report.
+    /// The muxer puts the wall clock in sender reports, so this works as long
+    /// as the clock does not jump in between.
+    pub fn round_trip_time(&self, arrival: SystemTime) -> Option<Duration> {
+        if self.last_sr == 0 {
+            return None;
+        }
+        let since_last_sr = ntp_middle_bits(arrival).wrapping_sub(self.last_sr);
+        let rtt = since_last_sr.checked_sub(self.delay_since_last_sr)?;
+        Some(Duration::from_secs_f64(rtt as f64 / 65536.0))
+    }
+}
+
+/// Parse a compound RTCP packet from a client. Packets we do not care about
+/// are skipped, and parsing stops at the first malformed packet.
+pub fn parse(mut compound: &[u8]) -> Vec<ClientPacket> {
+    let mut packets = Vec::new();
+    while compound.len() >= HEADER_SIZE {
+        if compound[0] & 0xc0 != VERSION {
+            break;
+        }
+        let count = (compound[0] & 0x1f) as usize;
+        let packet_type = compound[1];
+        // Length is in words minus one, and includes the header.
+        let len = (u16::from_be_bytes([compound[2], compound[3]]) as usize + 1) * 4;
+        if compound.len() < len {
+            break;
+        }
+        let (packet, rest) = compound.split_at(len);
+        compound = rest;
+
+        let blocks = match packet_type {
+            PACKET_TYPE_SENDER_REPORT => HEADER_SIZE + SSRC_SIZE + SENDER_INFO_SIZE,
+            PACKET_TYPE_RECEIVER_REPORT => HEADER_SIZE + SSRC_SIZE,
+            PACKET_TYPE_BYE => {
+                packets.push(ClientPacket::Bye);
+                continue;
+            }
+            _ => continue,
+        };
+        match packet
+            .get(blocks..blocks + count * REPORT_BLOCK_SIZE)
+            .map(parse_report_blocks)
+        {
+            Some(blocks) => packets.push(ClientPacket::Report(blocks)),
+            None => break,
+        }
+    }
+    packets
+}
+
+fn parse_report_blocks(blocks: &[u8]) -> Vec<ReportBlock> {
+    blocks
+        .chunks_exact(REPORT_BLOCK_SIZE)
+        .map(|block| {
+            let word = |index: usize| {
+                u32::from_be_bytes([
+                    block[index],
+                    block[index + 1],
+                    block[index + 2],
+                    block[index + 3],
+                ])
+            };
+            ReportBlock {
+                ssrc: word(0),
+                fraction_lost: block[4],
+                // Sign extend the 24 bit number of packets lost.
+                packets_lost: (word(4) << 8) as i32 >> 8,
+                highest_seq: word(8),
+                jitter: word(12),
+                last_sr: word(16),
+                delay_since_last_sr: word(20),
+            }
+        })
+        .collect()
+}
+
+/// Middle 32 bits of the NTP timestamp of the given time, which is what
+/// reports use to refer to sender reports.
+fn ntp_middle_bits(time: SystemTime) -> u32 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() + NTP_UNIX_OFFSET_SECS;
+    let fraction = ((since_epoch.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (((secs & 0xffff) << 16) | (fraction >> 16)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{bye, ntp_middle_bits, parse, ClientPacket, ReportBlock};
+
+    const SSRC: u32 = 0x01020304;
+
+    /// Receiver report with a single report block, followed by a source
+    /// description with a CNAME, like clients send them.
+    const RECEIVER_REPORT_AND_SDES: [u8; 44] = [
+        // Receiver report, one block, seven words after the header.
+        0x81, 201, 0, 7, //
+        0xaa, 0xbb, 0xcc, 0xdd, // SSRC of the client
+        0x01, 0x02, 0x03, 0x04, // SSRC of the stream
+        0x40, 0xff, 0xff, 0xfe, // fraction lost, packets lost (-2)
+        0x00, 0x01, 0x00, 0x10, // highest sequence number
+        0x00, 0x00, 0x01, 0x00, // jitter
+        0x12, 0x34, 0x56, 0x78, // last SR
+        0x00, 0x01, 0x00, 0x00, // delay since last SR
+        // Source description, one chunk, two words after the header.
+        0x81, 202, 0, 2, //
+        0xaa, 0xbb, 0xcc, 0xdd, // SSRC of the client
+        0x01, 0x01, b'a', 0x00, // CNAME "a", end of items
+    ];
+
+    fn report_block() -> ReportBlock {
+        ReportBlock {
+            ssrc: SSRC,
+            fraction_lost: 0x40,
+            packets_lost: -2,
+            highest_seq: 0x00010010,
+            jitter: 0x100,
+            last_sr: 0x12345678,
+            delay_since_last_sr: 0x10000,
+        }
+    }
+
+    #[test]
+    fn parse_receiver_report_and_sdes() {
+        assert_eq!(
+            parse(&RECEIVER_REPORT_AND_SDES),
+            vec![ClientPacket::Report(vec![report_block()])],
+        );
+    }
+
+    #[test]
+    fn parse_bye() {
+        assert_eq!(
+            parse(&bye(SSRC)),
+            vec![ClientPacket::Report(Vec::new()), ClientPacket::Bye],
+        );
+    }
+
+    #[test]
+    fn bye_is_empty_receiver_report_and_bye() {
+        assert_eq!(
+            bye(SSRC),
+            vec![
+                0x80, 201, 0, 1, 0x01, 0x02, 0x03, 0x04, //
+                0x81, 203, 0, 1, 0x01, 0x02, 0x03, 0x04,
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_truncated() {
+        for len in 0..RECEIVER_REPORT_AND_SDES.len() {
+            let packets = parse(&RECEIVER_REPORT_AND_SDES[..len]);
+            // The report is only there once it is complete, the description
+            // does not matter.
+            if len >= 32 {
+                assert_eq!(packets, vec![ClientPacket::Report(vec![report_block()])]);
+            } else {
+                assert!(packets.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn parse_report_with_missing_blocks() {
+        // Claims 31 report blocks but has none.
+        assert!(parse(&[0x9f, 201, 0, 1, 0xaa, 0xbb, 0xcc, 0xdd]).is_empty());
+    }
+
+    #[test]
+    fn parse_garbage() {
+        assert!(parse(&[]).is_empty());
+        assert!(parse(&[0xff; 64]).is_empty());
+        assert!(parse(b"GET_PARAMETER rtsp://localhost/ RTSP/1.0\r\n").is_empty());
+        for seed in 0..=255u8 {
+            let garbage = (0..64u8)
+                .map(|index| index.wrapping_mul(seed).wrapping_add(0x80))
+                .collect::<Vec<_>>();
+            parse(&garbage);
+        }
+    }
+
+    #[test]
+    fn round_trip_time() {
+        let arrival = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let block = ReportBlock {
+            // Sender report went out three seconds ago, and the client held on
+            // to it for two seconds.
+            last_sr: ntp_middle_bits(arrival).wrapping_sub(3 << 16),
+            delay_since_last_sr: 2 << 16,
+            ..report_block()
+        };
+        assert_eq!(block.round_trip_time(arrival), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn round_trip_time_without_sender_report() {
+        let block = ReportBlock {
+            last_sr: 0,
+            delay_since_last_sr: 0,
+            ..report_block()
+        };
+        assert_eq!(block.round_trip_time(SystemTime::now()), None);
+    }
+
+    #[test]
+    fn round_trip_time_with_delay_longer_than_elapsed() {
+        // The client claims to have held on to the report for longer than it
+        // has been since it went out, which would make the time negative.
+        let arrival = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let block = ReportBlock {
+            last_sr: ntp_middle_bits(arrival).wrapping_sub(1 << 16),
+            delay_since_last_sr: 2 << 16,
+            ..report_block()
+        };
+        assert_eq!(block.round_trip_time(arrival), None);
+    }
+}
@@ -0,0 +1,389 @@
+//! Sources mux their streams to RTP once, and share the packets with all of
+//! their sessions. Every session rewrites the headers for its own client, see
+//! [`Rewriter`].
+//!
+//! There is also just enough parsing to find out where a client can start
+//! decoding the video. Only H.264 (RFC 6184) is supported, like everywhere
+//! else.
+
+use bytes::Bytes;
+
+use rand::Rng;
+
+use video_rs as video;
+
+use crate::media::rtcp;
+use crate::media::StreamState;
+
+/// Size of the fixed part of the RTP header.
+const HEADER_SIZE: usize = 12;
+
+/// H.264 NAL unit types (ITU-T H.264 Table 7-1, RFC 6184 Section 5.2).
+const NAL_TYPE_IDR: u8 = 5;
+const NAL_TYPE_SPS: u8 = 7;
+const NAL_TYPE_STAP_A: u8 = 24;
+const NAL_TYPE_FU_A: u8 = 28;
+
+/// Get the payload of an RTP packet, skipping the CSRC list and header
+/// extension if there are any.
+pub fn payload(packet: &[u8]) -> Option<&[u8]> {
+    let first = *packet.first()?;
+    let csrc_count = (first & 0x0f) as usize;
+    let has_extension = first & 0x10 != 0;
+    let mut offset = HEADER_SIZE + csrc_count * 4;
+    if has_extension {
+        let extension = packet.get(offset..offset + 4)?;
+        offset += 4 + u16::from_be_bytes([extension[2], extension[3]]) as usize * 4;
+    }
+    packet.get(offset..)
+}
+
+/// Whether or not the RTP packet with an H.264 payload starts a keyframe.
+/// Streams usually carry the parameter sets right before every IDR picture,
+/// so either one counts as the start.
+pub fn starts_h264_keyframe(packet: &[u8]) -> bool {
+    let payload = match payload(packet) {
+        Some(payload) => payload,
+        None => return false,
+    };
+    let nal_type = |byte: Option<&u8>| byte.map(|byte| byte & 0x1f);
+    match nal_type(payload.first()) {
+        Some(NAL_TYPE_IDR) | Some(NAL_TYPE_SPS) => true,
+        // Aggregation packet, the first NAL unit follows its 16 bit size.
+        Some(NAL_TYPE_STAP_A) => matches!(
+            nal_type(payload.get(3)),
+            Some(NAL_TYPE_IDR) | Some(NAL_TYPE_SPS)
+        ),
+        // Fragment, only the first one of an IDR picture counts.
+        Some(NAL_TYPE_FU_A) => payload
+            .get(1)
+            .map(|header| header & 0x80 != 0 && header & 0x1f == NAL_TYPE_IDR)
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// RTP or RTCP packet as muxed by a source.
+#[derive(Clone, Debug)]
+pub enum SharedRtpBuf {
+    Rtp(Bytes),
+    Rtcp(Bytes),
+}
+
+impl From<video::RtpBuf> for SharedRtpBuf {
+    fn from(buf: video::RtpBuf) -> Self {
+        match buf {
+            video::RtpBuf::Rtp(payload) => SharedRtpBuf::Rtp(payload.into()),
+            video::RtpBuf::Rtcp(payload) => SharedRtpBuf::Rtcp(payload.into()),
+        }
+    }
+}
+
+/// Gives the packets of a source their own SSRC, sequence numbers and
+/// timestamps for a single client, as if the client had its own muxer.
+/// Sequence numbers count the packets the client was actually sent, so
+/// packets that were dropped before sending, like while paused, do not leave
+/// a gap.
+///
+/// When the source starts over, the packets of the new stream come with a new
+/// epoch (see [`StreamPacket::epoch`]). The client keeps its SSRC and sequence
+/// numbers, and the timestamps of the new stream are moved to continue right
+/// after the last one sent, so the client only sees a short gap. RTCP packets
+/// of the new stream are dropped until its first RTP packet arrives, since
+/// their timestamps cannot be moved before then.
+///
+/// [`StreamPacket::epoch`]: crate::media::video::reader::StreamPacket::epoch
+pub struct Rewriter {
+    ssrc: u32,
+    next_seq: u16,
+    timestamp_offset: u32,
+    /// Epoch of the packets the offset is meant for, if any were sent.
+    epoch: Option<u64>,
+    /// Timestamp of the last packet sent, after rewriting.
+    last_timestamp: u32,
+    /// How much the timestamp moved forward last, usually the duration of a
+    /// frame. Timestamps of a new stream continue this far after the last one.
+    last_increment: u32,
+    /// Number of packets and payload octets sent, for sender reports.
+    packets: u32,
+    octets: u32,
+}
+
+impl Rewriter {
+    /// The initial sequence number and timestamp are random (RFC 3550
+    /// Section 5.1).
+    pub fn new() -> Self {
+        let mut rng = rand::thread_rng();
+        let timestamp_offset = rng.gen();
+        Self {
+            ssrc: rng.gen(),
+            next_seq: rng.gen(),
+            timestamp_offset,
+            epoch: None,
+            last_timestamp: timestamp_offset,
+            last_increment: 1,
+            packets: 0,
+            octets: 0,
+        }
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    /// Sequence number and timestamp the client is sent next, for the given
+    /// packets of the given epoch if there are any.
+    pub fn stream_state(&self, epoch: u64, bufs: &[SharedRtpBuf]) -> StreamState {
+        let rtp_timestamp = bufs
+            .iter()
+            .find_map(|buf| match buf {
+                SharedRtpBuf::Rtp(packet) => timestamp_of_rtp_packet(packet),
+                SharedRtpBuf::Rtcp(_) => None,
+            })
+            .map(|timestamp| timestamp.wrapping_add(self.timestamp_offset_for(epoch, timestamp)))
+            .unwrap_or(self.last_timestamp);
+        StreamState {
+            rtp_seq: self.next_seq,
+            rtp_timestamp,
+        }
+    }
+
+    /// Rewrite the packet for the client. Returns nothing if the packet must
+    /// not be sent, see [`Rewriter`].
+    pub fn rewrite(&mut self, epoch: u64, buf: &SharedRtpBuf) -> Option<video::RtpBuf> {
+        match buf {
+            SharedRtpBuf::Rtp(packet) => {
+                let mut packet = packet.to_vec();
+                if let Some(timestamp) = timestamp_of_rtp_packet(&packet) {
+                    if self.epoch != Some(epoch) {
+                        self.timestamp_offset = self.timestamp_offset_for(epoch, timestamp);
+                        self.epoch = Some(epoch);
+                    }
+                    let timestamp = timestamp.wrapping_add(self.timestamp_offset);
+                    // Video timestamps go back and forth a little with B-frames, only
+                    // moving forward counts.
+                    let increment = timestamp.wrapping_sub(self.last_timestamp);
+                    if self.packets > 0 && (increment as i32) > 0 {
+                        self.last_increment = increment;
+                    }
+                    packet[2..4].copy_from_slice(&self.next_seq.to_be_bytes());
+                    packet[4..8].copy_from_slice(&timestamp.to_be_bytes());
+                    packet[8..12].copy_from_slice(&self.ssrc.to_be_bytes());
+                    self.next_seq = self.next_seq.wrapping_add(1);
+                    self.last_timestamp = timestamp;
+                    self.packets = self.packets.wrapping_add(1);
+                    self.octets = self
+                        .octets
+                        .wrapping_add(payload(&packet).map(<[u8]>::len).unwrap_or(0) as u32);
+                }
+                Some(video::RtpBuf::Rtp(packet))
+            }
+            SharedRtpBuf::Rtcp(packet) => {
+                // A sender report of a new stream would otherwise be sent with
+                // the timestamp offset of the previous one.
+                if self.epoch.is_some() && self.epoch != Some(epoch) {
+                    return None;
+                }
+                let mut packet = packet.to_vec();
+                rtcp::rewrite(
+                    &mut packet,
+                    self.ssrc,
+                    self.timestamp_offset,
+                    self.packets,
+                    self.octets,
+                );
+                Some(video::RtpBuf::Rtcp(packet))
+            }
+        }
+    }
+
+    /// Offset for the timestamps of the given epoch, where `timestamp` is
+    /// the first one of the epoch that is sent.
+    fn timestamp_offset_for(&self, epoch: u64, timestamp: u32) -> u32 {
+        match self.epoch {
+            Some(current) if current != epoch => self
+                .last_timestamp
+                .wrapping_add(self.last_increment)
+                .wrapping_sub(timestamp),
+            _ => self.timestamp_offset,
+        }
+    }
+}
+
+/// Get the timestamp from the header of an RTP packet. Returns nothing if
+/// the packet is too short to even have a header.
+pub fn timestamp_of_rtp_packet(packet: &[u8]) -> Option<u32> {
+    if packet.len() < HEADER_SIZE {
+        return None;
+    }
+    let timestamp = &packet[4..8];
+    Some(u32::from_be_bytes([
+        timestamp[0],
+        timestamp[1],
+        timestamp[2],
+        timestamp[3],
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use video_rs as video;
+
+    use super::{Rewriter, SharedRtpBuf};
+
+    const SSRC: u32 = 0xdeadbeef;
+
+    fn rtp(seq: u16, timestamp: u32) -> SharedRtpBuf {
+        let mut packet = vec![2 << 6, 96];
+        packet.extend(seq.to_be_bytes());
+        packet.extend(timestamp.to_be_bytes());
+        packet.extend(SSRC.to_be_bytes());
+        packet.extend([0x41, 0x00, 0x00]);
+        SharedRtpBuf::Rtp(packet.into())
+    }
+
+    fn sender_report(timestamp: u32) -> SharedRtpBuf {
+        let mut packet = vec![2 << 6, 200, 0, 6];
+        packet.extend(SSRC.to_be_bytes());
+        packet.extend([0; 8]);
+        packet.extend(timestamp.to_be_bytes());
+        packet.extend([0; 8]);
+        SharedRtpBuf::Rtcp(packet.into())
+    }
+
+    fn header(buf: Option<video::RtpBuf>) -> (u16, u32, u32) {
+        match buf {
+            Some(video::RtpBuf::Rtp(packet)) => (
+                u16::from_be_bytes([packet[2], packet[3]]),
+                u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+                u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]),
+            ),
+            _ => panic!("expected RTP packet"),
+        }
+    }
+
+    fn sender_report_timestamp(buf: Option<video::RtpBuf>) -> u32 {
+        match buf {
+            Some(video::RtpBuf::Rtcp(packet)) => {
+                u32::from_be_bytes([packet[16], packet[17], packet[18], packet[19]])
+            }
+            _ => panic!("expected RTCP packet"),
+        }
+    }
+
+    #[test]
+    fn rewrite_replaces_ssrc() {
+        let mut rewriter = Rewriter::new();
+        let (_, _, ssrc) = header(rewriter.rewrite(0, &rtp(1, 1000)));
+        assert_eq!(ssrc, rewriter.ssrc());
+    }
+
+    #[test]
+    fn rewrite_keeps_payload() {
+        let mut rewriter = Rewriter::new();
+        match rewriter.rewrite(0, &rtp(1, 1000)) {
+            Some(video::RtpBuf::Rtp(packet)) => assert_eq!(&packet[12..], &[0x41, 0x00, 0x00]),
+            _ => panic!("expected RTP packet"),
+        }
+    }
+
+    #[test]
+    fn sequence_counts_packets_sent() {
+        let mut rewriter = Rewriter::new();
+        let (seq_0, timestamp_0, _) = header(rewriter.rewrite(0, &rtp(100, 1000)));
+        // Packets the client was not sent do not leave a gap.
+        let (seq_1, timestamp_1, _) = header(rewriter.rewrite(0, &rtp(105, 16000)));
+        assert_eq!(seq_1, seq_0.wrapping_add(1));
+        assert_eq!(timestamp_1, timestamp_0.wrapping_add(15000));
+    }
+
+    #[test]
+    fn stream_state_refers_to_next_packet() {
+        let mut rewriter = Rewriter::new();
+        let (seq, timestamp, _) = header(rewriter.rewrite(0, &rtp(100, 1000)));
+        let next = rtp(101, 4000);
+        let state = rewriter.stream_state(0, std::slice::from_ref(&next));
+        assert_eq!(state.rtp_seq, seq.wrapping_add(1));
+        assert_eq!(state.rtp_timestamp, timestamp.wrapping_add(3000));
+        // Without packets, the timestamp is that of the last packet sent.
+        assert_eq!(rewriter.stream_state(0, &[]).rtp_timestamp, timestamp);
+        let (next_seq, next_timestamp, _) = header(rewriter.rewrite(0, &next));
+        assert_eq!(next_seq, state.rtp_seq);
+        assert_eq!(next_timestamp, state.rtp_timestamp);
+    }
+
+    #[test]
+    fn sequence_and_timestamps_continue_across_epochs() {
+        let mut rewriter = Rewriter::new();
+        let (seq_0, timestamp_0, _) = header(rewriter.rewrite(0, &rtp(100, 1000)));
+        let (seq_1, timestamp_1, _) = header(rewriter.rewrite(0, &rtp(101, 4000)));
+        assert_eq!(seq_1, seq_0.wrapping_add(1));
+        assert_eq!(timestamp_1, timestamp_0.wrapping_add(3000));
+        // The new stream starts with entirely different numbers.
+        let (seq_2, timestamp_2, _) = header(rewriter.rewrite(1, &rtp(7, 90)));
+        let (seq_3, timestamp_3, _) = header(rewriter.rewrite(1, &rtp(8, 3090)));
+        assert_eq!(seq_2, seq_1.wrapping_add(1));
+        assert_eq!(seq_3, seq_2.wrapping_add(1));
+        assert_eq!(timestamp_2, timestamp_1.wrapping_add(3000));
+        assert_eq!(timestamp_3, timestamp_2.wrapping_add(3000));
+    }
+
+    #[test]
+    fn offset_is_set_by_first_packet_of_epoch() {
+        let mut rewriter = Rewriter::new();
+        rewriter.rewrite(0, &rtp(100, 1000));
+        let (_, timestamp, _) = header(rewriter.rewrite(1, &rtp(7, 90)));
+        let (_, next_timestamp, _) = header(rewriter.rewrite(1, &rtp(8, 3090)));
+        assert_eq!(next_timestamp, timestamp.wrapping_add(3000));
+    }
+
+    #[test]
+    fn sequence_wraps_around() {
+        let mut rewriter = Rewriter::new();
+        rewriter.next_seq = u16::MAX;
+        let (seq_0, _, _) = header(rewriter.rewrite(0, &rtp(1, 1000)));
+        let (seq_1, _, _) = header(rewriter.rewrite(0, &rtp(2, 1000)));
+        assert_eq!(seq_0, u16::MAX);
+        assert_eq!(seq_1, 0);
+    }
+
+    #[test]
+    fn timestamps_wrap_around() {
+        let mut rewriter = Rewriter::new();
+        rewriter.timestamp_offset = u32::MAX - 1000;
+        let (_, timestamp_0, _) = header(rewriter.rewrite(0, &rtp(1, 0)));
+        let (_, timestamp_1, _) = header(rewriter.rewrite(0, &rtp(2, 3000)));
+        assert_eq!(timestamp_0, u32::MAX - 1000);
+        assert_eq!(timestamp_1, 1999);
+        // The increment is measured across the wraparound too.
+        let (_, timestamp_2, _) = header(rewriter.rewrite(1, &rtp(1, 500_000)));
+        assert_eq!(timestamp_2, 4999);
+    }
+
+    #[test]
+    fn sender_reports_of_new_epoch_wait_for_first_packet() {
+        let mut rewriter = Rewriter::new();
+        let (_, timestamp, _) = header(rewriter.rewrite(0, &rtp(100, 1000)));
+        assert_eq!(
+            sender_report_timestamp(rewriter.rewrite(0, &sender_report(1000))),
+            timestamp
+        );
+        // The source started over, and its first sender report came before
+        // its first packet.
+        assert!(rewriter.rewrite(1, &sender_report(90)).is_none());
+        let (_, timestamp, _) = header(rewriter.rewrite(1, &rtp(7, 90)));
+        assert_eq!(
+            sender_report_timestamp(rewriter.rewrite(1, &sender_report(90))),
+            timestamp
+        );
+    }
+
+    #[test]
+    fn sender_reports_before_first_packet_are_sent() {
+        let mut rewriter = Rewriter::new();
+        let report_timestamp = sender_report_timestamp(rewriter.rewrite(0, &sender_report(1000)));
+        let (_, timestamp, _) = header(rewriter.rewrite(0, &rtp(100, 1000)));
+        assert_eq!(report_timestamp, timestamp);
+    }
+}
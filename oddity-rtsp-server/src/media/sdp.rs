@@ -1,43 +1,127 @@
 use std::error;
 use std::fmt;
+use std::time::Duration;
 
-use oddity_sdp_protocol::{CodecInfo, Direction, Kind, Protocol, TimeRange};
+use oddity_sdp_protocol::{CodecInfo, Direction, Kind, Protocol, Tag, TimeRange};
 
+use crate::media::track;
 use crate::media::video::reader;
 use crate::media::video::rtp_muxer;
-use crate::media::MediaDescriptor;
+use crate::media::video::stream::StreamInfo;
+use crate::media::{MediaDescriptor, MediaInfo};
 
 pub use oddity_sdp_protocol::Sdp;
 
+const TARGET_DUMMY_PORT: u16 = 0;
+
 /// Create a new SDP description for the given media descriptor. The
 /// SDP contents can be used over RTSP when the client requested a
 /// stream description.
 ///
 /// Note: This function only handles the most appropriate video stream
-/// and tosses any audio or other streams.
+/// and audio stream and tosses any other streams. Every stream gets its
+/// own media section, with a control attribute that identifies its track.
 ///
 /// # Arguments
 ///
 /// * `name` - Name of stream.
 /// * `descriptor` - Media stream descriptor.
-pub async fn create(name: &str, descriptor: &MediaDescriptor) -> Result<Sdp, SdpError> {
-    const ORIGIN_DUMMY_HOST: [u8; 4] = [0, 0, 0, 0];
-    const TARGET_DUMMY_HOST: [u8; 4] = [0, 0, 0, 0];
-    const TARGET_DUMMY_PORT: u16 = 0;
-
+/// * `vod` - Whether or not the media is served as video-on-demand, in
+///   which case the range of the media is included.
+pub async fn create(name: &str, descriptor: &MediaDescriptor, vod: bool) -> Result<Sdp, SdpError> {
     tracing::trace!("sdp: initializing reader");
-    let reader = reader::backend::make_reader_with_sane_settings(descriptor.clone().into())
+    let reader = reader::backend::make_reader_with_sane_settings(descriptor.clone())
         .await
         .map_err(SdpError::Media)?;
-    let best_video_stream = reader.best_video_stream_index().map_err(SdpError::Media)?;
-    tracing::trace!(best_video_stream, "sdp: initialized reader");
+    let media_info = MediaInfo::from_reader_best_streams(&reader).map_err(SdpError::Media)?;
+    tracing::trace!(
+        num_streams = media_info.streams.len(),
+        "sdp: initialized reader"
+    );
+
+    let duration = if vod {
+        // The duration of the input is in microseconds.
+        Some(Duration::from_micros(reader.input.duration().max(0) as u64))
+    } else {
+        None
+    };
+
+    describe(name, media_info, duration).await
+}
 
-    tracing::trace!("sdp: initializing muxer");
+/// Create a new SDP description from media information that is already
+/// known, such as that of a running source. The media is assumed to be
+/// live.
+///
+/// # Arguments
+///
+/// * `name` - Name of stream.
+/// * `media_info` - Streams to describe.
+pub async fn create_from_media_info(name: &str, media_info: MediaInfo) -> Result<Sdp, SdpError> {
+    describe(name, media_info, None).await
+}
+
+async fn describe(
+    name: &str,
+    media_info: MediaInfo,
+    duration: Option<Duration>,
+) -> Result<Sdp, SdpError> {
+    const ORIGIN_DUMMY_HOST: [u8; 4] = [0, 0, 0, 0];
+    const TARGET_DUMMY_HOST: [u8; 4] = [0, 0, 0, 0];
+
+    let sdp = Sdp::new(
+        ORIGIN_DUMMY_HOST.into(),
+        name.to_string(),
+        TARGET_DUMMY_HOST.into(),
+        // Live streams and playback on repeat are "live". Video-on-demand
+        // sessions are not bounded in time either, the range of the media
+        // is advertised separately.
+        TimeRange::Live,
+    );
+
+    // Aggregate control of all tracks uses the presentation URL itself.
+    let sdp = sdp.with_tag(Tag::Value("control".to_string(), "*".to_string()));
+
+    let mut sdp = if let Some(duration) = duration {
+        sdp.with_tag(Tag::Value(
+            "range".to_string(),
+            format!("npt=0-{:.3}", duration.as_secs_f64()),
+        ))
+    } else {
+        sdp
+    };
+
+    for (track, stream_info) in media_info.streams.into_iter().enumerate() {
+        let control = Tag::Value("control".to_string(), track::control(track));
+        sdp = if track::is_video(track) {
+            add_video_media(sdp, stream_info)
+                .await?
+                .with_media_tag(control)
+        } else {
+            // Not being able to serve audio is not a reason to not serve the
+            // video, so we just leave the audio stream out in that case. Its
+            // control attribute must not end up on the video media section.
+            match add_audio_media(sdp.clone(), stream_info).await {
+                Ok(sdp) => sdp.with_media_tag(control),
+                Err(err) => {
+                    tracing::warn!(%err, track, "sdp: leaving out audio stream");
+                    sdp
+                }
+            }
+        };
+    }
+
+    tracing::trace!(%sdp, "generated sdp");
+    Ok(sdp)
+}
+
+async fn add_video_media(sdp: Sdp, stream_info: StreamInfo) -> Result<Sdp, SdpError> {
+    tracing::trace!("sdp: initializing video muxer");
     let muxer = rtp_muxer::make_rtp_muxer()
         .await
-        .and_then(|muxer| muxer.with_stream(reader.stream_info(best_video_stream)?))
+        .and_then(|muxer| muxer.with_stream(stream_info))
         .map_err(SdpError::Media)?;
-    tracing::trace!("sdp: initialized muxer");
+    tracing::trace!("sdp: initialized video muxer");
 
     let (sps, pps) = muxer
         .parameter_sets_h264()
@@ -55,30 +139,47 @@ pub async fn create(name: &str, descriptor: &MediaDescriptor) -> Result<Sdp, Sdp
     // assume H.264 from this point onwards.
     let codec_info = CodecInfo::h264(sps, pps.as_slice(), muxer.packetization_mode());
 
-    let sdp = Sdp::new(
-        ORIGIN_DUMMY_HOST.into(),
-        name.to_string(),
-        TARGET_DUMMY_HOST.into(),
-        // Since we support only live streams or playback on repeat,
-        // all streams are basically "live".
-        TimeRange::Live,
-    );
-
-    let sdp = sdp.with_media(
+    Ok(sdp.with_media(
         Kind::Video,
         TARGET_DUMMY_PORT,
         Protocol::RtpAvp,
         codec_info,
         Direction::ReceiveOnly,
-    );
+    ))
+}
 
-    tracing::trace!(%sdp, "generated sdp");
-    Ok(sdp)
+async fn add_audio_media(sdp: Sdp, stream_info: StreamInfo) -> Result<Sdp, SdpError> {
+    tracing::trace!("sdp: initializing audio muxer");
+    let muxer = rtp_muxer::make_rtp_muxer()
+        .await
+        .and_then(|muxer| muxer.with_stream(stream_info))
+        .map_err(SdpError::Media)?;
+    tracing::trace!("sdp: initialized audio muxer");
+
+    // Like with video, only a single codec is supported: AAC.
+    let (sample_rate, channels, config) = muxer
+        .audio_specific_config_aac()
+        .into_iter()
+        .filter_map(Result::ok)
+        .next()
+        .ok_or(SdpError::CodecNotSupported)?;
+    tracing::trace!(sample_rate, channels, "sdp: found AAC config");
+
+    let codec_info = CodecInfo::aac(sample_rate, channels, config);
+
+    Ok(sdp.with_media(
+        Kind::Audio,
+        TARGET_DUMMY_PORT,
+        Protocol::RtpAvp,
+        codec_info,
+        Direction::ReceiveOnly,
+    ))
 }
 
 #[derive(Debug)]
 pub enum SdpError {
     CodecNotSupported,
+    SourceUnavailable,
     Media(video_rs::Error),
 }
 
@@ -86,6 +187,7 @@ impl fmt::Display for SdpError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             SdpError::CodecNotSupported => write!(f, "codec not supported"),
+            SdpError::SourceUnavailable => write!(f, "source unavailable"),
             SdpError::Media(error) => write!(f, "media error: {}", error),
         }
     }
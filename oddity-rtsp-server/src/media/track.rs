@@ -0,0 +1,30 @@
+//! Clients refer to the tracks of a media item (see [`super::MediaInfo`]) by
+//! control URL. The control URL of a track is the URL of the media item with
+//! `/trackID=N` appended to it.
+
+const CONTROL_PREFIX: &str = "trackID=";
+
+/// Whether or not the track is the video track. The video always comes
+/// first, any other tracks are audio.
+pub fn is_video(track: usize) -> bool {
+    track == 0
+}
+
+/// Control attribute of a track, relative to the URL of the media item.
+pub fn control(track: usize) -> String {
+    format!("{}{}", CONTROL_PREFIX, track)
+}
+
+/// Split request path into the path of the media item and the track it
+/// refers to, if any.
+pub fn split_path(path: &str) -> (&str, Option<usize>) {
+    if let Some((media_path, control)) = path.rsplit_once('/') {
+        if let Some(track) = control
+            .strip_prefix(CONTROL_PREFIX)
+            .and_then(|track| track.parse().ok())
+        {
+            return (media_path, Some(track));
+        }
+    }
+    (path, None)
+}
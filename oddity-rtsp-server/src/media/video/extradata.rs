@@ -0,0 +1,219 @@
+//! Codec configuration in the extradata of a stream, which is what the SDP
+//! needs to describe it.
+
+use video_rs::{self as video, Pps, Sps};
+
+type Result<T> = std::result::Result<T, video::Error>;
+
+/// Sample rates of AAC by their index in an AudioSpecificConfig.
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Extract the H.264 parameter sets from extradata, which is either in AVCC
+/// format (common for files) or in Annex B format (common for live streams).
+pub fn parameter_sets_h264(extradata: &[u8]) -> Result<(Sps<'_>, Pps<'_>)> {
+    match extradata.first() {
+        Some(0x00) => parameter_sets_h264_annexb(extradata),
+        Some(0x01) => parameter_sets_h264_avcc(extradata),
+        _ => Err(video::Error::InvalidExtraData),
+    }
+}
+
+/// Sample rate and number of channels in an AAC AudioSpecificConfig (ISO/IEC
+/// 14496-3). Channel layouts that are defined in the stream itself are not
+/// supported.
+pub fn audio_specific_config_aac(config: &[u8]) -> Result<(u32, u32)> {
+    let mut bits = BitReader::new(config);
+    let object_type = bits.read(5)?;
+    if object_type == 31 {
+        // Escaped object type.
+        bits.read(6)?;
+    }
+    let sample_rate = match bits.read(4)? {
+        // Sample rate that is not in the table.
+        0xf => bits.read(24)?,
+        index => *AAC_SAMPLE_RATES
+            .get(index as usize)
+            .ok_or(video::Error::InvalidExtraData)?,
+    };
+    let channels = match bits.read(4)? {
+        channels @ 1..=6 => channels,
+        7 => 8,
+        _ => return Err(video::Error::InvalidExtraData),
+    };
+    Ok((sample_rate, channels))
+}
+
+fn parameter_sets_h264_avcc(bytes: &[u8]) -> Result<(Sps<'_>, Pps<'_>)> {
+    let sps_size = match bytes.get(6..8) {
+        Some(size) => u16::from_be_bytes([size[0], size[1]]) as usize,
+        None => return Err(video::Error::InvalidExtraData),
+    };
+    let sps = bytes
+        .get(8..8 + sps_size)
+        .ok_or(video::Error::InvalidExtraData)?;
+
+    let (pps_num, mut rest) = match bytes.get(8 + sps_size..) {
+        Some([pps_num, rest @ ..]) => (*pps_num, rest),
+        _ => return Err(video::Error::InvalidExtraData),
+    };
+    let mut ppss = Vec::with_capacity(pps_num as usize);
+    for _ in 0..pps_num {
+        let pps_size = match rest {
+            [high, low, ..] => u16::from_be_bytes([*high, *low]) as usize,
+            _ => return Err(video::Error::InvalidExtraData),
+        };
+        let pps = rest
+            .get(2..2 + pps_size)
+            .ok_or(video::Error::InvalidExtraData)?;
+        ppss.push(pps);
+        rest = &rest[2 + pps_size..];
+    }
+
+    Ok((sps, ppss))
+}
+
+fn parameter_sets_h264_annexb(bytes: &[u8]) -> Result<(Sps<'_>, Pps<'_>)> {
+    let mut sps = None;
+    let mut ppss = Vec::new();
+
+    let mut current = find_start_code(bytes, 0).map(|(_, next)| next);
+    while let Some(index) = current {
+        let (end, next) = match find_start_code(bytes, index) {
+            Some((end, next)) => (end, Some(next)),
+            None => (bytes.len(), None),
+        };
+        let nal = &bytes[index..end];
+        match nal.first().map(|header| header & 0x1f) {
+            Some(0x07) => sps = Some(nal),
+            Some(0x08) => ppss.push(nal),
+            _ => {}
+        }
+        current = next;
+    }
+
+    sps.map(|sps| (sps, ppss))
+        .ok_or(video::Error::InvalidExtraData)
+}
+
+/// Find the first start code (`00 00 01` or `00 00 00 01`) from `offset`
+/// onwards, and return where it starts and where the NAL unit after it
+/// starts.
+fn find_start_code(bytes: &[u8], offset: usize) -> Option<(usize, usize)> {
+    let part = bytes.get(offset..)?;
+    (0..part.len()).find_map(|i| {
+        if part[i..].starts_with(&[0x00, 0x00, 0x01]) {
+            Some((offset + i, offset + i + 3))
+        } else if part[i..].starts_with(&[0x00, 0x00, 0x00, 0x01]) {
+            Some((offset + i, offset + i + 4))
+        } else {
+            None
+        }
+    })
+}
+
+/// Reads big-endian bit fields.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn read(&mut self, num_bits: usize) -> Result<u32> {
+        let mut value = 0;
+        for _ in 0..num_bits {
+            let byte = self
+                .bytes
+                .get(self.position / 8)
+                .ok_or(video::Error::InvalidExtraData)?;
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: &[u8] = &[0x67, 0x64, 0x00, 0x1f, 0xac];
+    const PPS: &[u8] = &[0x68, 0xee, 0x3c, 0x80];
+
+    #[test]
+    fn parameter_sets_h264_from_avcc() {
+        let mut extradata = vec![0x01, 0x64, 0x00, 0x1f, 0xff, 0xe1, 0x00, SPS.len() as u8];
+        extradata.extend_from_slice(SPS);
+        extradata.extend_from_slice(&[0x01, 0x00, PPS.len() as u8]);
+        extradata.extend_from_slice(PPS);
+        let (sps, pps) = parameter_sets_h264(&extradata).unwrap();
+        assert_eq!(sps, SPS);
+        assert_eq!(pps, vec![PPS]);
+    }
+
+    #[test]
+    fn parameter_sets_h264_from_truncated_avcc() {
+        let mut extradata = vec![0x01, 0x64, 0x00, 0x1f, 0xff, 0xe1, 0x00, SPS.len() as u8];
+        extradata.extend_from_slice(SPS);
+        extradata.extend_from_slice(&[0x01, 0x00, PPS.len() as u8]);
+        extradata.extend_from_slice(&PPS[..2]);
+        assert!(parameter_sets_h264(&extradata).is_err());
+        assert!(parameter_sets_h264(&extradata[..10]).is_err());
+    }
+
+    #[test]
+    fn parameter_sets_h264_from_annexb() {
+        let mut extradata = vec![0x00, 0x00, 0x00, 0x01];
+        extradata.extend_from_slice(SPS);
+        extradata.extend_from_slice(&[0x00, 0x00, 0x01]);
+        extradata.extend_from_slice(PPS);
+        let (sps, pps) = parameter_sets_h264(&extradata).unwrap();
+        assert_eq!(sps, SPS);
+        assert_eq!(pps, vec![PPS]);
+    }
+
+    #[test]
+    fn parameter_sets_h264_without_sps() {
+        let mut extradata = vec![0x00, 0x00, 0x00, 0x01];
+        extradata.extend_from_slice(PPS);
+        assert!(parameter_sets_h264(&extradata).is_err());
+        assert!(parameter_sets_h264(&[]).is_err());
+    }
+
+    #[test]
+    fn audio_specific_config_aac_lc() {
+        // AAC LC, 44.1 kHz, stereo.
+        assert_eq!(
+            audio_specific_config_aac(&[0x12, 0x10]).unwrap(),
+            (44100, 2)
+        );
+        // AAC LC, 48 kHz, 5.1.
+        assert_eq!(
+            audio_specific_config_aac(&[0x11, 0xb0]).unwrap(),
+            (48000, 6)
+        );
+    }
+
+    #[test]
+    fn audio_specific_config_aac_explicit_sample_rate() {
+        // AAC LC, 22050 Hz written out in full, mono.
+        assert_eq!(
+            audio_specific_config_aac(&[0x17, 0x80, 0x2b, 0x11, 0x08]).unwrap(),
+            (22050, 1),
+        );
+    }
+
+    #[test]
+    fn audio_specific_config_aac_invalid() {
+        assert!(audio_specific_config_aac(&[]).is_err());
+        assert!(audio_specific_config_aac(&[0x12]).is_err());
+        // Channel layout defined in the stream.
+        assert!(audio_specific_config_aac(&[0x12, 0x00]).is_err());
+    }
+}
@@ -1,2 +1,5 @@
+pub mod extradata;
+pub mod packet;
 pub mod reader;
 pub mod rtp_muxer;
+pub mod stream;
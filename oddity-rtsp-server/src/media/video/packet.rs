@@ -0,0 +1,81 @@
+//! Packets of any stream of a reader.
+//!
+//! [`video_rs::Reader::read`] only returns packets of a single stream and
+//! drops the others, and [`video_rs::Packet`] does not know which stream it
+//! belongs to. Serving video and audio together needs both, so packets are
+//! read from the underlying input directly.
+
+use std::time::Duration;
+
+use ffmpeg_next::{Packet as AvPacket, Rational as AvRational, Rescale};
+
+use video_rs::{self as video, Reader};
+
+type Result<T> = std::result::Result<T, video::Error>;
+
+/// Packet of any stream of a reader, in the time base of that stream.
+#[derive(Clone)]
+pub struct Packet {
+    inner: AvPacket,
+    stream_index: usize,
+    time_base: AvRational,
+}
+
+impl Packet {
+    /// Index of the stream the packet belongs to.
+    pub fn stream_index(&self) -> usize {
+        self.stream_index
+    }
+
+    /// Presentation timestamp of the packet, or zero if it has none.
+    pub fn pts(&self) -> Duration {
+        self.to_duration(self.inner.pts().unwrap_or(0))
+    }
+
+    /// Time it takes to play the packet, or zero if it is not known.
+    pub fn duration(&self) -> Duration {
+        self.to_duration(self.inner.duration())
+    }
+
+    /// Move the packet to the given timestamp, in the time base of its
+    /// stream, and return the timestamp of the packet that follows it.
+    pub fn retime(&mut self, timestamp: i64) -> i64 {
+        self.inner.set_dts(Some(timestamp));
+        self.inner.set_pts(Some(timestamp));
+        timestamp + self.inner.duration()
+    }
+
+    pub(crate) fn into_parts(self) -> (AvPacket, usize, AvRational) {
+        (self.inner, self.stream_index, self.time_base)
+    }
+
+    fn to_duration(&self, value: i64) -> Duration {
+        // Rescale to microseconds (the time base of ffmpeg itself).
+        let micros = value.rescale(self.time_base, AvRational::new(1, 1_000_000));
+        Duration::from_micros(micros.max(0) as u64)
+    }
+}
+
+/// Read the next packet of any stream of the reader. Like
+/// [`video_rs::Reader::read`], this gives up after a couple of attempts
+/// that do not yield a packet, with [`video_rs::Error::ReadExhausted`].
+pub fn read_any(reader: &mut Reader) -> Result<Packet> {
+    let mut error_count = 0;
+    loop {
+        match reader.input.packets().next() {
+            Some((stream, packet)) => {
+                return Ok(Packet {
+                    inner: packet,
+                    stream_index: stream.index(),
+                    time_base: stream.time_base(),
+                });
+            }
+            None => {
+                error_count += 1;
+                if error_count > 3 {
+                    return Err(video::Error::ReadExhausted);
+                }
+            }
+        }
+    }
+}
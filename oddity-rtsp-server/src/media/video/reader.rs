@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::thread;
 
 use tokio::sync::mpsc;
@@ -5,35 +7,97 @@ use tokio::task;
 
 use video_rs as video;
 
+use crate::media::rtp::{self, SharedRtpBuf};
+use crate::media::track;
+use crate::media::video::packet::{self, Packet};
+use crate::media::video::rtp_muxer::{self, RtpMuxer};
 use crate::media::{MediaDescriptor, MediaInfo};
+use crate::metrics::PathMetrics;
 
 type Result<T> = std::result::Result<T, video::Error>;
 
+/// Packet read by [`StreamReader`], muxed to RTP. Sessions share the muxed
+/// packets, and only rewrite the headers for their client.
+#[derive(Clone)]
+pub struct StreamPacket {
+    /// Track the packet belongs to, see [`MediaInfo`].
+    pub track: usize,
+    pub bufs: Vec<SharedRtpBuf>,
+    /// Whether or not the packet is a keyframe of the video stream.
+    pub keyframe: bool,
+    /// Number of times the source started over before the packet was read,
+    /// which is up to the source. The timestamps of packets of different
+    /// epochs have nothing to do with each other.
+    pub epoch: u64,
+    /// Position of the packet among all packets of the source, which is up to
+    /// the source, see [`GopCache`].
+    ///
+    /// [`GopCache`]: crate::source::gop_cache::GopCache
+    pub number: u64,
+}
+
+impl StreamPacket {
+    /// Timestamp of the first RTP packet, if there is one.
+    pub fn rtp_timestamp(&self) -> Option<u32> {
+        self.bufs.iter().find_map(|buf| match buf {
+            SharedRtpBuf::Rtp(packet) => rtp::timestamp_of_rtp_packet(packet),
+            SharedRtpBuf::Rtcp(_) => None,
+        })
+    }
+}
+
 pub struct StreamReader {
     pub info: MediaInfo,
     handle: Option<thread::JoinHandle<()>>,
-    packet_rx: mpsc::UnboundedReceiver<Result<video::Packet>>,
+    packet_rx: mpsc::UnboundedReceiver<Result<StreamPacket>>,
     stop_tx: mpsc::UnboundedSender<()>,
 }
 
 impl StreamReader {
-    pub async fn new(descriptor: &MediaDescriptor) -> Result<Self> {
+    pub async fn new(descriptor: &MediaDescriptor, path_metrics: Arc<PathMetrics>) -> Result<Self> {
         let is_file = matches!(descriptor, MediaDescriptor::File(_));
 
         tracing::trace!(%descriptor, "initializing reader");
-        let inner = backend::make_reader_with_sane_settings(descriptor.clone().into()).await?;
+        let inner = backend::make_reader_with_sane_settings(descriptor.clone()).await?;
         tracing::trace!(%descriptor, "initialized reader");
 
-        let info = MediaInfo::from_reader_best_video_stream(&inner)?;
-        let stream_index = info.streams[0].index;
-        tracing::trace!(%descriptor, stream_index=stream_index, "selected video stream");
+        let info = MediaInfo::from_reader_best_streams(&inner)?;
+        let video_stream_index = info.streams[0].index;
+        tracing::trace!(
+            %descriptor,
+            video_stream_index,
+            num_streams = info.streams.len(),
+            "selected streams",
+        );
+
+        // Every stream is muxed once here, for all sessions together.
+        let mut muxers = Vec::with_capacity(info.streams.len());
+        for stream_info in info.streams.clone() {
+            muxers.push(
+                rtp_muxer::make_rtp_muxer()
+                    .await?
+                    .with_stream(stream_info)?,
+            );
+        }
 
         let (packet_tx, packet_rx) = mpsc::unbounded_channel();
         let (stop_tx, stop_rx) = mpsc::unbounded_channel();
 
         tracing::trace!(%descriptor, "starting stream reader");
-        let handle =
-            thread::spawn(move || Self::run(inner, stream_index, packet_tx, stop_rx, is_file));
+        let handle = thread::spawn({
+            let info = info.clone();
+            move || {
+                Self::run(
+                    inner,
+                    info,
+                    muxers,
+                    packet_tx,
+                    stop_rx,
+                    is_file,
+                    path_metrics,
+                )
+            }
+        });
         tracing::trace!(%descriptor, "started stream reader");
 
         Ok(Self {
@@ -44,7 +108,7 @@ impl StreamReader {
         })
     }
 
-    pub async fn read(&mut self) -> Option<Result<video::Packet>> {
+    pub async fn read(&mut self) -> Option<Result<StreamPacket>> {
         self.packet_rx.recv().await
     }
 
@@ -60,12 +124,15 @@ impl StreamReader {
 
     fn run(
         mut reader: video::Reader,
-        stream_index: usize,
-        packet_tx: mpsc::UnboundedSender<Result<video::Packet>>,
+        info: MediaInfo,
+        mut muxers: Vec<RtpMuxer>,
+        packet_tx: mpsc::UnboundedSender<Result<StreamPacket>>,
         mut stop_rx: mpsc::UnboundedReceiver<()>,
         is_file: bool,
+        path_metrics: Arc<PathMetrics>,
     ) {
-        let mut times = Times::new();
+        let video_stream_index = info.streams[0].index;
+        let mut times = HashMap::new();
 
         loop {
             match stop_rx.try_recv() {
@@ -76,26 +143,63 @@ impl StreamReader {
                 Err(mpsc::error::TryRecvError::Empty) => {}
             };
 
-            let read = reader.read(stream_index);
+            let read = packet::read_any(&mut reader);
 
             if is_file {
                 // To pretend the file is a live stream, we need to wait a bit after
-                // each packet or we'll overload the consumer.
+                // each packet or we'll overload the consumer. Only the video stream
+                // is used for timing, the other streams are interleaved with it.
                 if let Ok(packet) = read.as_ref() {
-                    thread::sleep(packet.duration().into());
+                    if packet.stream_index() == video_stream_index {
+                        thread::sleep(packet.duration());
+                    }
                 }
             }
 
             let packet = match read {
-                // Forward OK packets.
                 Ok(mut packet) => {
+                    // Skip packets of streams we do not serve.
+                    let track = match info
+                        .streams
+                        .iter()
+                        .position(|stream_info| stream_info.index == packet.stream_index())
+                    {
+                        Some(track) => track,
+                        None => continue,
+                    };
+
                     // Manually keep time for file-based streams. This way we can seek
                     // in the file and pretend that time is still running linearly.
                     if is_file {
-                        times.update(&mut packet);
+                        times
+                            .entry(packet.stream_index())
+                            .or_insert_with(Times::new)
+                            .update(&mut packet);
                     }
 
-                    Some(Ok(packet))
+                    // A packet that cannot be muxed is skipped, the stream itself
+                    // is fine.
+                    let bufs = match muxers[track].mux(packet) {
+                        Ok(bufs) => bufs,
+                        Err(err) => {
+                            path_metrics.mux_failures.inc();
+                            tracing::error!(%err, track, "failed to mux packet");
+                            continue;
+                        }
+                    };
+                    let bufs = bufs.into_iter().map(SharedRtpBuf::from).collect::<Vec<_>>();
+                    let keyframe = track::is_video(track)
+                        && bufs.iter().any(|buf| match buf {
+                            SharedRtpBuf::Rtp(payload) => rtp::starts_h264_keyframe(payload),
+                            SharedRtpBuf::Rtcp(_) => false,
+                        });
+                    Some(Ok(StreamPacket {
+                        track,
+                        bufs,
+                        keyframe,
+                        epoch: 0,
+                        number: 0,
+                    }))
                 }
                 // If the error was caused by an exhausted stream, try and see if we
                 // can seek to the beginning of the file and then just keep reading:
@@ -122,6 +226,12 @@ impl StreamReader {
                 }
             }
         }
+
+        // Throw away possible last RTP buffers, there is no "trailer" for a
+        // real-time stream.
+        for muxer in muxers.iter_mut() {
+            let _ = muxer.finish();
+        }
     }
 }
 
@@ -133,48 +243,253 @@ impl Drop for StreamReader {
     }
 }
 
+/// Reads a file for a single video-on-demand session. Unlike [`StreamReader`],
+/// this reader does not loop, only reads when asked to and can seek.
+pub struct VodReader {
+    handle: Option<thread::JoinHandle<()>>,
+    event_rx: mpsc::UnboundedReceiver<(usize, Result<VodEvent>)>,
+    control_tx: mpsc::UnboundedSender<VodControlMessage>,
+    epoch: usize,
+}
+
+pub enum VodEvent {
+    Packet(Packet),
+    /// Reached end of file or end of requested range.
+    End,
+}
+
+enum VodControlMessage {
+    Seek {
+        epoch: usize,
+        start: Option<f64>,
+        end: Option<f64>,
+    },
+    Resume,
+    Pause,
+    Stop,
+}
+
+impl VodReader {
+    pub async fn new(descriptor: &MediaDescriptor) -> Result<Self> {
+        tracing::trace!(%descriptor, "initializing vod reader");
+        let inner = backend::make_reader_with_sane_settings(descriptor.clone()).await?;
+        tracing::trace!(%descriptor, "initialized vod reader");
+
+        let info = MediaInfo::from_reader_best_streams(&inner)?;
+
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let (control_tx, control_rx) = mpsc::unbounded_channel();
+
+        tracing::trace!(%descriptor, "starting vod reader");
+        let handle = thread::spawn(move || Self::run(inner, info, event_tx, control_rx));
+        tracing::trace!(%descriptor, "started vod reader");
+
+        Ok(Self {
+            handle: Some(handle),
+            event_rx,
+            control_tx,
+            epoch: 0,
+        })
+    }
+
+    /// Read next event. Events that were produced before the last call to
+    /// [`VodReader::seek`] are skipped.
+    pub async fn read(&mut self) -> Option<Result<VodEvent>> {
+        loop {
+            match self.event_rx.recv().await {
+                Some((epoch, event)) if epoch == self.epoch => break Some(event),
+                Some(_) => continue,
+                None => break None,
+            }
+        }
+    }
+
+    /// Seek to `start` (in seconds) and stop reading at `end`. If `start`
+    /// is not given, reading continues from the current position.
+    pub fn seek(&mut self, start: Option<f64>, end: Option<f64>) {
+        self.epoch += 1;
+        let _ = self.control_tx.send(VodControlMessage::Seek {
+            epoch: self.epoch,
+            start,
+            end,
+        });
+    }
+
+    pub fn resume(&mut self) {
+        let _ = self.control_tx.send(VodControlMessage::Resume);
+    }
+
+    pub fn pause(&mut self) {
+        let _ = self.control_tx.send(VodControlMessage::Pause);
+    }
+
+    pub async fn stop(&mut self) {
+        if let Ok(()) = self.control_tx.send(VodControlMessage::Stop) {
+            if let Some(handle) = self.handle.take() {
+                tracing::trace!("sending stop signal to vod reader");
+                let _ = task::spawn_blocking(|| handle.join()).await;
+                tracing::trace!("stopped vod reader");
+            }
+        }
+    }
+
+    fn run(
+        mut reader: video::Reader,
+        info: MediaInfo,
+        event_tx: mpsc::UnboundedSender<(usize, Result<VodEvent>)>,
+        mut control_rx: mpsc::UnboundedReceiver<VodControlMessage>,
+    ) {
+        let video_stream_index = info.streams[0].index;
+        let mut epoch = 0;
+        let mut end = None;
+        let mut reading = false;
+
+        loop {
+            // Only block on the control channel when we're not reading, otherwise
+            // just check if there's anything new.
+            let message = if reading {
+                match control_rx.try_recv() {
+                    Ok(message) => Some(message),
+                    Err(mpsc::error::TryRecvError::Empty) => None,
+                    Err(mpsc::error::TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match control_rx.blocking_recv() {
+                    Some(message) => Some(message),
+                    None => break,
+                }
+            };
+
+            match message {
+                Some(VodControlMessage::Seek {
+                    epoch: new_epoch,
+                    start,
+                    end: new_end,
+                }) => {
+                    epoch = new_epoch;
+                    end = new_end;
+                    if let Some(start) = start {
+                        tracing::trace!(start, "seeking");
+                        if let Err(err) = reader.seek((start * 1000.0) as i64) {
+                            tracing::error!(%err, start, "failed to seek");
+                            reading = false;
+                            if event_tx.send((epoch, Err(err))).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    continue;
+                }
+                Some(VodControlMessage::Resume) => {
+                    reading = true;
+                    continue;
+                }
+                Some(VodControlMessage::Pause) => {
+                    reading = false;
+                    continue;
+                }
+                Some(VodControlMessage::Stop) => {
+                    tracing::trace!("stopping vod reader");
+                    break;
+                }
+                None => {}
+            }
+
+            let event = match packet::read_any(&mut reader) {
+                Ok(packet) if !info.contains_stream(packet.stream_index()) => continue,
+                Ok(packet) => {
+                    if end
+                        .map(|end| packet.pts().as_secs_f64() >= end)
+                        .unwrap_or(false)
+                    {
+                        reading = false;
+                        Ok(VodEvent::End)
+                    } else {
+                        // Send packets at the rate at which they should be played,
+                        // using the video stream for timing.
+                        if packet.stream_index() == video_stream_index {
+                            thread::sleep(packet.duration());
+                        }
+                        Ok(VodEvent::Packet(packet))
+                    }
+                }
+                Err(video::Error::ReadExhausted) => {
+                    reading = false;
+                    Ok(VodEvent::End)
+                }
+                Err(err) => {
+                    reading = false;
+                    Err(err)
+                }
+            };
+
+            if event_tx.send((epoch, event)).is_err() {
+                tracing::trace!("vod event channel broke");
+                break;
+            }
+        }
+    }
+}
+
+impl Drop for VodReader {
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            panic!("Dropped `VodReader` whilst running.");
+        }
+    }
+}
+
+/// Timestamp of the next packet of a stream, in the time base of that stream.
 struct Times {
-    next_dts: video::Time,
-    next_pts: video::Time,
+    next: i64,
 }
 
 impl Times {
     pub fn new() -> Self {
-        Times {
-            next_dts: video::Time::zero(),
-            next_pts: video::Time::zero(),
-        }
+        Times { next: 0 }
     }
 
-    pub fn update(&mut self, packet: &mut video::Packet) {
-        if packet.duration().has_value() {
-            packet.set_dts(&self.next_dts);
-            packet.set_pts(&self.next_pts);
-            self.next_dts = self.next_dts.aligned_with(&packet.duration()).add();
-            self.next_pts = self.next_pts.aligned_with(&packet.duration()).add();
-        }
+    pub fn update(&mut self, packet: &mut Packet) {
+        self.next = packet.retime(self.next);
     }
 }
 
 // Holds functions that deal with the video backend stuff in `video_rs`.
 pub mod backend {
 
+    use std::collections::HashMap;
+
     use tokio::task;
 
     use video_rs::{Error, Locator, Options, Reader};
 
-    pub async fn make_reader_with_sane_settings(locator: Locator) -> Result<Reader, Error> {
+    use crate::media::MediaDescriptor;
+
+    pub async fn make_reader_with_sane_settings(
+        descriptor: MediaDescriptor,
+    ) -> Result<Reader, Error> {
         task::spawn_blocking(move || {
-            let options = match locator {
-                Locator::Path(_) => Default::default(),
-                Locator::Url(_) => {
+            let options = match &descriptor {
+                MediaDescriptor::File(_) => Default::default(),
+                MediaDescriptor::Stream(_) => {
                     // For streaming sources (live sources), we want to use TCP transport
                     // over UDP and have sane timeouts.
                     Options::new_with_rtsp_transport_tcp_and_sane_timeouts()
                 }
+                MediaDescriptor::Published(_, reservation) => {
+                    // The demuxer is about to bind the ports in the SDP file, so they
+                    // no longer need to be kept free for it.
+                    reservation.release();
+                    // The SDP file points the demuxer at local UDP ports, which it is
+                    // not allowed to open by default.
+                    Options::from(HashMap::from([(
+                        "protocol_whitelist".to_string(),
+                        "file,udp,rtp".to_string(),
+                    )]))
+                }
             };
 
-            Reader::new_with_options(&locator, &options)
+            Reader::new_with_options(&Locator::from(descriptor), &options)
         })
         .await
         .unwrap()
@@ -1,19 +1,259 @@
-//! Async wrapper functions for [`video_rs::RtpMuxer`].
+//! RTP muxer for a single stream of a reader, and async wrapper functions for
+//! it.
+//!
+//! This works like [`video_rs::RtpMuxer`], but it muxes the streams and
+//! packets of [`crate::media::video::stream`] and
+//! [`crate::media::video::packet`], which can be audio as well as video.
+
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::slice;
 
 use tokio::task;
 
-use video_rs::{self as video, RtpMuxer};
+use ffmpeg_next::codec::Id as AvCodecId;
+use ffmpeg_next::format::context::Output as AvOutput;
+use ffmpeg_next::{encoder, ffi, Error as AvError};
+
+use video_rs::{self as video, Buf, Pps, RtpBuf, Sps};
+
+use crate::media::video::extradata;
+use crate::media::video::packet::Packet;
+use crate::media::video::stream::StreamInfo;
 
 type Result<T> = std::result::Result<T, video::Error>;
 
+/// Largest RTP packet the muxer produces.
+const MAX_PACKET_SIZE: usize = 1024;
+
+pub struct RtpMuxer {
+    output: AvOutput,
+    buffers: Vec<Buf>,
+    /// Index of the muxed stream in the reader it comes from.
+    stream_index: Option<usize>,
+    have_written_header: bool,
+    have_written_trailer: bool,
+}
+
+impl RtpMuxer {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            output: output_raw("rtp")?,
+            buffers: Vec::new(),
+            stream_index: None,
+            have_written_header: false,
+            have_written_trailer: false,
+        })
+    }
+
+    /// Set the stream to mux. RTP carries a single stream, so packets of any
+    /// other stream cannot be muxed.
+    pub fn with_stream(mut self, stream_info: StreamInfo) -> Result<Self> {
+        let (index, codec_parameters, _) = stream_info.into_parts();
+        let mut stream = self
+            .output
+            .add_stream(encoder::find(codec_parameters.id()))?;
+        stream.set_parameters(codec_parameters);
+        self.stream_index = Some(index);
+        Ok(self)
+    }
+
+    /// H.264 packetization mode of the muxer. The muxer is never asked to
+    /// use mode 0, so it uses mode 1 (non-interleaved), which allows large
+    /// NAL units to be fragmented.
+    pub fn packetization_mode(&self) -> usize {
+        1
+    }
+
+    /// Parameter sets of the muxed stream, if it is H.264.
+    pub fn parameter_sets_h264(&self) -> Vec<Result<(Sps<'_>, Pps<'_>)>> {
+        self.output
+            .streams()
+            .map(|stream| match stream.parameters().id() {
+                AvCodecId::H264 => extradata::parameter_sets_h264(self.extradata(stream.index())?),
+                _ => Err(video::Error::UnsupportedCodecParameterSets),
+            })
+            .collect()
+    }
+
+    /// Sample rate, number of channels and AudioSpecificConfig of the muxed
+    /// stream, if it is AAC.
+    pub fn audio_specific_config_aac(&self) -> Vec<Result<(u32, u32, &[u8])>> {
+        self.output
+            .streams()
+            .map(|stream| match stream.parameters().id() {
+                AvCodecId::AAC => {
+                    let config = self.extradata(stream.index())?;
+                    let (sample_rate, channels) = extradata::audio_specific_config_aac(config)?;
+                    Ok((sample_rate, channels, config))
+                }
+                _ => Err(video::Error::UnsupportedCodecParameterSets),
+            })
+            .collect()
+    }
+
+    pub fn mux(&mut self, packet: Packet) -> Result<Vec<RtpBuf>> {
+        if !self.have_written_header {
+            // The header of RTP is the SDP, which is produced separately, so
+            // nothing that is written here needs to be sent.
+            self.write(|output| output.write_header())?;
+            self.have_written_header = true;
+        }
+
+        let (mut packet, stream_index, source_time_base) = packet.into_parts();
+        if self.stream_index != Some(stream_index) {
+            return Err(AvError::StreamNotFound.into());
+        }
+        let destination_time_base = self
+            .output
+            .stream(0)
+            .ok_or(AvError::StreamNotFound)?
+            .time_base();
+        packet.set_stream(0);
+        packet.set_position(-1);
+        packet.rescale_ts(source_time_base, destination_time_base);
+
+        let bufs = self.write(|output| {
+            packet.write(output)?;
+            flush_output(output)
+        })?;
+        Ok(bufs.into_iter().map(RtpBuf::from).collect())
+    }
+
+    pub fn finish(&mut self) -> Result<Option<Vec<RtpBuf>>> {
+        if !self.have_written_header || self.have_written_trailer {
+            return Ok(None);
+        }
+        self.have_written_trailer = true;
+        let bufs = self.write(|output| output.write_trailer())?;
+        Ok(Some(bufs.into_iter().map(RtpBuf::from).collect()))
+    }
+
+    /// Let the output write, and take the RTP packets it wrote.
+    fn write<F>(&mut self, write: F) -> Result<Vec<Buf>>
+    where
+        F: FnOnce(&mut AvOutput) -> std::result::Result<(), AvError>,
+    {
+        // SAFETY: The buffers outlive the IO context, since it is freed again
+        // before this function returns.
+        unsafe { output_packetized_buf_start(&mut self.output, &mut self.buffers)? };
+        let result = write(&mut self.output);
+        unsafe { output_packetized_buf_end(&mut self.output) };
+        let bufs = mem::take(&mut self.buffers);
+        result?;
+        Ok(bufs)
+    }
+
+    fn extradata(&self, stream_index: usize) -> Result<&[u8]> {
+        let parameters = self
+            .output
+            .stream(stream_index)
+            .ok_or(AvError::StreamNotFound)?
+            .parameters();
+        // SAFETY: The parameters belong to the output, so the extradata lives
+        // as long as the output does.
+        unsafe {
+            let parameters = parameters.as_ptr();
+            if (*parameters).extradata.is_null() {
+                return Err(video::Error::MissingCodecParameters);
+            }
+            Ok(slice::from_raw_parts(
+                (*parameters).extradata,
+                (*parameters).extradata_size as usize,
+            ))
+        }
+    }
+}
+
+/// Allocate an output of the given format that does not write anywhere
+/// until it is pointed at buffers with [`output_packetized_buf_start`].
+fn output_raw(format: &str) -> Result<AvOutput> {
+    let format = CString::new(format).unwrap();
+    let mut output_ptr = ptr::null_mut();
+    // SAFETY: The output context is owned by the `Output` as soon as it is
+    // allocated.
+    unsafe {
+        match ffi::avformat_alloc_output_context2(
+            &mut output_ptr,
+            ptr::null_mut(),
+            format.as_ptr(),
+            ptr::null(),
+        ) {
+            ret if ret < 0 => Err(AvError::from(ret).into()),
+            _ => Ok(AvOutput::wrap(output_ptr)),
+        }
+    }
+}
+
+/// Point the output at an IO context that pushes every packet it writes onto
+/// `buffers`. Must be followed by [`output_packetized_buf_end`] before
+/// `buffers` is moved or dropped.
+unsafe fn output_packetized_buf_start(
+    output: &mut AvOutput,
+    buffers: &mut Vec<Buf>,
+) -> std::result::Result<(), AvError> {
+    let buffer = ffi::av_malloc(MAX_PACKET_SIZE) as *mut u8;
+    if buffer.is_null() {
+        return Err(AvError::Unknown);
+    }
+    let io = ffi::avio_alloc_context(
+        buffer,
+        MAX_PACKET_SIZE as c_int,
+        // Write only.
+        1,
+        buffers as *mut Vec<Buf> as *mut c_void,
+        None,
+        Some(push_packet),
+        None,
+    );
+    if io.is_null() {
+        ffi::av_free(buffer as *mut c_void);
+        return Err(AvError::Unknown);
+    }
+    // This is what makes every write a packet of its own.
+    (*io).max_packet_size = MAX_PACKET_SIZE as c_int;
+    (*output.as_mut_ptr()).pb = io;
+    Ok(())
+}
+
+/// Flush the IO context of the output and free it again.
+unsafe fn output_packetized_buf_end(output: &mut AvOutput) {
+    let mut io = (*output.as_mut_ptr()).pb;
+    ffi::avio_flush(io);
+    // The IO context may have replaced the buffer we gave it.
+    ffi::av_freep(&mut (*io).buffer as *mut *mut u8 as *mut c_void);
+    ffi::avio_context_free(&mut io);
+    // Otherwise the output would free it again when it is dropped.
+    (*output.as_mut_ptr()).pb = ptr::null_mut();
+}
+
+/// Called by the IO context for every packet the output writes, see
+/// [`output_packetized_buf_start`].
+unsafe extern "C" fn push_packet(
+    opaque: *mut c_void,
+    buffer: *mut u8,
+    buffer_size: c_int,
+) -> c_int {
+    let buffers = &mut *(opaque as *mut Vec<Buf>);
+    buffers.push(slice::from_raw_parts(buffer, buffer_size as usize).to_vec());
+    buffer_size
+}
+
+fn flush_output(output: &mut AvOutput) -> std::result::Result<(), AvError> {
+    // SAFETY: Writing a null packet only flushes the output.
+    match unsafe { ffi::av_write_frame(output.as_mut_ptr(), ptr::null_mut()) } {
+        0 | 1 => Ok(()),
+        ret => Err(AvError::from(ret)),
+    }
+}
+
 pub async fn make_rtp_muxer() -> Result<RtpMuxer> {
     task::spawn_blocking(RtpMuxer::new).await.unwrap()
 }
 
-pub async fn muxed(
-    mut rtp_muxer: RtpMuxer,
-    packet: video::Packet,
-) -> (RtpMuxer, Result<Vec<video::RtpBuf>>) {
+pub async fn muxed(mut rtp_muxer: RtpMuxer, packet: Packet) -> (RtpMuxer, Result<Vec<RtpBuf>>) {
     task::spawn_blocking(move || {
         let out = rtp_muxer.mux(packet);
         (rtp_muxer, out)
@@ -22,7 +262,7 @@ pub async fn muxed(
     .unwrap()
 }
 
-pub async fn finish(mut rtp_muxer: RtpMuxer) -> Result<Option<Vec<video::RtpBuf>>> {
+pub async fn finish(mut rtp_muxer: RtpMuxer) -> Result<Option<Vec<RtpBuf>>> {
     task::spawn_blocking(move || rtp_muxer.finish())
         .await
         .unwrap()
@@ -0,0 +1,51 @@
+//! Streams of a reader.
+//!
+//! [`video_rs::StreamInfo`] keeps the codec parameters of a stream to itself,
+//! so it can only be muxed by [`video_rs::RtpMuxer`]. This one can be muxed by
+//! [`crate::media::video::rtp_muxer::RtpMuxer`].
+
+use ffmpeg_next::codec::Parameters as AvCodecParameters;
+use ffmpeg_next::media::Type as AvMediaType;
+use ffmpeg_next::{Error as AvError, Rational as AvRational};
+
+use video_rs::{self as video, Reader};
+
+type Result<T> = std::result::Result<T, video::Error>;
+
+/// Information about a stream of a reader, which is all that is needed to
+/// mux its packets.
+#[derive(Clone)]
+pub struct StreamInfo {
+    pub index: usize,
+    codec_parameters: AvCodecParameters,
+    time_base: AvRational,
+}
+
+impl StreamInfo {
+    pub fn from_reader(reader: &Reader, stream_index: usize) -> Result<Self> {
+        let stream = reader
+            .input
+            .stream(stream_index)
+            .ok_or(AvError::StreamNotFound)?;
+        Ok(Self {
+            index: stream_index,
+            // The parameters are copied, so that they are no longer tied to
+            // the reader.
+            codec_parameters: stream.parameters().clone(),
+            time_base: stream.time_base(),
+        })
+    }
+
+    pub(crate) fn into_parts(self) -> (usize, AvCodecParameters, AvRational) {
+        (self.index, self.codec_parameters, self.time_base)
+    }
+}
+
+/// Index of the best audio stream of the reader, if it has one.
+pub fn best_audio_stream_index(reader: &Reader) -> Option<usize> {
+    reader
+        .input
+        .streams()
+        .best(AvMediaType::Audio)
+        .map(|stream| stream.index())
+}
@@ -0,0 +1,92 @@
+//! Counters for things that happen throughout the server. They are kept in
+//! a single global registry, since they are updated from deep inside the
+//! workers, and exported by [`crate::app::metrics`].
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+static METRICS: Metrics = Metrics::new();
+
+pub fn global() -> &'static Metrics {
+    &METRICS
+}
+
+pub struct Metrics {
+    pub connections_accepted: Counter,
+    pub connections_closed: Counter,
+    /// Media dropped because the send queue of its connection was full.
+    pub send_queue_dropped: Counter,
+    /// Connections closed because the send queue was full.
+    pub slow_client_disconnects: Counter,
+    paths: Mutex<BTreeMap<String, Arc<PathMetrics>>>,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            connections_accepted: Counter::new(),
+            connections_closed: Counter::new(),
+            send_queue_dropped: Counter::new(),
+            slow_client_disconnects: Counter::new(),
+            paths: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Counters of a single path. They are created on first use and kept
+    /// around after the source goes away, so that they never go backwards
+    /// while the server is running.
+    pub fn path(&self, path: &str) -> Arc<PathMetrics> {
+        self.paths
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(path.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Counters of every path that has been used so far, ordered by path.
+    pub fn paths(&self) -> Vec<(String, Arc<PathMetrics>)> {
+        self.paths
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .map(|(path, metrics)| (path.clone(), metrics.clone()))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct PathMetrics {
+    /// Number of times the source recovered after its stream broke.
+    pub source_restarts: Counter,
+    /// Number of times the source was restarted because it stalled.
+    pub source_stalls: Counter,
+    /// RTP packets and bytes sent to clients, over all sessions.
+    pub packets_sent: Counter,
+    pub bytes_sent: Counter,
+    /// Number of times a session fell behind on the packets of the source.
+    pub lagged: Counter,
+    pub mux_failures: Counter,
+}
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
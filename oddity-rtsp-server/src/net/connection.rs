@@ -1,12 +1,19 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashSet;
 use std::fmt;
 use std::io::ErrorKind;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::SinkExt;
 
+use tokio::io::{self, AsyncRead, AsyncWrite};
 use tokio::net;
 use tokio::select;
 use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
 use tokio_stream::StreamExt;
 use tokio_util::codec;
 
@@ -15,8 +22,11 @@ use oddity_rtsp_protocol::{
 };
 
 use crate::net::handler::Handler;
+use crate::net::send_queue::{self, ResponseSenderRx, ResponseSenderTx, SendQueueConfig};
+use crate::net::tunnel::{self, Incoming, TunnelMap};
 use crate::runtime::task_manager::{Task, TaskContext};
 use crate::runtime::Runtime;
+use crate::session::SessionId;
 
 pub enum ConnectionState {
     Disconnected(ConnectionId),
@@ -26,22 +36,56 @@ pub enum ConnectionState {
 pub type ConnectionStateTx = mpsc::UnboundedSender<ConnectionState>;
 pub type ConnectionStateRx = mpsc::UnboundedReceiver<ConnectionState>;
 
-pub type ResponseSenderTx = mpsc::UnboundedSender<ResponseMaybeInterleaved>;
-pub type ResponseSenderRx = mpsc::UnboundedReceiver<ResponseMaybeInterleaved>;
+/// Addresses of both ends of a connection. The handler uses these to
+/// figure out where to send media when the client asked for RTP over
+/// UDP, and on which interface to bind the server side sockets.
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    pub peer_addr: SocketAddr,
+    pub local_addr: SocketAddr,
+    /// Whether or not the connection is over TLS.
+    pub secure: bool,
+}
+
+/// Underlying stream of a connection.
+pub enum ConnectionStream {
+    Plain(net::TcpStream),
+    /// The TLS handshake is done by the connection worker, so that a slow
+    /// client does not hold up the server accepting other connections.
+    Tls(net::TcpStream, TlsAcceptor),
+}
+
+impl ConnectionStream {
+    fn tcp_stream(&self) -> &net::TcpStream {
+        match self {
+            ConnectionStream::Plain(inner) => inner,
+            ConnectionStream::Tls(inner, _) => inner,
+        }
+    }
+}
+
+type ConnectionRead = Box<dyn AsyncRead + Send + Unpin>;
+type ConnectionWrite = Box<dyn AsyncWrite + Send + Unpin>;
 
 pub struct Connection {
     worker: Task,
 }
 
 impl Connection {
+    /// Clients that do not finish the TLS handshake within this time are
+    /// disconnected.
+    const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
     pub async fn start(
         id: ConnectionId,
-        inner: net::TcpStream,
+        inner: ConnectionStream,
         handler: Arc<Handler>,
+        tunnels: TunnelMap,
+        send_queue: SendQueueConfig,
         state_tx: ConnectionStateTx,
         runtime: &Runtime,
     ) -> Self {
-        let (sender_tx, sender_rx) = mpsc::unbounded_channel();
+        let (sender_tx, sender_rx) = send_queue::channel(id, send_queue);
 
         tracing::trace!(%id, "starting connection");
         let worker = runtime
@@ -51,6 +95,7 @@ impl Connection {
                     id,
                     inner,
                     handler,
+                    tunnels,
                     state_tx,
                     sender_tx,
                     sender_rx,
@@ -69,28 +114,159 @@ impl Connection {
         tracing::trace!("closed connection");
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn run(
         id: ConnectionId,
-        inner: net::TcpStream,
+        inner: ConnectionStream,
         handler: Arc<Handler>,
+        tunnels: TunnelMap,
         state_tx: ConnectionStateTx,
         response_tx: ResponseSenderTx,
         mut response_rx: ResponseSenderRx,
         mut task_context: TaskContext,
     ) {
         let mut disconnected = false;
+        // Sessions that were set up over this connection.
+        let mut sessions = HashSet::new();
+
+        let info = match (
+            inner.tcp_stream().peer_addr(),
+            inner.tcp_stream().local_addr(),
+        ) {
+            (Ok(peer_addr), Ok(local_addr)) => ConnectionInfo {
+                peer_addr,
+                local_addr,
+                secure: matches!(inner, ConnectionStream::Tls(..)),
+            },
+            (Err(err), _) | (_, Err(err)) => {
+                tracing::error!(%err, %id, "connection: failed to query socket addresses");
+                let _ = state_tx.send(ConnectionState::Closed(id));
+                return;
+            }
+        };
+        let addr = info.peer_addr;
+        let (read, mut write): (ConnectionRead, ConnectionWrite) = match inner {
+            ConnectionStream::Plain(inner) => {
+                let (read, write) = inner.into_split();
+                (Box::new(read), Box::new(write))
+            }
+            ConnectionStream::Tls(inner, acceptor) => {
+                select! {
+                  // CANCEL SAFETY: We do not care about the handshake if we are stopping.
+                  handshake = timeout(Self::TLS_HANDSHAKE_TIMEOUT, acceptor.accept(inner)) => {
+                    match handshake {
+                      Ok(Ok(inner)) => {
+                        tracing::trace!(%id, %addr, "connection: finished tls handshake");
+                        let (read, write) = io::split(inner);
+                        (Box::new(read), Box::new(write))
+                      },
+                      Ok(Err(err)) => {
+                        tracing::info!(%err, %id, %addr, "connection: tls handshake failed");
+                        let _ = state_tx.send(ConnectionState::Closed(id));
+                        return;
+                      },
+                      Err(_) => {
+                        tracing::info!(%id, %addr, "connection: tls handshake timed out");
+                        let _ = state_tx.send(ConnectionState::Closed(id));
+                        return;
+                      },
+                    }
+                  },
+                  // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+                  _ = task_context.wait_for_stop() => {
+                    tracing::trace!(%id, %addr, "connection worker stopping");
+                    let _ = state_tx.send(ConnectionState::Closed(id));
+                    return;
+                  },
+                }
+            }
+        };
+
+        let incoming = select! {
+          // CANCEL SAFETY: We do not care about the first request if we are stopping.
+          incoming = tunnel::accept(read) => incoming,
+          // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+          _ = task_context.wait_for_stop() => {
+            tracing::trace!(%id, %addr, "connection worker stopping");
+            let _ = state_tx.send(ConnectionState::Closed(id));
+            return;
+          },
+        };
+        // Session cookie of the tunnel this connection sends responses for, if any.
+        let mut tunnel_cookie = None;
+        let read = match incoming {
+            Ok(Incoming::Rtsp(read)) => read,
+            Ok(Incoming::Get { cookie }) => {
+                let (tunnel_tx, tunnel_rx) = mpsc::unbounded_channel();
+                let registered = match tunnels.lock().await.entry(cookie.clone()) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(tunnel_tx);
+                        true
+                    }
+                    Entry::Occupied(_) => false,
+                };
+                if !registered {
+                    tracing::info!(%id, %addr, %cookie, "connection: tunnel cookie already in use");
+                    let _ = tunnel::reply_error(&mut write, "409 Conflict").await;
+                    let _ = state_tx.send(ConnectionState::Closed(id));
+                    return;
+                }
+
+                tracing::trace!(%id, %addr, %cookie, "connection: opened tunnel");
+                if let Err(err) = tunnel::reply_to_get(&mut write).await {
+                    tracing::info!(%err, %id, %addr, "connection: failed to open tunnel");
+                    tunnels.lock().await.remove(&cookie);
+                    let _ = state_tx.send(ConnectionState::Closed(id));
+                    return;
+                }
+                tunnel_cookie = Some(cookie);
+                tunnel::reader(tunnel_rx)
+            }
+            Ok(Incoming::Post { cookie, read }) => {
+                let tunnel_tx = tunnels.lock().await.get(&cookie).cloned();
+                match tunnel_tx {
+                    Some(tunnel_tx) => {
+                        tracing::trace!(%id, %addr, %cookie, "connection: receiving for tunnel");
+                        select! {
+                          // CANCEL SAFETY: Requests that were not forwarded yet are lost anyway.
+                          result = tunnel::forward(read, tunnel_tx) => {
+                            if let Err(err) = result {
+                              tracing::info!(%err, %id, %addr, "connection: failed to receive for tunnel");
+                            }
+                          },
+                          // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+                          _ = task_context.wait_for_stop() => {
+                            tracing::trace!(%id, %addr, "connection worker stopping");
+                          },
+                        }
+                    }
+                    None => {
+                        tracing::info!(%id, %addr, %cookie, "connection: tunnel not found");
+                        let _ = tunnel::reply_error(&mut write, "404 Not Found").await;
+                    }
+                }
+                let _ = state_tx.send(ConnectionState::Disconnected(id));
+                return;
+            }
+            Ok(Incoming::Invalid) => {
+                tracing::info!(%id, %addr, "connection: invalid http request");
+                let _ = tunnel::reply_error(&mut write, "400 Bad Request").await;
+                let _ = state_tx.send(ConnectionState::Closed(id));
+                return;
+            }
+            Err(err) => {
+                tracing::info!(%err, %id, %addr, "connection: failed to read first request");
+                let _ = state_tx.send(ConnectionState::Closed(id));
+                return;
+            }
+        };
 
-        let addr = inner
-            .peer_addr()
-            .map(|peer_addr| peer_addr.to_string())
-            .unwrap_or("?".to_string());
-        let (read, write) = inner.into_split();
         let mut inbound = codec::FramedRead::new(read, Codec::<AsServer>::new());
         let mut outbound = codec::FramedWrite::new(write, Codec::<AsServer>::new());
 
         loop {
             select! {
-              // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
+              // CANCEL SAFETY: `ResponseSenderRx::recv` is cancel safe.
               message = response_rx.recv() => {
                 match message {
                   Some(message) => {
@@ -108,6 +284,7 @@ impl Connection {
                     }
                   },
                   None => {
+                    tracing::info!(%id, %addr, "connection: closing connection of slow client");
                     break;
                   },
                 }
@@ -118,7 +295,55 @@ impl Connection {
                   Some(Ok(request)) => {
                     match request {
                       RequestMaybeInterleaved::Message(request) => {
-                        let response = handler.handle(&request, &response_tx).await;
+                        // Handling a request can mean waiting for a session, which in turn
+                        // may be waiting for room in the send queue. So the queue is kept
+                        // draining in the meantime.
+                        let mut draining = true;
+                        let mut broken = None;
+                        let handle = handler.handle(&request, &info, &response_tx);
+                        tokio::pin!(handle);
+                        let response = loop {
+                          select! {
+                            // CANCEL SAFETY: The future is pinned outside of the loop, so it
+                            // is picked up again in the next iteration.
+                            response = &mut handle => break response,
+                            // CANCEL SAFETY: `ResponseSenderRx::recv` is cancel safe.
+                            message = response_rx.recv(), if draining => {
+                              match message {
+                                Some(message) => {
+                                  if let Err(err) = outbound.send(message).await {
+                                    draining = false;
+                                    broken = Some(err);
+                                  }
+                                },
+                                None => {
+                                  draining = false;
+                                },
+                              }
+                            },
+                          }
+                        };
+                        // The request is handled all the way even if the connection broke
+                        // in the meantime, so that it does not end up halfway done.
+                        match broken {
+                          Some(Error::Io(err)) if err.kind() == ErrorKind::ConnectionReset => {
+                            disconnected = true;
+                            tracing::info!(%id, %addr, "connection: client disconnected (reset)");
+                            break;
+                          },
+                          Some(err) => {
+                            tracing::error!(%err, %id, %addr, "connection: failed to send message");
+                            break;
+                          },
+                          None if !draining => {
+                            tracing::info!(%id, %addr, "connection: closing connection of slow client");
+                            break;
+                          },
+                          None => {},
+                        }
+                        if let Some(session_id) = response.session() {
+                          sessions.insert(SessionId::from(session_id));
+                        }
                         let response = ResponseMaybeInterleaved::Message(response);
                         match outbound.send(response).await {
                           Ok(()) => {},
@@ -133,8 +358,8 @@ impl Connection {
                           },
                         }
                       },
-                      RequestMaybeInterleaved::Interleaved { channel, .. } => {
-                        tracing::debug!(%id, %addr, %channel, "ignored request with interleaved data");
+                      RequestMaybeInterleaved::Interleaved { channel, payload } => {
+                        handler.handle_interleaved(channel, &payload, &sessions).await;
                       },
                     }
                   },
@@ -162,6 +387,11 @@ impl Connection {
             };
         }
 
+        handler.handle_disconnect(&sessions).await;
+        if let Some(cookie) = tunnel_cookie {
+            tunnels.lock().await.remove(&cookie);
+        }
+
         if disconnected {
             // Client disconnected.
             let _ = state_tx.send(ConnectionState::Disconnected(id));
@@ -1,16 +1,18 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use tokio::net;
 use tokio::select;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
 
+use crate::metrics;
 use crate::net::connection::{
     Connection, ConnectionId, ConnectionIdGenerator, ConnectionState, ConnectionStateRx,
-    ConnectionStateTx,
+    ConnectionStateTx, ConnectionStream,
 };
 use crate::net::handler::Handler;
+use crate::net::send_queue::SendQueueConfig;
+use crate::net::tunnel::TunnelMap;
 use crate::runtime::task_manager::{Task, TaskContext};
 use crate::runtime::Runtime;
 
@@ -21,12 +23,19 @@ pub struct ConnectionManager {
     connection_id_generator: ConnectionIdGenerator,
     connection_state_tx: ConnectionStateTx,
     handler: Arc<Handler>,
+    /// Pairs the two connections that make up an RTSP over HTTP tunnel.
+    tunnels: TunnelMap,
+    send_queue: SendQueueConfig,
     worker: Task,
     runtime: Arc<Runtime>,
 }
 
 impl ConnectionManager {
-    pub async fn start(handler: Handler, runtime: Arc<Runtime>) -> Self {
+    pub async fn start(
+        handler: Handler,
+        send_queue: SendQueueConfig,
+        runtime: Arc<Runtime>,
+    ) -> Self {
         let connections = Arc::new(Mutex::new(HashMap::new()));
 
         let (connection_state_tx, connection_state_rx) = mpsc::unbounded_channel();
@@ -46,6 +55,8 @@ impl ConnectionManager {
             connection_id_generator: ConnectionIdGenerator::new(),
             connection_state_tx,
             handler: Arc::new(handler),
+            tunnels: Arc::new(Mutex::new(HashMap::new())),
+            send_queue,
             worker,
             runtime,
         }
@@ -60,12 +71,15 @@ impl ConnectionManager {
         }
     }
 
-    pub async fn spawn(&mut self, stream: net::TcpStream) {
+    pub async fn spawn(&mut self, stream: ConnectionStream) {
         let id = self.connection_id_generator.generate();
+        metrics::global().connections_accepted.inc();
         let connection = Connection::start(
             id,
             stream,
             self.handler.clone(),
+            self.tunnels.clone(),
+            self.send_queue,
             self.connection_state_tx.clone(),
             self.runtime.as_ref(),
         )
@@ -90,6 +104,7 @@ impl ConnectionManager {
                       "connection manager: received disconnected",
                     );
                     connections.lock().await.remove(&connection_id);
+                    metrics::global().connections_closed.inc();
                   },
                   Some(ConnectionState::Closed(connection_id)) => {
                     tracing::trace!(
@@ -97,6 +112,7 @@ impl ConnectionManager {
                       "connection manager: received closed",
                     );
                     connections.lock().await.remove(&connection_id);
+                    metrics::global().connections_closed.inc();
                   },
                   None => {
                     tracing::error!("connection state channel broke unexpectedly");
@@ -1,4 +1,8 @@
 pub mod connection;
 pub mod connection_manager;
 pub mod handler;
+pub mod send_queue;
 pub mod server;
+pub mod tls;
+pub mod tunnel;
+pub mod udp;
@@ -0,0 +1,354 @@
+//! Queue for the media a connection sends to its client, interleaved with
+//! the responses. The queue holds a limited number of bytes, so that a client
+//! that cannot keep up does not make the server buffer its media without
+//! limit. What happens when the queue is full depends on the
+//! [`SlowClientPolicy`].
+
+use std::collections::{HashSet, VecDeque};
+use std::error;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::Deserialize;
+
+use tokio::sync::Notify;
+use tokio::time::{timeout_at, Duration, Instant};
+
+use oddity_rtsp_protocol::ResponseMaybeInterleaved;
+
+use crate::metrics;
+use crate::net::connection::ConnectionId;
+
+/// What to do with media for a client whose send queue is full.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowClientPolicy {
+    /// Drop media until the client can be sent the start of a keyframe
+    /// again, so that it can continue decoding from there.
+    #[default]
+    DropUntilKeyframe,
+    /// Close the connection, which ends the sessions set up over it.
+    Disconnect,
+    /// Wait for the client. A session that falls too far behind its source
+    /// ends, and a client that does not make room in time is disconnected.
+    Block,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SendQueueConfig {
+    /// Number of bytes of media that can be queued for a single connection.
+    pub size: usize,
+    pub policy: SlowClientPolicy,
+}
+
+/// Create the send queue of a connection. The connection worker holds on to
+/// the receiving end, the sending ends go to its sessions.
+pub fn channel(id: ConnectionId, config: SendQueueConfig) -> (ResponseSenderTx, ResponseSenderRx) {
+    let queue = Arc::new(SendQueue {
+        id,
+        config,
+        state: Mutex::new(SendQueueState {
+            messages: VecDeque::new(),
+            size: 0,
+            dropping: HashSet::new(),
+            closed: false,
+        }),
+        ready: Notify::new(),
+        space: Notify::new(),
+    });
+    (ResponseSenderTx(queue.clone()), ResponseSenderRx(queue))
+}
+
+struct SendQueue {
+    id: ConnectionId,
+    config: SendQueueConfig,
+    state: Mutex<SendQueueState>,
+    /// Notifies the connection worker that there is something to send.
+    ready: Notify,
+    /// Notifies blocked senders that there is space in the queue again.
+    space: Notify,
+}
+
+impl SendQueue {
+    /// Longest time a sender waits for room under [`SlowClientPolicy::Block`]
+    /// before the client is disconnected. Nothing else guarantees that a
+    /// blocked sender, and whoever waits for it, ever gets going again.
+    const MAX_BLOCK: Duration = Duration::from_secs(10);
+
+    fn lock(&self) -> MutexGuard<'_, SendQueueState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Disconnect a client that cannot keep up.
+    fn disconnect(&self, mut state: MutexGuard<'_, SendQueueState>) {
+        tracing::warn!(id = %self.id, "connection: client cannot keep up, disconnecting");
+        metrics::global().slow_client_disconnects.inc();
+        state.closed = true;
+        drop(state);
+        // Wakes up the connection worker, which finds the queue closed and
+        // closes the connection, and any other blocked senders.
+        self.ready.notify_one();
+        self.space.notify_waiters();
+    }
+}
+
+struct SendQueueState {
+    messages: VecDeque<ResponseMaybeInterleaved>,
+    /// Number of bytes of all queued messages.
+    size: usize,
+    /// Channels on which media is dropped until the next keyframe.
+    dropping: HashSet<u8>,
+    /// The connection is gone, or the client was disconnected for being too
+    /// slow.
+    closed: bool,
+}
+
+impl SendQueueState {
+    /// A message that is larger than the whole queue still goes through if
+    /// the queue is empty, otherwise it could never be sent.
+    fn has_space_for(&self, len: usize, config: &SendQueueConfig) -> bool {
+        self.size == 0 || self.size + len <= config.size
+    }
+
+    fn push(&mut self, channel: u8, payload: Vec<u8>) {
+        self.size += payload.len();
+        self.messages
+            .push_back(ResponseMaybeInterleaved::Interleaved {
+                channel,
+                payload: payload.into(),
+            });
+    }
+}
+
+#[derive(Clone)]
+pub struct ResponseSenderTx(Arc<SendQueue>);
+
+impl ResponseSenderTx {
+    /// Queue interleaved data for the client. `sync_point` tells whether the
+    /// client can pick up the stream on the channel again from this data on,
+    /// like at the start of a keyframe or for RTCP.
+    ///
+    /// Fails if the connection is gone, or if the client was disconnected
+    /// because it could not keep up.
+    pub async fn send(
+        &self,
+        channel: u8,
+        payload: Vec<u8>,
+        sync_point: bool,
+    ) -> Result<(), SendError> {
+        let queue = &self.0;
+        match queue.config.policy {
+            SlowClientPolicy::DropUntilKeyframe => {
+                let mut state = queue.lock();
+                if state.closed {
+                    return Err(SendError::Closed);
+                }
+                if state.dropping.contains(&channel) && !sync_point {
+                    metrics::global().send_queue_dropped.inc();
+                    return Ok(());
+                }
+                if !state.has_space_for(payload.len(), &queue.config) {
+                    metrics::global().send_queue_dropped.inc();
+                    if state.dropping.insert(channel) {
+                        tracing::warn!(
+                            id = %queue.id,
+                            channel,
+                            "connection: client cannot keep up, dropping media until next keyframe",
+                        );
+                    }
+                    return Ok(());
+                }
+                if state.dropping.remove(&channel) {
+                    tracing::info!(id = %queue.id, channel, "connection: client caught up");
+                }
+                state.push(channel, payload);
+            }
+            SlowClientPolicy::Disconnect => {
+                let mut state = queue.lock();
+                if state.closed {
+                    return Err(SendError::Closed);
+                }
+                if !state.has_space_for(payload.len(), &queue.config) {
+                    queue.disconnect(state);
+                    return Err(SendError::Closed);
+                }
+                state.push(channel, payload);
+            }
+            SlowClientPolicy::Block => {
+                let deadline = Instant::now() + SendQueue::MAX_BLOCK;
+                loop {
+                    // The notification has to be created before checking for
+                    // space, or we might miss it.
+                    let space = queue.space.notified();
+                    {
+                        let mut state = queue.lock();
+                        if state.closed {
+                            return Err(SendError::Closed);
+                        }
+                        if state.has_space_for(payload.len(), &queue.config) {
+                            state.push(channel, payload);
+                            break;
+                        }
+                    }
+                    if timeout_at(deadline, space).await.is_err() {
+                        let state = queue.lock();
+                        if !state.closed {
+                            queue.disconnect(state);
+                        }
+                        return Err(SendError::Closed);
+                    }
+                }
+            }
+        }
+        queue.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ResponseSenderTx {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResponseSenderTx")
+            .field("id", &self.0.id.to_string())
+            .field("config", &self.0.config)
+            .finish()
+    }
+}
+
+pub struct ResponseSenderRx(Arc<SendQueue>);
+
+impl ResponseSenderRx {
+    /// Wait for the next message to send to the client. Returns `None` once
+    /// the client was disconnected for being too slow.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancel safe.
+    pub async fn recv(&mut self) -> Option<ResponseMaybeInterleaved> {
+        let queue = &self.0;
+        loop {
+            {
+                let mut state = queue.lock();
+                if state.closed {
+                    return None;
+                }
+                if let Some(message) = state.messages.pop_front() {
+                    if let ResponseMaybeInterleaved::Interleaved { payload, .. } = &message {
+                        state.size -= payload.len();
+                    }
+                    drop(state);
+                    queue.space.notify_waiters();
+                    return Some(message);
+                }
+            }
+            // There is only ever one receiver, so `notify_one` stores a permit
+            // for us if nobody is waiting yet.
+            queue.ready.notified().await;
+        }
+    }
+}
+
+impl Drop for ResponseSenderRx {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        state.closed = true;
+        state.messages.clear();
+        state.size = 0;
+        drop(state);
+        self.0.space.notify_waiters();
+    }
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    Closed,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendError::Closed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl error::Error for SendError {}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use crate::net::connection::ConnectionId;
+
+    use super::{channel, SendError, SendQueue, SendQueueConfig, SlowClientPolicy};
+
+    const BLOCK: SendQueueConfig = SendQueueConfig {
+        size: 4,
+        policy: SlowClientPolicy::Block,
+    };
+
+    #[tokio::test]
+    async fn block_continues_once_drained() {
+        let (tx, mut rx) = channel(ConnectionId::new(0), BLOCK);
+        tx.send(0, vec![0; 4], true).await.unwrap();
+
+        let blocked = tokio::spawn({
+            let tx = tx.clone();
+            async move { tx.send(0, vec![1; 4], true).await }
+        });
+        assert!(rx.recv().await.is_some());
+        assert!(timeout(Duration::from_secs(1), blocked)
+            .await
+            .unwrap()
+            .unwrap()
+            .is_ok());
+        assert!(rx.recv().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn block_fails_on_teardown() {
+        let (tx, rx) = channel(ConnectionId::new(0), BLOCK);
+        tx.send(0, vec![0; 4], true).await.unwrap();
+
+        let blocked = tokio::spawn({
+            let tx = tx.clone();
+            async move { tx.send(0, vec![1; 4], true).await }
+        });
+        tokio::task::yield_now().await;
+        drop(rx);
+        assert!(matches!(
+            timeout(Duration::from_secs(1), blocked)
+                .await
+                .unwrap()
+                .unwrap(),
+            Err(SendError::Closed),
+        ));
+        assert!(matches!(
+            tx.send(0, vec![2; 1], true).await,
+            Err(SendError::Closed),
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn block_disconnects_client_that_does_not_make_room() {
+        let (tx, mut rx) = channel(ConnectionId::new(0), BLOCK);
+        tx.send(0, vec![0; 4], true).await.unwrap();
+
+        let blocked = tokio::spawn({
+            let tx = tx.clone();
+            async move { tx.send(0, vec![1; 4], true).await }
+        });
+        assert!(matches!(
+            timeout(SendQueue::MAX_BLOCK * 2, blocked)
+                .await
+                .unwrap()
+                .unwrap(),
+            Err(SendError::Closed),
+        ));
+        assert!(rx.recv().await.is_none());
+    }
+}
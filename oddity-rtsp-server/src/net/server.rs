@@ -1,16 +1,27 @@
 use std::net::IpAddr;
 use std::sync::Arc;
 
+use futures::future;
+
 use tokio::net;
 use tokio::select;
+use tokio_rustls::TlsAcceptor;
 
+use crate::net::connection::ConnectionStream;
 use crate::net::connection_manager::ConnectionManager;
 use crate::net::handler::Handler;
+use crate::net::send_queue::SendQueueConfig;
 use crate::runtime::task_manager::{Task, TaskContext};
 use crate::runtime::Runtime;
 
 type Result<T> = std::result::Result<T, std::io::Error>;
 
+/// Listener for RTSP over TLS (`rtsps://`), next to the plain listener.
+pub struct TlsListenerConfig {
+    pub port: u16,
+    pub acceptor: TlsAcceptor,
+}
+
 pub struct Server {
     worker: Task,
 }
@@ -19,24 +30,38 @@ impl Server {
     pub async fn start(
         host: IpAddr,
         port: u16,
+        tls: Option<TlsListenerConfig>,
+        send_queue: SendQueueConfig,
         handler: Handler,
         runtime: Arc<Runtime>,
     ) -> Result<Self> {
         tracing::trace!(%host, port, "starting server");
-        let listener = match net::TcpListener::bind((host, port)).await {
-            Ok(listener) => listener,
-            Err(err) => {
-                tracing::error!(%err, %host, port, "failed to listen for connections");
-                return Err(err);
+        let listener = Self::bind(host, port).await?;
+        tracing::info!(%host, port, "server listening for incoming connections");
+
+        let tls_listener = match tls {
+            Some(tls) => {
+                let listener = Self::bind(host, tls.port).await?;
+                tracing::info!(%host, port = tls.port, "server listening for incoming tls connections");
+                Some((listener, tls.acceptor))
             }
+            None => None,
         };
-        tracing::info!(%host, port, "server listening for incoming connections");
 
         let worker = runtime
             .task()
             .spawn({
                 let runtime = runtime.clone();
-                move |task_context| Self::run(listener, handler, runtime, task_context)
+                move |task_context| {
+                    Self::run(
+                        listener,
+                        tls_listener,
+                        send_queue,
+                        handler,
+                        runtime,
+                        task_context,
+                    )
+                }
             })
             .await;
         tracing::trace!(%host, port, "started server");
@@ -50,13 +75,25 @@ impl Server {
         tracing::trace!("server stopped");
     }
 
+    async fn bind(host: IpAddr, port: u16) -> Result<net::TcpListener> {
+        match net::TcpListener::bind((host, port)).await {
+            Ok(listener) => Ok(listener),
+            Err(err) => {
+                tracing::error!(%err, %host, port, "failed to listen for connections");
+                Err(err)
+            }
+        }
+    }
+
     async fn run(
         listener: net::TcpListener,
+        tls_listener: Option<(net::TcpListener, TlsAcceptor)>,
+        send_queue: SendQueueConfig,
         handler: Handler,
         runtime: Arc<Runtime>,
         mut task_context: TaskContext,
     ) {
-        let mut connection_manager = ConnectionManager::start(handler, runtime).await;
+        let mut connection_manager = ConnectionManager::start(handler, send_queue, runtime).await;
         loop {
             select! {
               // CANCEL SAFETY: `tokio::net::TcpListener::accept` is cancel safe.
@@ -64,13 +101,25 @@ impl Server {
                 match incoming {
                   Ok((incoming, peer_addr)) => {
                     tracing::trace!(%peer_addr, "accepted client");
-                    connection_manager.spawn(incoming).await;
+                    connection_manager.spawn(ConnectionStream::Plain(incoming)).await;
                   },
                   Err(err) => {
                     tracing::error!(%err, "failed to accept connection");
                   },
                 }
               },
+              // CANCEL SAFETY: `tokio::net::TcpListener::accept` is cancel safe.
+              incoming = Self::accept_tls(tls_listener.as_ref()) => {
+                match incoming {
+                  Ok((incoming, peer_addr, acceptor)) => {
+                    tracing::trace!(%peer_addr, "accepted tls client");
+                    connection_manager.spawn(ConnectionStream::Tls(incoming, acceptor)).await;
+                  },
+                  Err(err) => {
+                    tracing::error!(%err, "failed to accept tls connection");
+                  },
+                }
+              },
               // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
               _ = task_context.wait_for_stop() => {
                 tracing::trace!("server stopping");
@@ -81,4 +130,18 @@ impl Server {
 
         connection_manager.stop().await;
     }
+
+    /// Accept a connection on the TLS listener. If there is no TLS listener,
+    /// this never completes.
+    async fn accept_tls(
+        tls_listener: Option<&(net::TcpListener, TlsAcceptor)>,
+    ) -> Result<(net::TcpStream, std::net::SocketAddr, TlsAcceptor)> {
+        match tls_listener {
+            Some((listener, acceptor)) => {
+                let (incoming, peer_addr) = listener.accept().await?;
+                Ok((incoming, peer_addr, acceptor.clone()))
+            }
+            None => future::pending().await,
+        }
+    }
 }
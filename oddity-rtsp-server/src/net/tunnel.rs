@@ -0,0 +1,319 @@
+//! RTSP over HTTP tunneling, as introduced by QuickTime. The client opens
+//! two HTTP connections that share a session cookie: a `GET` on which the
+//! server sends responses and media, and a `POST` on which the client sends
+//! its requests, base64 encoded.
+//!
+//! The `GET` connection is served like any other connection, except that
+//! its requests come from the `POST` connection instead of its own socket.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use tokio::io::{self, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
+use tokio_util::io::StreamReader;
+
+pub type TunnelTx = mpsc::UnboundedSender<Cursor<Vec<u8>>>;
+pub type TunnelRx = mpsc::UnboundedReceiver<Cursor<Vec<u8>>>;
+
+/// Tunnels that are waiting for, or receiving, requests from their `POST`
+/// connection, by session cookie.
+pub type TunnelMap = Arc<Mutex<HashMap<String, TunnelTx>>>;
+
+type Read = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Largest HTTP request head we accept. Tunneling requests only carry a few
+/// small headers.
+const MAX_HEAD_SIZE: u64 = 8192;
+
+const CONTENT_TYPE_TUNNELLED: &str = "application/x-rtsp-tunnelled";
+
+/// What the client opened the connection for.
+pub enum Incoming {
+    /// Regular RTSP connection.
+    Rtsp(Read),
+    /// The client wants to receive responses on this connection.
+    Get { cookie: String },
+    /// The client will send requests for the tunnel over this connection.
+    Post { cookie: String, read: Read },
+    /// The client sent an HTTP request that is not part of a tunnel.
+    Invalid,
+}
+
+/// Figure out whether the connection is a regular RTSP connection or one half
+/// of a tunnel by looking at the first line the client sends. In case of a
+/// regular connection, the returned reader still produces that line.
+pub async fn accept(read: Read) -> io::Result<Incoming> {
+    let mut read = io::BufReader::new(read);
+    let mut request_line = Vec::new();
+    (&mut read)
+        .take(MAX_HEAD_SIZE)
+        .read_until(b'\n', &mut request_line)
+        .await?;
+
+    // RTSP request lines end in `RTSP/1.0`, and HTTP request lines in
+    // `HTTP/1.0` or `HTTP/1.1`. Note that `GET_PARAMETER` starts with `GET` as
+    // well, so the method alone is not enough.
+    let method = match std::str::from_utf8(&request_line)
+        .ok()
+        .map(str::trim_end)
+        .filter(|line| line.ends_with("HTTP/1.0") || line.ends_with("HTTP/1.1"))
+        .and_then(|line| line.split(' ').next())
+    {
+        Some(method) => method.to_string(),
+        None => {
+            let read: Read = Box::new(Cursor::new(request_line).chain(read));
+            return Ok(Incoming::Rtsp(read));
+        }
+    };
+
+    let headers = read_headers(&mut read).await?;
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(var, _)| var.eq_ignore_ascii_case(name))
+            .map(|(_, val)| val.as_str())
+    };
+
+    let cookie = match header("x-sessioncookie") {
+        Some(cookie) if !cookie.is_empty() => cookie.to_string(),
+        _ => return Ok(Incoming::Invalid),
+    };
+
+    match method.as_str() {
+        "GET" if header("Accept") == Some(CONTENT_TYPE_TUNNELLED) => Ok(Incoming::Get { cookie }),
+        "POST" => Ok(Incoming::Post {
+            cookie,
+            read: Box::new(read),
+        }),
+        _ => Ok(Incoming::Invalid),
+    }
+}
+
+async fn read_headers(read: &mut io::BufReader<Read>) -> io::Result<Vec<(String, String)>> {
+    let mut head = String::new();
+    let mut limited = read.take(MAX_HEAD_SIZE);
+    loop {
+        let len = limited.read_line(&mut head).await?;
+        if len == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if head.ends_with("\r\n\r\n") || head.ends_with("\n\n") {
+            break;
+        }
+    }
+
+    Ok(head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(var, val)| (var.trim().to_string(), val.trim().to_string()))
+        .collect())
+}
+
+/// Tell the client that the `GET` connection is ready to receive responses.
+pub async fn reply_to_get(write: &mut (impl AsyncWrite + Unpin)) -> io::Result<()> {
+    write
+        .write_all(
+            format!(
+                "HTTP/1.0 200 OK\r\n\
+                 Content-Type: {}\r\n\
+                 Cache-Control: no-cache\r\n\
+                 Pragma: no-cache\r\n\
+                 Connection: close\r\n\
+                 \r\n",
+                CONTENT_TYPE_TUNNELLED,
+            )
+            .as_bytes(),
+        )
+        .await?;
+    write.flush().await
+}
+
+pub async fn reply_error(write: &mut (impl AsyncWrite + Unpin), status: &str) -> io::Result<()> {
+    write
+        .write_all(format!("HTTP/1.0 {}\r\nConnection: close\r\n\r\n", status).as_bytes())
+        .await?;
+    write.flush().await
+}
+
+/// Reader that produces the requests received by the `POST` connection.
+pub fn reader(tunnel_rx: TunnelRx) -> Read {
+    Box::new(StreamReader::new(
+        UnboundedReceiverStream::new(tunnel_rx).map(Ok::<_, io::Error>),
+    ))
+}
+
+/// Decode the requests the client sends on the `POST` connection and pass
+/// them on to the `GET` connection, until either one goes away.
+pub async fn forward(mut read: Read, tunnel_tx: TunnelTx) -> io::Result<()> {
+    let mut buf = vec![0; 4096];
+    let mut encoded = Vec::new();
+    loop {
+        let len = read.read(&mut buf).await?;
+        if len == 0 {
+            return Ok(());
+        }
+        encoded.extend(buf[..len].iter().filter(|b| !b.is_ascii_whitespace()));
+
+        // Every request is encoded separately, so padding can show up in the
+        // middle of the stream. We decode up to and including each group of
+        // four characters that is padded, and keep incomplete groups around
+        // until the rest arrives.
+        let mut decoded = Vec::new();
+        let mut start = 0;
+        for (index, group) in encoded.chunks_exact(4).enumerate() {
+            let end = (index + 1) * 4;
+            if group.contains(&b'=') || end + 4 > encoded.len() {
+                decoded.extend(
+                    base64::decode(&encoded[start..end])
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+                );
+                start = end;
+            }
+        }
+        encoded.drain(..start);
+
+        if !decoded.is_empty() && tunnel_tx.send(Cursor::new(decoded)).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Cursor;
+
+    use tokio::io::AsyncReadExt;
+    use tokio::sync::mpsc;
+    use tokio_util::io::StreamReader;
+
+    use super::{accept, forward, Incoming, Read};
+
+    /// Reader that produces the given chunks one read at a time, like a
+    /// socket that receives them in separate segments.
+    fn chunked(chunks: &[&[u8]]) -> Read {
+        let chunks = chunks
+            .iter()
+            .map(|chunk| Ok::<_, std::io::Error>(Cursor::new(chunk.to_vec())))
+            .collect::<Vec<_>>();
+        Box::new(StreamReader::new(tokio_stream::iter(chunks)))
+    }
+
+    async fn read_to_end(mut read: Read) -> Vec<u8> {
+        let mut buf = Vec::new();
+        read.read_to_end(&mut buf).await.unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn accept_rtsp() {
+        let request = b"OPTIONS rtsp://example.com/media RTSP/1.0\r\nCSeq: 1\r\n\r\n";
+        match accept(chunked(&[request])).await.unwrap() {
+            Incoming::Rtsp(read) => assert_eq!(read_to_end(read).await, request),
+            _ => panic!("expected RTSP connection"),
+        }
+    }
+
+    #[tokio::test]
+    async fn accept_rtsp_get_parameter() {
+        let request = b"GET_PARAMETER rtsp://example.com/media RTSP/1.0\r\nCSeq: 2\r\n\r\n";
+        match accept(chunked(&[request])).await.unwrap() {
+            Incoming::Rtsp(read) => assert_eq!(read_to_end(read).await, request),
+            _ => panic!("expected RTSP connection"),
+        }
+    }
+
+    #[tokio::test]
+    async fn accept_tunnel_get() {
+        let request = b"GET /media HTTP/1.0\r\n\
+            x-sessioncookie: abc123\r\n\
+            Accept: application/x-rtsp-tunnelled\r\n\
+            \r\n";
+        match accept(chunked(&[request])).await.unwrap() {
+            Incoming::Get { cookie } => assert_eq!(cookie, "abc123"),
+            _ => panic!("expected tunnel GET"),
+        }
+    }
+
+    #[tokio::test]
+    async fn accept_tunnel_post() {
+        let request = b"POST /media HTTP/1.1\r\n\
+            X-SessionCookie: abc123\r\n\
+            Content-Type: application/x-rtsp-tunnelled\r\n\
+            \r\n\
+            T1BUSU9OUw==";
+        match accept(chunked(&[request])).await.unwrap() {
+            Incoming::Post { cookie, read } => {
+                assert_eq!(cookie, "abc123");
+                assert_eq!(read_to_end(read).await, b"T1BUSU9OUw==");
+            }
+            _ => panic!("expected tunnel POST"),
+        }
+    }
+
+    #[tokio::test]
+    async fn accept_request_line_split_across_reads() {
+        let request: &[&[u8]] = &[
+            b"GET /media HT",
+            b"TP/1.0\r\nx-sessioncookie: abc\r\n",
+            b"\r\n",
+        ];
+        match accept(chunked(request)).await.unwrap() {
+            // Not a tunnel without the right `Accept` header.
+            Incoming::Invalid => {}
+            _ => panic!("expected invalid HTTP request"),
+        }
+    }
+
+    #[tokio::test]
+    async fn accept_http_without_cookie() {
+        let request = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        assert!(matches!(
+            accept(chunked(&[request])).await.unwrap(),
+            Incoming::Invalid
+        ));
+    }
+
+    async fn forwarded(chunks: &[&[u8]]) -> std::io::Result<Vec<u8>> {
+        let (tunnel_tx, mut tunnel_rx) = mpsc::unbounded_channel();
+        forward(chunked(chunks), tunnel_tx).await?;
+        let mut decoded = Vec::new();
+        while let Some(cursor) = tunnel_rx.recv().await {
+            decoded.extend(cursor.into_inner());
+        }
+        Ok(decoded)
+    }
+
+    #[tokio::test]
+    async fn forward_groups_split_across_reads() {
+        // "OPTIONS rtsp://example.com/media RTSP/1.0\r\nCSeq: 1\r\n\r\n"
+        let encoded = b"T1BUSU9OUyBydHNwOi8vZXhhbXBsZS5jb20vbWVkaWEgUlRTUC8xLjANCkNTZXE6IDENCg0K";
+        for split in 1..encoded.len() {
+            let (first, second) = encoded.split_at(split);
+            assert_eq!(
+                forwarded(&[first, second]).await.unwrap(),
+                b"OPTIONS rtsp://example.com/media RTSP/1.0\r\nCSeq: 1\r\n\r\n",
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn forward_padding_between_requests() {
+        // "a\r\n\r\n" and "bc\r\n\r\n", encoded separately, with a line break
+        // and the second padding in the middle of a read.
+        let chunks: &[&[u8]] = &[b"YQ0KDQo=\r\nYmMNCg", b"0K"];
+        assert_eq!(forwarded(chunks).await.unwrap(), b"a\r\n\r\nbc\r\n\r\n");
+        let chunks: &[&[u8]] = &[b"YQ0KDQ", b"o=YmMNCg0K"];
+        assert_eq!(forwarded(chunks).await.unwrap(), b"a\r\n\r\nbc\r\n\r\n");
+    }
+
+    #[tokio::test]
+    async fn forward_invalid_base64() {
+        let err = forwarded(&[b"!!!!"]).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::io;
+use std::net::{self, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use tokio::net::UdpSocket;
+
+/// Number of times we try to find a free even/odd port pair before
+/// giving up.
+const MAX_BIND_ATTEMPTS: usize = 16;
+
+/// Bind a pair of UDP sockets for RTP and RTCP on the given address.
+///
+/// RFC 3550 Section 11 recommends that RTP uses an even port number and
+/// that the RTCP port is the next higher (odd) port. We ask the OS for
+/// an ephemeral port and retry until we get an even port for which the
+/// next port is also available.
+pub async fn bind_rtp_and_rtcp(ip_addr: IpAddr) -> io::Result<(UdpSocket, UdpSocket)> {
+    for _ in 0..MAX_BIND_ATTEMPTS {
+        let rtp_socket = UdpSocket::bind((ip_addr, 0)).await?;
+        let rtp_port = rtp_socket.local_addr()?.port();
+        if rtp_port % 2 != 0 || rtp_port == u16::MAX {
+            continue;
+        }
+
+        if let Ok(rtcp_socket) = UdpSocket::bind((ip_addr, rtp_port + 1)).await {
+            tracing::trace!(%ip_addr, rtp_port, "bound rtp and rtcp sockets");
+            return Ok((rtp_socket, rtcp_socket));
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AddrNotAvailable,
+        "failed to find free port pair for rtp and rtcp",
+    ))
+}
+
+/// Ports that stay bound until whoever is going to use them is about to bind
+/// them itself. Clones refer to the same ports.
+#[derive(Clone)]
+pub struct Reservation {
+    sockets: Arc<Mutex<Vec<net::UdpSocket>>>,
+}
+
+impl Reservation {
+    pub fn new(sockets: Vec<net::UdpSocket>) -> Self {
+        Self {
+            sockets: Arc::new(Mutex::new(sockets)),
+        }
+    }
+
+    /// Unbind the reserved ports. Releasing them again does nothing.
+    pub fn release(&self) {
+        self.sockets.lock().unwrap().clear();
+    }
+}
+
+/// Multicast group that a source is streamed to. RTP is sent to `port`
+/// and RTCP to `port + 1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MulticastGroup {
+    pub addr: IpAddr,
+    pub port: u16,
+    pub ttl: u32,
+}
+
+impl MulticastGroup {
+    pub fn rtp_addr(&self) -> SocketAddr {
+        (self.addr, self.port).into()
+    }
+
+    pub fn rtcp_addr(&self) -> SocketAddr {
+        (self.addr, self.port + 1).into()
+    }
+
+    /// Every track is sent on its own pair of ports, after the ports of the
+    /// previous track.
+    pub fn for_track(&self, track: usize) -> Option<MulticastGroup> {
+        let offset = u16::try_from(track * 2).ok()?;
+        let port = self.port.checked_add(offset)?;
+        // Make sure there's room for the RTCP port.
+        port.checked_add(1)?;
+        Some(MulticastGroup { port, ..*self })
+    }
+}
+
+impl fmt::Display for MulticastGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{} (ttl: {})", self.addr, self.port, self.ttl)
+    }
+}
+
+/// Bind a socket for sending to the given multicast group.
+pub async fn bind_multicast(group: &MulticastGroup) -> io::Result<UdpSocket> {
+    let socket = match group.addr {
+        IpAddr::V4(_) => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+            socket.set_multicast_ttl_v4(group.ttl)?;
+            socket
+        }
+        // Note that IPv6 does not have a TTL, the hop limit is determined by the
+        // scope of the multicast address instead.
+        IpAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await?,
+    };
+    tracing::trace!(%group, "bound multicast socket");
+    Ok(socket)
+}
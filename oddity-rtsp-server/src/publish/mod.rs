@@ -0,0 +1,357 @@
+//! Publishers push a stream to the server with ANNOUNCE, SETUP and RECORD
+//! (RFC 2326 Section 10.3 and 10.11), and send the media interleaved on the
+//! RTSP connection.
+//!
+//! The media is not handled by the server itself. Instead, the announced SDP
+//! is rewritten to point at local UDP ports and every interleaved packet is
+//! forwarded to the corresponding port. A regular source then reads the
+//! rewritten SDP like any other stream, which means players can subscribe to
+//! it as usual.
+
+pub mod publish_manager;
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+
+use tokio::fs;
+use tokio::net::UdpSocket;
+
+use crate::media::MediaDescriptor;
+use crate::net::udp;
+use crate::session::SessionId;
+use crate::source::{SourcePath, SourcePathRef};
+
+/// Media description announced by a publisher. We only care about the media
+/// sections and their control attributes, everything else is passed on to
+/// the demuxer as-is.
+#[derive(Clone)]
+pub struct Announcement {
+    session: Vec<String>,
+    media: Vec<AnnouncedMedia>,
+}
+
+#[derive(Clone)]
+struct AnnouncedMedia {
+    lines: Vec<String>,
+    control: Option<String>,
+}
+
+impl Announcement {
+    pub fn parse(sdp: &str) -> Result<Self, PublishError> {
+        let mut session = Vec::new();
+        let mut media: Vec<AnnouncedMedia> = Vec::new();
+        for line in sdp.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if line.starts_with("m=") {
+                media.push(AnnouncedMedia {
+                    lines: vec![line.to_string()],
+                    control: None,
+                });
+            } else if let Some(current) = media.last_mut() {
+                if let Some(control) = line.strip_prefix("a=control:") {
+                    current.control = Some(control.trim().to_string());
+                }
+                current.lines.push(line.to_string());
+            } else {
+                session.push(line.to_string());
+            }
+        }
+
+        let has_version = session
+            .first()
+            .map(|line| line.starts_with("v="))
+            .unwrap_or(false);
+        if !has_version || media.is_empty() {
+            return Err(PublishError::DescriptionInvalid);
+        }
+
+        Ok(Self { session, media })
+    }
+
+    /// Find the media that the URL of a SETUP request refers to. Control
+    /// attributes are either absolute, or relative to the presentation URL.
+    /// If there is only one media without a control attribute, the publisher
+    /// may use any URL.
+    fn find_media(&self, uri: &str) -> Option<usize> {
+        let uri = uri.trim_end_matches('/');
+        if let [media] = self.media.as_slice() {
+            if media.control.is_none() {
+                return Some(0);
+            }
+        }
+
+        self.media
+            .iter()
+            .position(|media| match media.control.as_deref() {
+                Some(control) if control.contains("://") => control.trim_end_matches('/') == uri,
+                Some(control) => uri.ends_with(&format!("/{}", control.trim_start_matches('/'))),
+                None => false,
+            })
+    }
+
+    /// Rewrite the description so that every media that has a port is
+    /// received on that port of the loopback interface. Media without a
+    /// port are left out.
+    fn to_local_sdp(&self, ports: &[Option<u16>]) -> String {
+        let mut sdp = String::new();
+        for line in self.session.iter().filter(|line| !line.starts_with("c=")) {
+            sdp.push_str(line);
+            sdp.push_str("\r\n");
+        }
+
+        for (media, port) in self.media.iter().zip(ports) {
+            let port = match port {
+                Some(port) => port,
+                None => continue,
+            };
+            for line in media.lines.iter() {
+                if let Some(description) = line.strip_prefix("m=") {
+                    // Media descriptions look like this: `m=video 0 RTP/AVP 96`.
+                    let mut fields = description.split(' ');
+                    let kind = fields.next().unwrap_or_default();
+                    let rest = fields.skip(1).collect::<Vec<_>>().join(" ");
+                    sdp.push_str(&format!("m={} {} {}\r\n", kind, port, rest));
+                    sdp.push_str(&format!("c=IN IP4 {}\r\n", Ipv4Addr::LOCALHOST));
+                } else if !line.starts_with("c=") {
+                    sdp.push_str(line);
+                    sdp.push_str("\r\n");
+                }
+            }
+        }
+
+        sdp
+    }
+}
+
+/// Interleaved RTP and RTCP channel of every media that was set up.
+pub type Channels = Vec<Option<(u8, u8)>>;
+
+/// Stream that a publisher is pushing, or is about to push, to a path.
+pub struct Publication {
+    pub path: SourcePath,
+    announcement: Announcement,
+    channels: Channels,
+    /// Whether or not recording began, but the forwarder does not exist yet.
+    starting: bool,
+    forwarder: Option<Forwarder>,
+}
+
+/// Sends interleaved packets to the ports the demuxer listens on.
+pub struct Forwarder {
+    socket: UdpSocket,
+    targets: HashMap<u8, SocketAddr>,
+    sdp_file: PathBuf,
+    /// Ports in the SDP file, for as long as the demuxer has not taken them
+    /// over.
+    reservation: udp::Reservation,
+}
+
+impl Publication {
+    pub fn new(path: SourcePath, announcement: Announcement) -> Self {
+        let channels = vec![None; announcement.media.len()];
+        Self {
+            path,
+            announcement,
+            channels,
+            starting: false,
+            forwarder: None,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.forwarder.is_some()
+    }
+
+    /// Find the media that the URL of a SETUP request refers to.
+    pub fn find_media(&self, uri: &str) -> Option<usize> {
+        self.announcement.find_media(uri)
+    }
+
+    /// Receive the given media on the given interleaved channels.
+    pub fn setup(
+        &mut self,
+        media: usize,
+        rtp_channel: u8,
+        rtcp_channel: u8,
+    ) -> Result<(), PublishError> {
+        if self.is_recording() || self.starting {
+            return Err(PublishError::AlreadyRecording);
+        }
+
+        let in_use = self
+            .channels
+            .iter()
+            .enumerate()
+            .filter(|(other, _)| *other != media)
+            .filter_map(|(_, channels)| *channels)
+            .any(|(rtp, rtcp)| {
+                [rtp, rtcp].contains(&rtp_channel) || [rtp, rtcp].contains(&rtcp_channel)
+            });
+        if in_use {
+            return Err(PublishError::ChannelInUse);
+        }
+
+        match self.channels.get_mut(media) {
+            Some(channels) => {
+                *channels = Some((rtp_channel, rtcp_channel));
+                Ok(())
+            }
+            None => Err(PublishError::MediaNotFound),
+        }
+    }
+
+    /// Begin recording. Nothing can be set up anymore after this. Returns the
+    /// description and channels to create a [`Forwarder`] for, which is handed
+    /// back with [`Publication::record`], or [`Publication::abort_record`] if
+    /// it could not be created.
+    pub fn begin_record(&mut self) -> Result<(Announcement, Channels), PublishError> {
+        if self.is_recording() || self.starting {
+            return Err(PublishError::AlreadyRecording);
+        }
+        if self.channels.iter().all(Option::is_none) {
+            return Err(PublishError::NothingSetup);
+        }
+        self.starting = true;
+        Ok((self.announcement.clone(), self.channels.clone()))
+    }
+
+    /// Start forwarding with the forwarder that was created for this
+    /// publication.
+    pub fn record(&mut self, forwarder: Forwarder) {
+        self.starting = false;
+        self.forwarder = Some(forwarder);
+    }
+
+    pub fn abort_record(&mut self) {
+        self.starting = false;
+    }
+
+    /// Forward a packet the publisher sent on the given channel. Returns
+    /// whether or not the channel belongs to this publication.
+    pub async fn forward(&self, channel: u8, payload: &[u8]) -> bool {
+        match self.forwarder.as_ref() {
+            Some(forwarder) => match forwarder.targets.get(&channel) {
+                Some(target) => {
+                    // Packets that get lost are lost, just like they would be if the
+                    // publisher had sent them over UDP.
+                    if let Err(err) = forwarder.socket.send_to(payload, target).await {
+                        tracing::debug!(%err, path = %self.path, channel, "failed to forward packet");
+                    }
+                    true
+                }
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    pub async fn finish(self) {
+        if let Some(forwarder) = self.forwarder {
+            forwarder.finish().await;
+        }
+    }
+}
+
+impl Forwarder {
+    /// Forward the media on the given channels to local ports, and produce
+    /// the descriptor that a source can read it from.
+    pub async fn create(
+        path: &SourcePathRef,
+        session_id: &SessionId,
+        announcement: &Announcement,
+        channels: &Channels,
+    ) -> Result<(Self, MediaDescriptor), PublishError> {
+        // The demuxer binds the ports itself once the source starts, so we keep
+        // them bound until it is about to, see [`udp::Reservation`]. All pairs
+        // are bound at the same time to make sure we don't get the same one
+        // twice.
+        let mut reserved = Vec::new();
+        let mut ports = Vec::new();
+        for media_channels in channels.iter() {
+            ports.push(match media_channels {
+                Some(_) => {
+                    let (rtp_socket, rtcp_socket) =
+                        udp::bind_rtp_and_rtcp(IpAddr::V4(Ipv4Addr::LOCALHOST))
+                            .await
+                            .map_err(PublishError::Io)?;
+                    let port = rtp_socket.local_addr().map_err(PublishError::Io)?.port();
+                    reserved.push(rtp_socket.into_std().map_err(PublishError::Io)?);
+                    reserved.push(rtcp_socket.into_std().map_err(PublishError::Io)?);
+                    Some(port)
+                }
+                None => None,
+            });
+        }
+        let reservation = udp::Reservation::new(reserved);
+
+        let mut targets = HashMap::new();
+        for (media_channels, port) in channels.iter().zip(ports.iter()) {
+            if let (Some((rtp_channel, rtcp_channel)), Some(port)) = (media_channels, port) {
+                targets.insert(*rtp_channel, (Ipv4Addr::LOCALHOST, *port).into());
+                targets.insert(*rtcp_channel, (Ipv4Addr::LOCALHOST, port + 1).into());
+            }
+        }
+
+        let sdp_file = std::env::temp_dir().join(format!("oddity-publish-{}.sdp", session_id));
+        fs::write(&sdp_file, announcement.to_local_sdp(&ports))
+            .await
+            .map_err(PublishError::Io)?;
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .map_err(PublishError::Io)?;
+
+        tracing::trace!(path, sdp_file = %sdp_file.display(), "created forwarder");
+        let forwarder = Self {
+            socket,
+            targets,
+            sdp_file: sdp_file.clone(),
+            reservation: reservation.clone(),
+        };
+
+        Ok((forwarder, MediaDescriptor::Published(sdp_file, reservation)))
+    }
+
+    pub async fn finish(self) {
+        self.reservation.release();
+        let _ = fs::remove_file(&self.sdp_file).await;
+    }
+}
+
+#[derive(Debug)]
+pub enum PublishError {
+    PathNotFound,
+    DescriptionInvalid,
+    NotAnnounced,
+    AlreadyPublishing,
+    SourceMismatch,
+    MediaNotFound,
+    TransportNotSupported,
+    ChannelInUse,
+    NothingSetup,
+    AlreadyRecording,
+    Io(io::Error),
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PublishError::PathNotFound => write!(f, "path is not a publish point"),
+            PublishError::DescriptionInvalid => write!(f, "description invalid"),
+            PublishError::NotAnnounced => write!(f, "nothing announced for path"),
+            PublishError::AlreadyPublishing => write!(f, "path is already being published"),
+            PublishError::SourceMismatch => write!(f, "source mismatch"),
+            PublishError::MediaNotFound => write!(f, "media not found"),
+            PublishError::TransportNotSupported => write!(f, "transport not supported"),
+            PublishError::ChannelInUse => write!(f, "interleaved channel already in use"),
+            PublishError::NothingSetup => write!(f, "no media set up"),
+            PublishError::AlreadyRecording => write!(f, "already recording"),
+            PublishError::Io(error) => write!(f, "io error: {}", error),
+        }
+    }
+}
+
+impl error::Error for PublishError {}
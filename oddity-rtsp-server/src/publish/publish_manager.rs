@@ -0,0 +1,309 @@
+use std::collections::{hash_map::Entry, HashMap};
+use std::error;
+use std::fmt;
+
+use tokio::sync::RwLock;
+
+use oddity_rtsp_protocol as rtsp;
+
+use crate::media::MediaDescriptor;
+use crate::net::udp::MulticastGroup;
+use crate::publish::{Announcement, Forwarder, Publication, PublishError};
+use crate::session::transport;
+use crate::session::SessionId;
+use crate::source::{self, SourcePath, SourcePathRef};
+
+/// Path that publishers are allowed to push a stream to.
+#[derive(Clone)]
+pub struct PublishPoint {
+    pub name: String,
+    pub multicast_group: Option<MulticastGroup>,
+}
+
+/// Everything needed to register the source of a publication that started
+/// recording.
+pub struct PublishedSource {
+    pub name: String,
+    pub path: SourcePath,
+    pub descriptor: MediaDescriptor,
+    pub multicast_group: Option<MulticastGroup>,
+}
+
+/// Keeps track of publish points and the publications on them. Announced
+/// descriptions wait for the first SETUP on their path, which creates the
+/// publication and its session. The publication ends when the publisher
+/// tears it down or disconnects.
+pub struct PublishManager {
+    points: RwLock<HashMap<SourcePath, PublishPoint>>,
+    announcements: RwLock<HashMap<SourcePath, Announcement>>,
+    publications: RwLock<HashMap<SessionId, Publication>>,
+}
+
+impl PublishManager {
+    pub fn new() -> Self {
+        Self {
+            points: RwLock::new(HashMap::new()),
+            announcements: RwLock::new(HashMap::new()),
+            publications: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub async fn stop(&self) {
+        for (_, publication) in self.publications.write().await.drain() {
+            publication.finish().await;
+        }
+    }
+
+    pub async fn register(
+        &self,
+        name: &str,
+        path: SourcePath,
+        multicast_group: Option<MulticastGroup>,
+    ) -> Result<(), RegisterPublishPointError> {
+        let path = source::normalize_path(path);
+        if let Entry::Vacant(entry) = self.points.write().await.entry(path.clone()) {
+            entry.insert(PublishPoint {
+                name: name.to_string(),
+                multicast_group,
+            });
+            tracing::trace!(name, %path, "registered publish point");
+            Ok(())
+        } else {
+            tracing::error!(name, %path, "publish point with given path already registered");
+            Err(RegisterPublishPointError::AlreadyRegistered)
+        }
+    }
+
+    pub async fn is_registered(&self, path: &SourcePathRef) -> bool {
+        self.points.read().await.contains_key(path)
+    }
+
+    /// Stop accepting streams on the path, and end the publication on it if
+    /// there is one. Returns `false` if the path is not a publish point.
+    pub async fn unregister(&self, path: &SourcePathRef) -> bool {
+        let registered = self.points.write().await.remove(path).is_some();
+        self.announcements.write().await.remove(path);
+        let publications = {
+            let mut publications = self.publications.write().await;
+            let session_ids = publications
+                .iter()
+                .filter(|(_, publication)| publication.path == path)
+                .map(|(session_id, _)| session_id.clone())
+                .collect::<Vec<_>>();
+            session_ids
+                .iter()
+                .filter_map(|session_id| publications.remove(session_id))
+                .collect::<Vec<_>>()
+        };
+        for publication in publications {
+            tracing::trace!(path, "ending publication of unregistered publish point");
+            publication.finish().await;
+        }
+        if registered {
+            tracing::trace!(path, "unregistered publish point");
+        }
+        registered
+    }
+
+    /// Remember the description of a stream that is about to be published.
+    /// Announcing again before the stream was set up replaces the previous
+    /// description.
+    pub async fn announce(
+        &self,
+        path: &SourcePathRef,
+        announcement: Announcement,
+    ) -> Result<(), PublishError> {
+        if !self.points.read().await.contains_key(path) {
+            return Err(PublishError::PathNotFound);
+        }
+        if self.is_published(path).await {
+            return Err(PublishError::AlreadyPublishing);
+        }
+
+        self.announcements
+            .write()
+            .await
+            .insert(path.to_string(), announcement);
+        tracing::trace!(path, "received announcement");
+        Ok(())
+    }
+
+    /// Set up a single media of an announced stream. Without a session, this
+    /// starts a new publication. Only interleaved transports are supported.
+    pub async fn setup(
+        &self,
+        session_id: Option<&SessionId>,
+        path: &SourcePathRef,
+        uri: &str,
+        rtsp_transport: &rtsp::Transport,
+    ) -> Result<(SessionId, rtsp::Transport), PublishError> {
+        let mut publications = self.publications.write().await;
+        match session_id {
+            Some(session_id) => {
+                let publication = publications
+                    .get_mut(session_id)
+                    .ok_or(PublishError::SourceMismatch)?;
+                if !source::is_path_below(path, &publication.path) {
+                    return Err(PublishError::SourceMismatch);
+                }
+                let resolved_transport = setup_media(publication, uri, rtsp_transport)?;
+                Ok((session_id.clone(), resolved_transport))
+            }
+            None => {
+                let point_path = self
+                    .points
+                    .read()
+                    .await
+                    .keys()
+                    .find(|point_path| source::is_path_below(path, point_path))
+                    .cloned()
+                    .ok_or(PublishError::PathNotFound)?;
+                if publications
+                    .values()
+                    .any(|publication| publication.path == point_path)
+                {
+                    return Err(PublishError::AlreadyPublishing);
+                }
+                let announcement = self
+                    .announcements
+                    .read()
+                    .await
+                    .get(&point_path)
+                    .cloned()
+                    .ok_or(PublishError::NotAnnounced)?;
+
+                // The publication only exists once its first media was set up,
+                // otherwise a failed SETUP would leave it behind without a session
+                // the publisher knows about.
+                let mut publication = Publication::new(point_path.clone(), announcement);
+                let resolved_transport = setup_media(&mut publication, uri, rtsp_transport)?;
+                let _ = self.announcements.write().await.remove(&point_path);
+
+                let session_id = SessionId::generate();
+                tracing::trace!(%session_id, path = %point_path, "started publication");
+                publications.insert(session_id.clone(), publication);
+                Ok((session_id, resolved_transport))
+            }
+        }
+    }
+
+    /// Start recording. The returned source is what players subscribe to.
+    pub async fn record(
+        &self,
+        session_id: &SessionId,
+    ) -> Option<Result<PublishedSource, PublishError>> {
+        let (path, point, begun) = {
+            let mut publications = self.publications.write().await;
+            let publication = publications.get_mut(session_id)?;
+            let point = self.points.read().await.get(&publication.path).cloned()?;
+            (publication.path.clone(), point, publication.begin_record())
+        };
+        let (announcement, channels) = match begun {
+            Ok(begun) => begun,
+            Err(err) => return Some(Err(err)),
+        };
+
+        // Creating the forwarder binds sockets and writes the SDP file, which
+        // should not hold up the other publications.
+        let created = Forwarder::create(&path, session_id, &announcement, &channels).await;
+
+        let mut publications = self.publications.write().await;
+        match (publications.get_mut(session_id), created) {
+            (Some(publication), Ok((forwarder, descriptor))) => {
+                publication.record(forwarder);
+                Some(Ok(PublishedSource {
+                    name: point.name,
+                    path,
+                    descriptor,
+                    multicast_group: point.multicast_group,
+                }))
+            }
+            (Some(publication), Err(err)) => {
+                publication.abort_record();
+                Some(Err(err))
+            }
+            // The publication ended in the meantime.
+            (None, Ok((forwarder, _))) => {
+                drop(publications);
+                forwarder.finish().await;
+                None
+            }
+            (None, Err(_)) => None,
+        }
+    }
+
+    /// Forward interleaved data of a publisher. Returns whether or not the
+    /// session is a publication that owns the channel.
+    pub async fn forward(&self, session_id: &SessionId, channel: u8, payload: &[u8]) -> bool {
+        match self.publications.read().await.get(session_id) {
+            Some(publication) => publication.forward(channel, payload).await,
+            None => false,
+        }
+    }
+
+    /// End a publication. Returns `None` if the session is not a publication,
+    /// and the path of its source if it was recording.
+    pub async fn teardown(&self, session_id: &SessionId) -> Option<Option<SourcePath>> {
+        let publication = self.publications.write().await.remove(session_id)?;
+        tracing::trace!(%session_id, path = %publication.path, "ending publication");
+        let path = publication.is_recording().then(|| publication.path.clone());
+        publication.finish().await;
+        Some(path)
+    }
+
+    async fn is_published(&self, path: &SourcePathRef) -> bool {
+        self.publications
+            .read()
+            .await
+            .values()
+            .any(|publication| publication.path == path)
+    }
+}
+
+fn setup_media(
+    publication: &mut Publication,
+    uri: &str,
+    rtsp_transport: &rtsp::Transport,
+) -> Result<rtsp::Transport, PublishError> {
+    let media = publication
+        .find_media(uri)
+        .ok_or(PublishError::MediaNotFound)?;
+    // Each media gets its own pair of channels if the publisher did not pick
+    // any, the same way tracks do when playing.
+    let resolved_transport = transport::resolve_transport(rtsp_transport, media);
+    let (rtp_channel, rtcp_channel) = match resolved_transport.interleaved_channel() {
+        Some(rtsp::Channel::Single(rtp_channel)) => (
+            *rtp_channel,
+            // There is no channel after the last one to send RTCP on.
+            rtp_channel
+                .checked_add(1)
+                .ok_or(PublishError::TransportNotSupported)?,
+        ),
+        Some(rtsp::Channel::Range(rtp_channel, rtcp_channel)) => (*rtp_channel, *rtcp_channel),
+        None => return Err(PublishError::TransportNotSupported),
+    };
+    publication.setup(media, rtp_channel, rtcp_channel)?;
+    tracing::trace!(
+        path = %publication.path,
+        media,
+        rtp_channel,
+        rtcp_channel,
+        "set up media for publishing"
+    );
+    Ok(resolved_transport)
+}
+
+#[derive(Debug)]
+pub enum RegisterPublishPointError {
+    AlreadyRegistered,
+}
+
+impl fmt::Display for RegisterPublishPointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegisterPublishPointError::AlreadyRegistered => write!(f, "already registered"),
+        }
+    }
+}
+
+impl error::Error for RegisterPublishPointError {}
@@ -1,10 +1,18 @@
-mod transport;
+pub mod transport;
 
 pub mod session_manager;
 pub mod setup;
+pub mod stats;
+pub mod track;
 
+use std::collections::HashSet;
 use std::error;
 use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use futures::future::{self, Future};
 
 use tokio::select;
 use tokio::sync::broadcast;
@@ -13,33 +21,58 @@ use tokio::sync::mpsc;
 use rand::Rng;
 
 use oddity_rtsp_protocol as rtsp;
-use video_rs as video;
 
 use crate::media;
-use crate::media::video::rtp_muxer;
+use crate::media::rtcp;
+use crate::media::rtp::SharedRtpBuf;
+use crate::media::video::reader::{VodEvent, VodReader};
+use crate::metrics;
 use crate::runtime::task_manager::{Task, TaskContext};
 use crate::runtime::Runtime;
 use crate::session::setup::{SessionSetup, SessionSetupTarget};
-use crate::source::SourceDelegate;
+use crate::session::stats::SessionStats;
+use crate::session::track::Track;
+use crate::source::{SourceDelegate, SourcePath, SourcePathRef};
 
 pub enum SessionState {
     Stopped(SessionId),
+    /// The client showed signs of life without sending a request.
+    KeepAlive(SessionId),
 }
 
 pub type SessionStateTx = mpsc::UnboundedSender<SessionState>;
 pub type SessionStateRx = mpsc::UnboundedReceiver<SessionState>;
 
-pub type SessionStreamStateTx = broadcast::Sender<media::StreamState>;
+/// Stream state of a single track, together with the control URL the client
+/// used to set up the track.
+#[derive(Clone)]
+pub struct TrackStreamState {
+    pub track: usize,
+    pub control: String,
+    pub stream_state: media::StreamState,
+}
+
+pub type SessionStreamStateTx = broadcast::Sender<TrackStreamState>;
 
 pub enum SessionControlMessage {
+    AddTrack(Box<Track>),
+    RemoveTrack(usize),
     Play,
+    Pause,
+    Seek(rtsp::Range),
     StreamState,
+    /// RTCP the client sent interleaved on the given channel.
+    Rtcp(u8, Vec<u8>),
 }
 
 pub type SessionControlTx = mpsc::UnboundedSender<SessionControlMessage>;
 pub type SessionControlRx = mpsc::UnboundedReceiver<SessionControlMessage>;
 
 pub struct Session {
+    path: SourcePath,
+    vod: bool,
+    tracks: HashSet<usize>,
+    stats: Arc<SessionStats>,
     worker: Task,
     control_tx: SessionControlTx,
     stream_state_tx: SessionStreamStateTx,
@@ -59,8 +92,13 @@ impl Session {
     ) -> Self {
         let (control_tx, control_rx) = mpsc::unbounded_channel();
         let (stream_state_tx, _) = broadcast::channel(Self::MAX_QUEUED_INFO);
+        let path = source_delegate.path().to_string();
+        let vod = source_delegate.is_vod();
+        let tracks = HashSet::from([setup.track]);
+        let stats = Arc::new(SessionStats::new(metrics::global().path(&path)));
+        let track = Track::new(setup, stats.clone());
 
-        tracing::trace!(%id, "starting session");
+        tracing::trace!(%id, vod, "starting session");
         let worker = runtime
             .task()
             .spawn({
@@ -70,7 +108,7 @@ impl Session {
                     Self::run(
                         id,
                         source_delegate,
-                        setup,
+                        track,
                         control_rx,
                         state_tx,
                         stream_state_tx,
@@ -82,22 +120,100 @@ impl Session {
         tracing::trace!(%id, "started session");
 
         Self {
+            path,
+            vod,
+            tracks,
+            stats,
             worker,
             control_tx,
             stream_state_tx,
         }
     }
 
+    pub fn stats(&self) -> Arc<SessionStats> {
+        self.stats.clone()
+    }
+
+    pub fn path(&self) -> &SourcePathRef {
+        &self.path
+    }
+
+    pub fn has_track(&self, track: usize) -> bool {
+        self.tracks.contains(&track)
+    }
+
+    /// Whether or not a request on the URL of the given track (or the
+    /// presentation URL if `None`) controls the session as a whole. A track URL
+    /// is only accepted as such if the session contains just that track.
+    pub fn is_aggregate(&self, track: Option<usize>) -> bool {
+        match track {
+            Some(track) => self.tracks.len() == 1 && self.has_track(track),
+            None => true,
+        }
+    }
+
+    pub async fn add_track(&mut self, setup: SessionSetup) -> Result<(), AddTrackError> {
+        let track = setup.track;
+        if self.has_track(track) {
+            return Err(AddTrackError::TrackAlreadySetup);
+        }
+
+        tracing::trace!(track, "sending track to session");
+        self.control_tx
+            .send(SessionControlMessage::AddTrack(Box::new(Track::new(
+                setup,
+                self.stats.clone(),
+            ))))
+            .map_err(|_| AddTrackError::ControlBroken)?;
+        self.tracks.insert(track);
+        tracing::trace!(track, "added track to session");
+
+        Ok(())
+    }
+
+    pub async fn remove_track(&mut self, track: usize) -> Result<(), TeardownSessionError> {
+        if !self.has_track(track) {
+            return Err(TeardownSessionError::TrackNotFound);
+        }
+
+        tracing::trace!(track, "sending remove track signal to session");
+        self.control_tx
+            .send(SessionControlMessage::RemoveTrack(track))
+            .map_err(|_| TeardownSessionError::ControlBroken)?;
+        self.tracks.remove(&track);
+        tracing::trace!(track, "removed track from session");
+
+        Ok(())
+    }
+
     pub async fn play(
         &mut self,
+        path: &SourcePathRef,
+        track: Option<usize>,
         range: Option<rtsp::Range>,
-    ) -> Result<media::StreamState, PlaySessionError> {
+    ) -> Result<Vec<TrackStreamState>, PlaySessionError> {
+        if self.path() != path {
+            return Err(PlaySessionError::SourceMismatch);
+        }
+
+        // Playing individual tracks at different times is not supported.
+        if !self.is_aggregate(track) {
+            return Err(PlaySessionError::OnlyAggregateOperationAllowed);
+        }
+
         if let Some(range) = range.as_ref() {
             tracing::trace!(%range, "checking if provided range is valid and supported");
-            if !Self::is_range_supported(range) {
+            if !Self::is_range_supported(range, self.vod) {
                 tracing::error!(%range, "session does not support playing with this range");
                 return Err(PlaySessionError::RangeNotSupported);
             }
+
+            if self.vod {
+                tracing::trace!(%range, "sending seek signal to session");
+                self.control_tx
+                    .send(SessionControlMessage::Seek(range.clone()))
+                    .map_err(|_| PlaySessionError::ControlBroken)?;
+            }
         }
 
         let mut stream_state_rx = self.stream_state_tx.subscribe();
@@ -106,10 +222,21 @@ impl Session {
             .send(SessionControlMessage::StreamState)
             .map_err(|_| PlaySessionError::ControlBroken)?;
 
-        let stream_state = stream_state_rx
-            .recv()
-            .await
-            .map_err(|_| PlaySessionError::ControlBroken)?;
+        // Every track reports its own stream state.
+        let mut stream_states: Vec<TrackStreamState> = Vec::with_capacity(self.tracks.len());
+        while stream_states.len() < self.tracks.len() {
+            let stream_state = stream_state_rx
+                .recv()
+                .await
+                .map_err(|_| PlaySessionError::ControlBroken)?;
+            if !stream_states
+                .iter()
+                .any(|existing| existing.track == stream_state.track)
+            {
+                stream_states.push(stream_state);
+            }
+        }
+        stream_states.sort_by_key(|stream_state| stream_state.track);
         tracing::trace!("received stream state");
 
         tracing::trace!("sending play signal to session");
@@ -118,7 +245,36 @@ impl Session {
             .map_err(|_| PlaySessionError::ControlBroken)?;
         tracing::trace!("session playing");
 
-        Ok(stream_state)
+        Ok(stream_states)
+    }
+
+    pub async fn pause(
+        &mut self,
+        path: &SourcePathRef,
+        track: Option<usize>,
+    ) -> Result<(), PauseSessionError> {
+        if self.path() != path {
+            return Err(PauseSessionError::SourceMismatch);
+        }
+
+        if !self.is_aggregate(track) {
+            return Err(PauseSessionError::OnlyAggregateOperationAllowed);
+        }
+
+        tracing::trace!("sending pause signal to session");
+        self.control_tx
+            .send(SessionControlMessage::Pause)
+            .map_err(|_| PauseSessionError::ControlBroken)?;
+        tracing::trace!("session paused");
+        Ok(())
+    }
+
+    /// Pass on RTCP that the client sent interleaved on its connection. It is
+    /// ignored if the channel does not belong to any of the tracks.
+    pub fn receive_rtcp(&self, channel: u8, rtcp: Vec<u8>) -> Result<(), ReceiveRtcpError> {
+        self.control_tx
+            .send(SessionControlMessage::Rtcp(channel, rtcp))
+            .map_err(|_| ReceiveRtcpError::ControlBroken)
     }
 
     pub async fn teardown(&mut self) {
@@ -130,80 +286,98 @@ impl Session {
     async fn run(
         id: SessionId,
         source_delegate: SourceDelegate,
-        setup: SessionSetup,
+        track: Track,
         control_rx: SessionControlRx,
         state_tx: SessionStateTx,
         stream_state_tx: SessionStreamStateTx,
         task_context: TaskContext,
     ) {
-        let muxer = setup.rtp_muxer;
-
-        match setup.rtp_target {
+        match &track.target {
             SessionSetupTarget::RtpUdp(_) => {
-                tracing::error!(%id, "started session with unsupported transport");
+                tracing::trace!(%id, "starting rtp over udp loop");
             }
-            SessionSetupTarget::RtpTcp(target) => {
+            SessionSetupTarget::RtpTcp(_) => {
                 tracing::trace!(%id, "starting rtp over tcp (interleaved) loop");
-                Self::run_tcp_interleaved(
-                    id.clone(),
-                    source_delegate,
-                    muxer,
-                    target,
-                    control_rx,
-                    stream_state_tx,
-                    task_context,
-                )
-                .await;
+            }
+            SessionSetupTarget::RtpMulticast(target) => {
+                tracing::trace!(%id, group=%target.group, "starting rtp over udp multicast loop");
             }
         };
 
+        let tracks = vec![track];
+
+        if source_delegate.is_vod() {
+            match VodReader::new(source_delegate.descriptor()).await {
+                Ok(reader) => {
+                    Self::run_vod_loop(
+                        id.clone(),
+                        reader,
+                        tracks,
+                        control_rx,
+                        state_tx.clone(),
+                        stream_state_tx,
+                        task_context,
+                    )
+                    .await;
+                }
+                Err(err) => {
+                    tracing::error!(%id, %err, "failed to open file for session");
+                }
+            }
+        } else {
+            Self::run_loop(
+                id.clone(),
+                source_delegate,
+                tracks,
+                control_rx,
+                state_tx.clone(),
+                stream_state_tx,
+                task_context,
+            )
+            .await;
+        }
+
         let _ = state_tx.send(SessionState::Stopped(id));
     }
 
-    async fn run_tcp_interleaved(
+    async fn run_loop(
         id: SessionId,
         source_delegate: SourceDelegate,
-        mut muxer: video::RtpMuxer,
-        target: setup::SendInterleaved,
+        mut tracks: Vec<Track>,
         mut control_rx: SessionControlRx,
+        state_tx: SessionStateTx,
         stream_state_tx: SessionStreamStateTx,
         mut task_context: TaskContext,
     ) {
         let mut state = SessionMediaState::Ready;
-        let mut need_stream_state = false;
-
-        let (mut source_reset_rx, mut source_packet_rx) = source_delegate.into_parts();
+        // Whether or not the client asked to play before. Until then, packets
+        // are dropped, so that the session can start with the cached packets
+        // of the source.
+        let mut started = false;
+        // Packets received between querying the stream state and playing.
+        let mut pending = Vec::new();
+        // Number of the first packet that was not in the cache the session
+        // started with, see `GopCache::start`.
+        let mut first_number = 0;
+
+        let path_metrics = metrics::global().path(source_delegate.path());
+        let (mut source_reset_rx, mut source_packet_rx, gop_cache) = source_delegate.into_parts();
 
         'main: loop {
             select! {
               // CANCEL SAFETY: `broadcast::Receiver::recv` is cancel safe.
               reset = source_reset_rx.recv() => {
-                // If the source reader had an error and reinitialized its reader, then regained
-                // the connection, we must reinitialize our muxer as well to cope.
+                // The source muxes the new stream by itself. Its packets come with a
+                // new epoch, on which the tracks move the timestamps to continue where
+                // they left off, see `Rewriter`.
                 match reset {
                   Ok(media_info) => {
-                    tracing::trace!("reinitializing muxer");
-                    let new_muxer = rtp_muxer::make_rtp_muxer()
-                      .await
-                      .and_then(|mut rtp_muxer| {
-                        for stream_info in media_info.streams {
-                          tracing::trace!(
-                            stream_index=stream_info.index,
-                            "reinitializing muxer: adding stream to muxer",
-                          );
-                          rtp_muxer = rtp_muxer.with_stream(stream_info)?;
-                        }
-                        Ok(rtp_muxer)
-                      });
-
-                    match new_muxer {
-                      Ok(new_muxer) => {
-                        muxer = new_muxer;
-                      },
-                      Err(err) => {
-                        tracing::error!(%err, %id, "failed to reinitialize muxer");
-                      },
-                    };
+                    for track in tracks.iter() {
+                      if media_info.streams.get(track.id).is_none() {
+                        tracing::error!(%id, track=track.id, "track missing after source reset");
+                      }
+                    }
+                    tracing::trace!(%id, "source reset");
                   },
                   Err(_) => {
                     tracing::error!(%id, "source broken");
@@ -215,75 +389,336 @@ impl Session {
               packet = source_packet_rx.recv() => {
                 match packet {
                   Ok(packet) => {
-                    let (muxed, packet) = rtp_muxer::muxed(muxer, packet).await;
-                    muxer = muxed;
-
-                    if need_stream_state {
-                      tracing::trace!(%id, "fetching stream state");
-                      let (rtp_seq, rtp_timestamp) = muxer.seq_and_timestamp();
-                      let stream_state = media::StreamState {
-                        rtp_seq,
-                        rtp_timestamp,
-                      };
-                      tracing::trace!(%id, rtp_seq, rtp_timestamp, "fetched stream state");
-                      let _ = stream_state_tx.send(stream_state);
-
-                      need_stream_state = false;
+                    let track = match tracks.iter_mut().find(|track| track.id == packet.track) {
+                      Some(track) => track,
+                      // The client did not set up the track this packet belongs to.
+                      None => continue,
+                    };
+
+                    // The session already got this packet from the cache, or it came
+                    // before the keyframe the session started with.
+                    if packet.number < first_number {
+                      continue;
+                    }
+
+                    // Before the session starts, and while paused, packets are dropped
+                    // before they are rewritten. This way the RTP sequence numbers continue
+                    // where they left off when the session is resumed. We only make an
+                    // exception when the stream state is needed, since it refers to the
+                    // next packet.
+                    if !started || (state == SessionMediaState::Paused && !track.need_stream_state) {
+                      continue;
+                    }
+
+                    if track.need_stream_state {
+                      Self::send_stream_state(&id, track, packet.epoch, &packet.bufs, &stream_state_tx);
+                    }
+
+                    if state == SessionMediaState::Playing {
+                      for buf in packet.bufs.iter() {
+                        if let Err(err) = track.send(packet.epoch, buf).await {
+                          tracing::trace!(%id, %err, "failed to send to client");
+                          break 'main;
+                        }
+                      }
+                    } else if state == SessionMediaState::Ready {
+                      let track_id = track.id;
+                      let epoch = packet.epoch;
+                      pending.extend(packet.bufs.into_iter().map(|buf| (track_id, epoch, buf)));
+                    }
+                  }
+                  Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    path_metrics.lagged.inc();
+                    tracing::error!(%id, skipped, "session fell behind on source");
+                    break;
+                  },
+                  Err(broadcast::error::RecvError::Closed) => {
+                    tracing::error!(%id, "source broken");
+                    break;
+                  },
+                }
+              },
+              // CANCEL SAFETY: `Session::recv_rtcp` is cancel safe since it only
+              // uses `UdpSocket::recv_from` internally, which is cancel safe.
+              (track_id, rtcp) = Self::recv_rtcp(&tracks) => {
+                if Self::handle_rtcp(&id, &tracks, track_id, rtcp, &state_tx) {
+                  break;
+                }
+              },
+              // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
+              message = control_rx.recv() => {
+                match message {
+                  Some(SessionControlMessage::AddTrack(track)) => {
+                    tracing::trace!(%id, track=track.id, "adding track");
+                    tracks.push(*track);
+                  },
+                  Some(SessionControlMessage::RemoveTrack(track_id)) => {
+                    // Not part of the `if let`, or the iterator would be held
+                    // across the await below.
+                    let position = tracks.iter().position(|track| track.id == track_id);
+                    if let Some(position) = position {
+                      tracing::trace!(%id, track=track_id, "removing track");
+                      tracks.remove(position).finish().await;
+                      pending.retain(|(pending_track_id, _, _)| *pending_track_id != track_id);
+                    }
+                  },
+                  Some(SessionControlMessage::Play) => {
+                    state = SessionMediaState::Playing;
+                    tracing::info!(%id, "session now playing");
+                    for (track_id, epoch, buf) in pending.drain(..) {
+                      if let Some(track) = tracks.iter_mut().find(|track| track.id == track_id) {
+                        if let Err(err) = track.send(epoch, &buf).await {
+                          tracing::trace!(%id, %err, "failed to send to client");
+                          break 'main;
+                        }
+                      }
+                    }
+                  },
+                  Some(SessionControlMessage::Pause) => {
+                    // Pausing a session that is not playing does not do anything.
+                    if state == SessionMediaState::Playing {
+                      state = SessionMediaState::Paused;
+                      tracing::info!(%id, "session now paused");
+                    }
+                  },
+                  Some(SessionControlMessage::StreamState) => {
+                    for track in tracks.iter_mut() {
+                      track.need_stream_state = true;
                     }
+                    tracing::trace!(%id, "set need stream state flag");
 
-                    let packet = match packet {
-                      Ok(packet) => packet,
+                    if !started {
+                      started = true;
+                      // Start with the packets since the last keyframe, so that the
+                      // client can show a picture right away. The stream state then
+                      // refers to the keyframe.
+                      let (cached, next_number) = gop_cache
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .start();
+                      first_number = next_number;
+                      tracing::trace!(%id, packets = cached.len(), "starting with cached packets");
+                      for packet in cached {
+                        let track = match tracks.iter_mut().find(|track| track.id == packet.track) {
+                          Some(track) => track,
+                          None => continue,
+                        };
+                        if track.need_stream_state {
+                          Self::send_stream_state(&id, track, packet.epoch, &packet.bufs, &stream_state_tx);
+                        }
+                        let track_id = track.id;
+                        let epoch = packet.epoch;
+                        pending.extend(packet.bufs.into_iter().map(|buf| (track_id, epoch, buf)));
+                      }
+                    }
+                  },
+                  Some(SessionControlMessage::Seek(_)) => {
+                    // Live sessions cannot seek, the range is checked before this
+                    // message would ever be sent.
+                  },
+                  Some(SessionControlMessage::Rtcp(channel, rtcp)) => {
+                    let track_id = tracks
+                      .iter()
+                      .find(|track| track.target.rtcp_channel() == Some(channel))
+                      .map(|track| track.id);
+                    match track_id {
+                      Some(track_id) => {
+                        if Self::handle_rtcp(&id, &tracks, track_id, Ok(rtcp), &state_tx) {
+                          break;
+                        }
+                      },
+                      None => {
+                        tracing::trace!(%id, channel, "ignoring interleaved data on unknown channel");
+                      },
+                    }
+                  },
+                  None => {
+                    tracing::error!(%id, "session control channel broke unexpectedly");
+                    break;
+                  },
+                };
+              },
+              // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+              _ = task_context.wait_for_stop() => {
+                tracing::trace!("tearing down session");
+                break;
+              },
+            }
+        }
+
+        tracing::trace!(%id, "finishing muxers");
+        for track in tracks {
+            track.finish().await;
+        }
+        tracing::trace!(%id, "finished muxers");
+    }
+
+    async fn run_vod_loop(
+        id: SessionId,
+        mut reader: VodReader,
+        mut tracks: Vec<Track>,
+        mut control_rx: SessionControlRx,
+        state_tx: SessionStateTx,
+        stream_state_tx: SessionStreamStateTx,
+        mut task_context: TaskContext,
+    ) {
+        let mut state = SessionMediaState::Ready;
+        // The reader starts when the stream state is queried, just before the
+        // session starts playing. Packets read in between are kept here so the
+        // client does not miss the start of the range.
+        let mut pending = Vec::new();
+        // The session muxes by itself, so its stream never starts over, see
+        // `StreamPacket::epoch`.
+        const EPOCH: u64 = 0;
+
+        'main: loop {
+            select! {
+              // CANCEL SAFETY: `VodReader::read` uses `mpsc::UnboundedReceiver::recv`
+              // internally which is cancel safe.
+              event = reader.read() => {
+                match event {
+                  Some(Ok(VodEvent::Packet(packet))) => {
+                    let track = match tracks
+                      .iter_mut()
+                      .find(|track| track.stream_index == packet.stream_index())
+                    {
+                      Some(track) => track,
+                      None => continue,
+                    };
+
+                    let bufs = match track.mux(packet).await {
+                      Ok(bufs) => bufs,
                       Err(err) => {
                         tracing::error!(%id, %err, "failed to mux packet");
                         break;
                       },
                     };
 
+                    if track.need_stream_state {
+                      Self::send_stream_state(&id, track, EPOCH, &bufs, &stream_state_tx);
+                    }
+
                     if state == SessionMediaState::Playing {
-                      let messages = packet
-                        .into_iter()
-                        .map(|item| {
-                          match item {
-                            video::RtpBuf::Rtp(payload) => {
-                              rtsp::ResponseMaybeInterleaved::Interleaved {
-                                channel: target.rtp_channel,
-                                payload: payload.into(),
-                              }
-                            },
-                            video::RtpBuf::Rtcp(payload) => {
-                              rtsp::ResponseMaybeInterleaved::Interleaved {
-                                channel: target.rtcp_channel,
-                                payload: payload.into(),
-                              }
-                            },
-                          }
-                        });
-
-                      for message in messages {
-                        if let Err(err) = target.sender.send(message) {
-                          tracing::trace!(%id, %err, "underlying connection closed");
+                      for buf in bufs.iter() {
+                        if let Err(err) = track.send(EPOCH, buf).await {
+                          tracing::trace!(%id, %err, "failed to send to client");
                           break 'main;
                         }
                       }
+                    } else {
+                      let track_id = track.id;
+                      pending.extend(bufs.into_iter().map(|buf| (track_id, buf)));
                     }
-                  }
-                  Err(_) => {
-                    tracing::error!(%id, "source broken");
+                  },
+                  Some(Ok(VodEvent::End)) => {
+                    tracing::info!(%id, "session reached end of range");
+                    for track in tracks.iter_mut() {
+                      // Whoever is waiting for the stream state would wait forever if
+                      // there is nothing left to play, so use what we have.
+                      if track.need_stream_state {
+                        Self::send_stream_state(&id, track, EPOCH, &[], &stream_state_tx);
+                      }
+
+                      let bye = SharedRtpBuf::Rtcp(rtcp::bye(track.ssrc()).into());
+                      if let Err(err) = track.send(EPOCH, &bye).await {
+                        tracing::trace!(%id, %err, "failed to send bye to client");
+                        break 'main;
+                      }
+                    }
+
+                    // The session stays around, so the client can still play another
+                    // range before tearing down.
+                    state = SessionMediaState::Ready;
+                  },
+                  Some(Err(err)) => {
+                    tracing::error!(%id, %err, "failed to read file");
                     break;
                   },
+                  None => {
+                    tracing::error!(%id, "vod reader broken unexpectedly");
+                    break;
+                  },
+                }
+              },
+              // CANCEL SAFETY: `Session::recv_rtcp` is cancel safe since it only
+              // uses `UdpSocket::recv_from` internally, which is cancel safe.
+              (track_id, rtcp) = Self::recv_rtcp(&tracks) => {
+                if Self::handle_rtcp(&id, &tracks, track_id, rtcp, &state_tx) {
+                  break;
                 }
               },
               // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
               message = control_rx.recv() => {
                 match message {
+                  Some(SessionControlMessage::AddTrack(track)) => {
+                    tracing::trace!(%id, track=track.id, "adding track");
+                    tracks.push(*track);
+                  },
+                  Some(SessionControlMessage::RemoveTrack(track_id)) => {
+                    // Not part of the `if let`, or the iterator would be held
+                    // across the await below.
+                    let position = tracks.iter().position(|track| track.id == track_id);
+                    if let Some(position) = position {
+                      tracing::trace!(%id, track=track_id, "removing track");
+                      tracks.remove(position).finish().await;
+                      pending.retain(|(pending_track_id, _)| *pending_track_id != track_id);
+                    }
+                  },
                   Some(SessionControlMessage::Play) => {
                     state = SessionMediaState::Playing;
                     tracing::info!(%id, "session now playing");
+                    for (track_id, buf) in pending.drain(..) {
+                      if let Some(track) = tracks.iter_mut().find(|track| track.id == track_id) {
+                        if let Err(err) = track.send(EPOCH, &buf).await {
+                          tracing::trace!(%id, %err, "failed to send to client");
+                          break 'main;
+                        }
+                      }
+                    }
+                  },
+                  Some(SessionControlMessage::Pause) => {
+                    if state == SessionMediaState::Playing {
+                      reader.pause();
+                      state = SessionMediaState::Paused;
+                      tracing::info!(%id, "session now paused");
+                    }
+                  },
+                  Some(SessionControlMessage::Seek(range)) => {
+                    let start = match range.start {
+                      Some(rtsp::NptTime::Time(start)) => Some(start),
+                      _ => None,
+                    };
+                    let end = match range.end {
+                      Some(rtsp::NptTime::Time(end)) => Some(end),
+                      _ => None,
+                    };
+                    tracing::trace!(%id, ?start, ?end, "seeking");
+                    reader.seek(start, end);
+                    pending.clear();
                   },
                   Some(SessionControlMessage::StreamState) => {
-                    need_stream_state = true;
+                    for track in tracks.iter_mut() {
+                      track.need_stream_state = true;
+                    }
+                    reader.resume();
                     tracing::trace!(%id, "set need stream state flag");
                   },
+                  Some(SessionControlMessage::Rtcp(channel, rtcp)) => {
+                    let track_id = tracks
+                      .iter()
+                      .find(|track| track.target.rtcp_channel() == Some(channel))
+                      .map(|track| track.id);
+                    match track_id {
+                      Some(track_id) => {
+                        if Self::handle_rtcp(&id, &tracks, track_id, Ok(rtcp), &state_tx) {
+                          break;
+                        }
+                      },
+                      None => {
+                        tracing::trace!(%id, channel, "ignoring interleaved data on unknown channel");
+                      },
+                    }
+                  },
                   None => {
                     tracing::error!(%id, "session control channel broke unexpectedly");
                     break;
@@ -298,18 +733,124 @@ impl Session {
             }
         }
 
-        tracing::trace!(%id, "finishing muxer");
-        // Throw away possible last RTP buffer (we don't care about
-        // it since this is real-time and there's no "trailer".
-        let _ = rtp_muxer::finish(muxer).await;
-        tracing::trace!(%id, "finished muxer");
+        reader.stop().await;
+
+        tracing::trace!(%id, "finishing muxers");
+        for track in tracks {
+            track.finish().await;
+        }
+        tracing::trace!(%id, "finished muxers");
     }
 
-    fn is_range_supported(range: &rtsp::Range) -> bool {
-        match (range.start.as_ref(), range.end.as_ref()) {
-            (Some(rtsp::NptTime::Now), None) => true,
-            (Some(rtsp::NptTime::Time(start)), None) if *start <= 0.0 => true,
-            _ => false,
+    /// Wait for RTCP from the client on any of the tracks.
+    fn recv_rtcp(
+        tracks: &[Track],
+    ) -> impl Future<Output = (usize, Result<Vec<u8>, io::Error>)> + '_ {
+        // The returned future only borrows the targets, since tracks that
+        // have a muxer cannot be shared between threads.
+        let track_ids = tracks.iter().map(|track| track.id).collect::<Vec<_>>();
+        let receives = tracks
+            .iter()
+            .map(|track| Box::pin(track.target.recv_rtcp()))
+            .collect::<Vec<_>>();
+        async move {
+            if receives.is_empty() {
+                return future::pending().await;
+            }
+
+            let (rtcp, index, _) = future::select_all(receives).await;
+            (track_ids[index], rtcp)
+        }
+    }
+
+    /// Handle RTCP the client sent on the given track. Returns `true` if the
+    /// client said goodbye, in which case the session should end.
+    fn handle_rtcp(
+        id: &SessionId,
+        tracks: &[Track],
+        track: usize,
+        rtcp: Result<Vec<u8>, io::Error>,
+        state_tx: &SessionStateTx,
+    ) -> bool {
+        let rtcp = match rtcp {
+            Ok(rtcp) => rtcp,
+            Err(err) => {
+                tracing::debug!(%id, track, %err, "failed to receive rtcp from client");
+                return false;
+            }
+        };
+
+        // Clients periodically send receiver reports, which is enough to keep
+        // the session alive.
+        tracing::trace!(%id, track, "received rtcp from client");
+        let _ = state_tx.send(SessionState::KeepAlive(id.clone()));
+
+        let arrival = SystemTime::now();
+        for packet in rtcp::parse(&rtcp) {
+            match packet {
+                rtcp::ClientPacket::Report(blocks) => {
+                    // Reports are matched by SSRC rather than by the track they
+                    // arrived on, since a single report can cover all tracks.
+                    for block in blocks {
+                        if let Some(track) = tracks.iter().find(|track| track.ssrc() == block.ssrc)
+                        {
+                            track.record_report(&block, arrival);
+                        }
+                    }
+                }
+                rtcp::ClientPacket::Bye => {
+                    tracing::info!(%id, track, "client said goodbye, ending session");
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Tell whoever is waiting for the stream state of the track, given the
+    /// packets that the client is sent next.
+    fn send_stream_state(
+        id: &SessionId,
+        track: &mut Track,
+        epoch: u64,
+        bufs: &[SharedRtpBuf],
+        stream_state_tx: &SessionStreamStateTx,
+    ) {
+        let stream_state = track.stream_state(epoch, bufs);
+        tracing::trace!(
+            %id,
+            track = track.id,
+            rtp_seq = stream_state.rtp_seq,
+            rtp_timestamp = stream_state.rtp_timestamp,
+            "fetched stream state",
+        );
+        let _ = stream_state_tx.send(TrackStreamState {
+            track: track.id,
+            control: track.control.clone(),
+            stream_state,
+        });
+        track.need_stream_state = false;
+    }
+
+    fn is_range_supported(range: &rtsp::Range, vod: bool) -> bool {
+        if vod {
+            // Any range within the file is fine. Note that the end of the
+            // range may lie beyond the end of the file.
+            match (range.start.as_ref(), range.end.as_ref()) {
+                (Some(rtsp::NptTime::Time(start)), Some(rtsp::NptTime::Time(end))) => {
+                    *start >= 0.0 && start < end
+                }
+                (Some(rtsp::NptTime::Time(start)), None) => *start >= 0.0,
+                (None, Some(rtsp::NptTime::Time(end))) => *end > 0.0,
+                (Some(rtsp::NptTime::Now), None) => true,
+                _ => false,
+            }
+        } else {
+            match (range.start.as_ref(), range.end.as_ref()) {
+                (Some(rtsp::NptTime::Now), None) => true,
+                (Some(rtsp::NptTime::Time(start)), None) if *start <= 0.0 => true,
+                _ => false,
+            }
         }
     }
 }
@@ -345,6 +886,8 @@ impl From<&str> for SessionId {
 
 #[derive(Debug)]
 pub enum PlaySessionError {
+    SourceMismatch,
+    OnlyAggregateOperationAllowed,
     RangeNotSupported,
     ControlBroken,
 }
@@ -352,6 +895,10 @@ pub enum PlaySessionError {
 impl fmt::Display for PlaySessionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            PlaySessionError::SourceMismatch => write!(f, "session belongs to other source"),
+            PlaySessionError::OnlyAggregateOperationAllowed => {
+                write!(f, "only aggregate operation allowed")
+            }
             PlaySessionError::RangeNotSupported => write!(f, "range not supported"),
             PlaySessionError::ControlBroken => write!(f, "failed to control session"),
         }
@@ -360,8 +907,89 @@ impl fmt::Display for PlaySessionError {
 
 impl error::Error for PlaySessionError {}
 
+#[derive(Debug)]
+pub enum AddTrackError {
+    SourceMismatch,
+    TrackAlreadySetup,
+    TransportMismatch,
+    ControlBroken,
+}
+
+impl fmt::Display for AddTrackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddTrackError::SourceMismatch => write!(f, "track belongs to other source"),
+            AddTrackError::TrackAlreadySetup => write!(f, "track already set up"),
+            AddTrackError::TransportMismatch => write!(f, "transport does not match session"),
+            AddTrackError::ControlBroken => write!(f, "failed to control session"),
+        }
+    }
+}
+
+impl error::Error for AddTrackError {}
+
+#[derive(Debug)]
+pub enum PauseSessionError {
+    SourceMismatch,
+    OnlyAggregateOperationAllowed,
+    ControlBroken,
+}
+
+impl fmt::Display for PauseSessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PauseSessionError::SourceMismatch => write!(f, "session belongs to other source"),
+            PauseSessionError::OnlyAggregateOperationAllowed => {
+                write!(f, "only aggregate operation allowed")
+            }
+            PauseSessionError::ControlBroken => write!(f, "failed to control session"),
+        }
+    }
+}
+
+impl error::Error for PauseSessionError {}
+
+#[derive(Debug)]
+pub enum TeardownSessionError {
+    SourceMismatch,
+    TrackNotFound,
+    OnlyAggregateOperationAllowed,
+    ControlBroken,
+}
+
+impl fmt::Display for TeardownSessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TeardownSessionError::SourceMismatch => write!(f, "session belongs to other source"),
+            TeardownSessionError::TrackNotFound => write!(f, "track not found in session"),
+            TeardownSessionError::OnlyAggregateOperationAllowed => {
+                write!(f, "only aggregate operation allowed")
+            }
+            TeardownSessionError::ControlBroken => write!(f, "failed to control session"),
+        }
+    }
+}
+
+impl error::Error for TeardownSessionError {}
+
+#[derive(Debug)]
+pub enum ReceiveRtcpError {
+    ControlBroken,
+}
+
+impl fmt::Display for ReceiveRtcpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReceiveRtcpError::ControlBroken => write!(f, "failed to control session"),
+        }
+    }
+}
+
+impl error::Error for ReceiveRtcpError {}
+
 #[derive(PartialEq)]
 enum SessionMediaState {
     Ready,
     Playing,
+    Paused,
 }
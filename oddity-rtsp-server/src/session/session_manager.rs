@@ -1,36 +1,90 @@
-use std::collections::{hash_map::Entry, HashMap};
+use std::collections::{hash_map::Entry, HashMap, HashSet};
 use std::error;
 use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::select;
 use tokio::sync::mpsc;
 use tokio::sync::{Mutex, RwLock};
+use tokio::time::{self, Instant};
 
 use oddity_rtsp_protocol as rtsp;
 
-use crate::media;
 use crate::runtime::task_manager::{Task, TaskContext};
 use crate::runtime::Runtime;
 use crate::session::setup::SessionSetup;
+use crate::session::stats::SessionStats;
 use crate::session::{
-    PlaySessionError, Session, SessionId, SessionState, SessionStateRx, SessionStateTx,
+    AddTrackError, PauseSessionError, PlaySessionError, ReceiveRtcpError, Session, SessionId,
+    SessionState, SessionStateRx, SessionStateTx, TeardownSessionError, TrackStreamState,
 };
-use crate::source::SourceDelegate;
+use crate::source::{SourceDelegate, SourcePath, SourcePathRef};
 
 type SessionShared = Arc<Mutex<Session>>;
 type SessionMap = Arc<RwLock<HashMap<SessionId, SessionShared>>>;
 
+type MulticastSessionMap = Arc<RwLock<HashMap<SourcePath, MulticastSession>>>;
+
+/// What we know about the client of each session. Members of a multicast
+/// session are tracked individually, the shared session itself is not.
+type SessionClientMap = Arc<RwLock<HashMap<SessionId, SessionClient>>>;
+
+struct SessionClient {
+    last_activity: Instant,
+    timeout: Duration,
+    label: Option<String>,
+}
+
+impl SessionClient {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            last_activity: Instant::now(),
+            timeout,
+            label: None,
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.last_activity.elapsed() > self.timeout
+    }
+}
+
+/// Snapshot of the state of a session, for reporting to its client.
+pub struct SessionInfo {
+    pub path: SourcePath,
+    pub stats: Arc<SessionStats>,
+    pub timeout: Duration,
+    pub label: Option<String>,
+}
+
+/// Session that sends a source to its multicast group. There is at most one
+/// per source, and it is shared by all clients that set up a multicast
+/// session for that source. It lives as long as it has members.
+struct MulticastSession {
+    id: SessionId,
+    session: SessionShared,
+    members: HashSet<SessionId>,
+}
+
 pub struct SessionManager {
     sessions: SessionMap,
+    multicast_sessions: MulticastSessionMap,
+    clients: SessionClientMap,
+    timeout: Duration,
     session_state_tx: SessionStateTx,
     worker: Task,
     runtime: Arc<Runtime>,
 }
 
 impl SessionManager {
-    pub async fn start(runtime: Arc<Runtime>) -> Self {
+    /// How often sessions are checked for having timed out.
+    const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub async fn start(timeout: Duration, runtime: Arc<Runtime>) -> Self {
         let sessions = Arc::new(RwLock::new(HashMap::new()));
+        let multicast_sessions = Arc::new(RwLock::new(HashMap::new()));
+        let clients = Arc::new(RwLock::new(HashMap::new()));
         let (session_state_tx, session_state_rx) = mpsc::unbounded_channel();
 
         tracing::trace!("starting session manager");
@@ -38,13 +92,26 @@ impl SessionManager {
             .task()
             .spawn({
                 let sessions = sessions.clone();
-                move |task_context| Self::run(sessions.clone(), session_state_rx, task_context)
+                let multicast_sessions = multicast_sessions.clone();
+                let clients = clients.clone();
+                move |task_context| {
+                    Self::run(
+                        sessions.clone(),
+                        multicast_sessions.clone(),
+                        clients.clone(),
+                        session_state_rx,
+                        task_context,
+                    )
+                }
             })
             .await;
         tracing::trace!("started session manager");
 
         Self {
             sessions,
+            multicast_sessions,
+            clients,
+            timeout,
             session_state_tx,
             runtime,
             worker,
@@ -58,6 +125,90 @@ impl SessionManager {
         for (_, session) in self.sessions.write().await.drain() {
             session.lock().await.teardown().await;
         }
+        // Shared multicast sessions have already been torn down through their
+        // members above.
+        self.multicast_sessions.write().await.clear();
+        self.clients.write().await.clear();
+    }
+
+    /// Default number of seconds of inactivity after which sessions are torn
+    /// down.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub async fn info(&self, id: &SessionId) -> Option<SessionInfo> {
+        let session = self.sessions.read().await.get(id).cloned()?;
+        let (path, stats) = {
+            let session = session.lock().await;
+            (session.path().to_string(), session.stats())
+        };
+        self.clients.read().await.get(id).map(|client| SessionInfo {
+            path,
+            stats,
+            timeout: client.timeout,
+            label: client.label.clone(),
+        })
+    }
+
+    /// Number of sessions per path. Every member of a multicast session is
+    /// counted separately.
+    pub async fn count_by_path(&self) -> HashMap<SourcePath, usize> {
+        let session_ids = self
+            .clients
+            .read()
+            .await
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut counts = HashMap::new();
+        for session_id in session_ids {
+            let session = self.sessions.read().await.get(&session_id).cloned();
+            if let Some(session) = session {
+                let path = session.lock().await.path().to_string();
+                *counts.entry(path).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    /// Change the timeout of a single session. Returns `false` if there is no
+    /// such session.
+    pub async fn set_timeout(&self, id: &SessionId, timeout: Duration) -> bool {
+        match self.clients.write().await.get_mut(id) {
+            Some(client) => {
+                client.timeout = timeout;
+                tracing::trace!(session_id=%id, ?timeout, "changed session timeout");
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Label the session so that it can be told apart in the logs. Returns
+    /// `false` if there is no such session.
+    pub async fn set_label(&self, id: &SessionId, label: String) -> bool {
+        match self.clients.write().await.get_mut(id) {
+            Some(client) => {
+                tracing::info!(session_id=%id, %label, "labeled session");
+                client.label = Some(label);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Mark the session as active, which postpones its timeout. Returns `false`
+    /// if there is no such session.
+    pub async fn keep_alive(&self, id: &SessionId) -> bool {
+        match self.clients.write().await.get_mut(id) {
+            Some(client) => {
+                client.last_activity = Instant::now();
+                tracing::trace!(session_id=%id, "session kept alive");
+                true
+            }
+            None => false,
+        }
     }
 
     pub async fn setup(
@@ -65,6 +216,10 @@ impl SessionManager {
         source_delegate: SourceDelegate,
         setup: SessionSetup,
     ) -> Result<SessionId, RegisterSessionError> {
+        if setup.rtp_target.is_multicast() {
+            return self.setup_multicast(source_delegate, setup).await;
+        }
+
         let session_id = SessionId::generate();
         let session = Session::setup_and_start(
             session_id.clone(),
@@ -77,6 +232,10 @@ impl SessionManager {
 
         if let Entry::Vacant(entry) = self.sessions.write().await.entry(session_id.clone()) {
             let _ = entry.insert(Arc::new(Mutex::new(session)));
+            self.clients
+                .write()
+                .await
+                .insert(session_id.clone(), SessionClient::new(self.timeout));
             tracing::trace!(%session_id, "registered new session");
             Ok(session_id)
         } else {
@@ -85,15 +244,109 @@ impl SessionManager {
         }
     }
 
+    /// Add a client to the multicast session of the source, starting the
+    /// multicast session if this is the first client to join it.
+    async fn setup_multicast(
+        &self,
+        source_delegate: SourceDelegate,
+        setup: SessionSetup,
+    ) -> Result<SessionId, RegisterSessionError> {
+        let session_id = SessionId::generate();
+        let mut sessions = self.sessions.write().await;
+        if sessions.contains_key(&session_id) {
+            tracing::error!(%session_id, "session with this ID already exists");
+            return Err(RegisterSessionError::AlreadyRegistered);
+        }
+
+        let mut multicast_sessions = self.multicast_sessions.write().await;
+        let multicast_session = match multicast_sessions.entry(source_delegate.path().to_string()) {
+            Entry::Occupied(entry) => {
+                // Setup is dropped here, the multicast session already has its
+                // own muxer and socket.
+                entry.into_mut()
+            }
+            Entry::Vacant(entry) => {
+                let multicast_session_id = SessionId::generate();
+                tracing::trace!(
+                  %multicast_session_id, path=source_delegate.path(),
+                  "starting multicast session for source",
+                );
+                let session = Session::setup_and_start(
+                    multicast_session_id.clone(),
+                    source_delegate,
+                    setup,
+                    self.session_state_tx.clone(),
+                    self.runtime.as_ref(),
+                )
+                .await;
+                entry.insert(MulticastSession {
+                    id: multicast_session_id,
+                    session: Arc::new(Mutex::new(session)),
+                    members: HashSet::new(),
+                })
+            }
+        };
+
+        multicast_session.members.insert(session_id.clone());
+        sessions.insert(session_id.clone(), multicast_session.session.clone());
+        self.clients
+            .write()
+            .await
+            .insert(session_id.clone(), SessionClient::new(self.timeout));
+        tracing::trace!(
+          %session_id, multicast_session_id=%multicast_session.id,
+          "registered new session as member of multicast session",
+        );
+        Ok(session_id)
+    }
+
+    /// Add another track to an existing session.
+    pub async fn add_track(
+        &self,
+        id: &SessionId,
+        path: &SourcePathRef,
+        setup: SessionSetup,
+    ) -> Option<Result<(), AddTrackError>> {
+        let session = self.sessions.read().await.get(id).cloned();
+        if let Some(session) = session {
+            if setup.rtp_target.is_multicast() != self.is_multicast_member(id).await {
+                return Some(Err(AddTrackError::TransportMismatch));
+            }
+
+            let mut session = session.lock().await;
+            if session.path() != path {
+                return Some(Err(AddTrackError::SourceMismatch));
+            }
+
+            // Another member of the multicast session might have set up the
+            // track already, in which case it is already being sent.
+            if setup.rtp_target.is_multicast() && session.has_track(setup.track) {
+                tracing::trace!(session_id=%id, track=setup.track, "multicast track already set up");
+                return Some(Ok(()));
+            }
+
+            tracing::trace!(session_id=%id, track=setup.track, "adding track");
+            Some(session.add_track(setup).await)
+        } else {
+            tracing::trace!(
+              session_id=%id,
+              "caller tried to add track to session that does not exist",
+            );
+            None
+        }
+    }
+
     pub async fn play(
         &self,
         id: &SessionId,
+        path: &SourcePathRef,
+        track: Option<usize>,
         range: Option<rtsp::Range>,
-    ) -> Option<Result<media::StreamState, PlaySessionError>> {
+    ) -> Option<Result<Vec<TrackStreamState>, PlaySessionError>> {
         let session = self.sessions.read().await.get(id).cloned();
         if let Some(session) = session {
             tracing::trace!(session_id=%id, "start playing");
-            Some(session.lock().await.play(range).await)
+            Some(session.lock().await.play(path, track, range).await)
         } else {
             tracing::trace!(
               session_id=%id,
@@ -103,42 +356,262 @@ impl SessionManager {
         }
     }
 
-    pub async fn teardown(&self, id: &SessionId) -> bool {
+    /// Pass on RTCP that the client sent interleaved on its connection, which
+    /// also keeps the session alive.
+    pub async fn receive_rtcp(
+        &self,
+        id: &SessionId,
+        channel: u8,
+        rtcp: &[u8],
+    ) -> Option<Result<(), ReceiveRtcpError>> {
+        if !self.keep_alive(id).await {
+            return None;
+        }
+        let session = self.sessions.read().await.get(id).cloned()?;
+        if self.is_multicast_member(id).await {
+            // A single member must not be able to end the session of everyone
+            // else with a BYE.
+            tracing::trace!(session_id=%id, "ignoring rtcp of multicast member");
+            return Some(Ok(()));
+        }
+
+        let result = session.lock().await.receive_rtcp(channel, rtcp.to_vec());
+        Some(result)
+    }
+
+    pub async fn pause(
+        &self,
+        id: &SessionId,
+        path: &SourcePathRef,
+        track: Option<usize>,
+    ) -> Option<Result<(), PauseSessionError>> {
+        let session = self.sessions.read().await.get(id).cloned();
+        if let Some(session) = session {
+            if self.is_multicast_member(id).await {
+                // Other members are still watching the multicast session, so we
+                // cannot actually pause it. The client can simply stop listening.
+                tracing::trace!(session_id=%id, "not pausing multicast session");
+                return Some(Ok(()));
+            }
+
+            tracing::trace!(session_id=%id, "pausing");
+            Some(session.lock().await.pause(path, track).await)
+        } else {
+            tracing::trace!(
+              session_id=%id,
+              "caller tried to pause session that does not exist",
+            );
+            None
+        }
+    }
+
+    /// Tear down the session, or only a single track of it if `track` refers
+    /// to one of multiple tracks in the session.
+    pub async fn teardown(
+        &self,
+        id: &SessionId,
+        path: &SourcePathRef,
+        track: Option<usize>,
+    ) -> Option<Result<(), TeardownSessionError>> {
         let session = self.sessions.read().await.get(id).cloned();
         if let Some(session) = session {
+            {
+                let mut session = session.lock().await;
+                if session.path() != path {
+                    return Some(Err(TeardownSessionError::SourceMismatch));
+                }
+
+                if let Some(track) = track.filter(|track| !session.is_aggregate(Some(*track))) {
+                    // The tracks of a multicast session are shared with other members,
+                    // so members can only leave the session as a whole.
+                    if self.is_multicast_member(id).await {
+                        return Some(Err(TeardownSessionError::OnlyAggregateOperationAllowed));
+                    }
+
+                    tracing::trace!(session_id=%id, track, "tearing down track");
+                    return Some(session.remove_track(track).await);
+                }
+            }
+
+            if Self::leave_multicast(&self.sessions, &self.multicast_sessions, &self.clients, id)
+                .await
+            {
+                return Some(Ok(()));
+            }
+
             tracing::trace!(session_id=%id, "tearing down session");
             session.lock().await.teardown().await;
             tracing::trace!(session_id=%id, "torn down session");
-            true
+            Some(Ok(()))
         } else {
             tracing::trace!(
               session_id=%id,
               "caller tried to tear down session that does not exist",
             );
-            false
+            None
+        }
+    }
+
+    /// Tear down every session of the source at the given path, for when the
+    /// source goes away. Returns the number of sessions that were torn down.
+    pub async fn teardown_all(&self, path: &SourcePathRef) -> usize {
+        let sessions = self
+            .sessions
+            .read()
+            .await
+            .iter()
+            .map(|(id, session)| (id.clone(), session.clone()))
+            .collect::<Vec<_>>();
+
+        let mut count = 0;
+        for (id, session) in sessions {
+            if session.lock().await.path() != path {
+                continue;
+            }
+
+            count += 1;
+            if Self::leave_multicast(&self.sessions, &self.multicast_sessions, &self.clients, &id)
+                .await
+            {
+                continue;
+            }
+
+            tracing::trace!(session_id=%id, path, "tearing down session of source");
+            let _ = self.clients.write().await.remove(&id);
+            session.lock().await.teardown().await;
+        }
+        count
+    }
+
+    async fn is_multicast_member(&self, id: &SessionId) -> bool {
+        self.multicast_sessions
+            .read()
+            .await
+            .values()
+            .any(|multicast_session| multicast_session.members.contains(id))
+    }
+
+    /// Remove the client from the multicast session it is a member of, and tear
+    /// down the multicast session if it has no members left. Returns `false` if
+    /// the client is not a member of any multicast session.
+    async fn leave_multicast(
+        sessions: &SessionMap,
+        multicast_sessions: &MulticastSessionMap,
+        clients: &SessionClientMap,
+        id: &SessionId,
+    ) -> bool {
+        let unused_session = {
+            let mut sessions = sessions.write().await;
+            let mut multicast_sessions = multicast_sessions.write().await;
+            let path = match multicast_sessions
+                .iter()
+                .find(|(_, multicast_session)| multicast_session.members.contains(id))
+            {
+                Some((path, _)) => path.clone(),
+                None => return false,
+            };
+
+            let _ = sessions.remove(id);
+            let _ = clients.write().await.remove(id);
+            match multicast_sessions.entry(path) {
+                Entry::Occupied(mut entry) => {
+                    entry.get_mut().members.remove(id);
+                    tracing::trace!(session_id=%id, path=entry.key(), "left multicast session");
+                    if entry.get().members.is_empty() {
+                        Some(entry.remove().session)
+                    } else {
+                        None
+                    }
+                }
+                Entry::Vacant(_) => None,
+            }
+        };
+
+        if let Some(session) = unused_session {
+            tracing::trace!(session_id=%id, "tearing down multicast session (no members left)");
+            session.lock().await.teardown().await;
+        }
+
+        true
+    }
+
+    /// Tear down sessions of which the client has not shown any signs of life
+    /// for longer than the timeout.
+    async fn reap(
+        sessions: &SessionMap,
+        multicast_sessions: &MulticastSessionMap,
+        clients: &SessionClientMap,
+    ) {
+        let expired = clients
+            .read()
+            .await
+            .iter()
+            .filter(|(_, client)| client.is_expired())
+            .map(|(session_id, _)| session_id.clone())
+            .collect::<Vec<_>>();
+
+        for session_id in expired {
+            tracing::info!(%session_id, "session timed out");
+            if Self::leave_multicast(sessions, multicast_sessions, clients, &session_id).await {
+                continue;
+            }
+
+            let _ = clients.write().await.remove(&session_id);
+            let session = sessions.read().await.get(&session_id).cloned();
+            if let Some(session) = session {
+                session.lock().await.teardown().await;
+            }
         }
     }
 
     async fn run(
         sessions: SessionMap,
+        multicast_sessions: MulticastSessionMap,
+        clients: SessionClientMap,
         mut session_state_rx: SessionStateRx,
         mut task_context: TaskContext,
     ) {
+        let mut reap_interval = time::interval(Self::REAP_INTERVAL);
         loop {
             select! {
               // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
               state = session_state_rx.recv() => {
                 match state {
                   Some(SessionState::Stopped(session_id)) => {
-                    let _ = sessions.write().await.remove(&session_id);
+                    let mut sessions = sessions.write().await;
+                    let mut clients = clients.write().await;
+                    let _ = sessions.remove(&session_id);
+                    let _ = clients.remove(&session_id);
+                    // If a multicast session stopped, then all of its members are
+                    // gone with it.
+                    multicast_sessions.write().await.retain(|_, multicast_session| {
+                      if multicast_session.id == session_id {
+                        for member_id in multicast_session.members.iter() {
+                          let _ = sessions.remove(member_id);
+                          let _ = clients.remove(member_id);
+                        }
+                        false
+                      } else {
+                        true
+                      }
+                    });
                     tracing::trace!(%session_id, "session manager: received stopped");
                   },
+                  Some(SessionState::KeepAlive(session_id)) => {
+                    if let Some(client) = clients.write().await.get_mut(&session_id) {
+                      client.last_activity = Instant::now();
+                    }
+                  },
                   None => {
                     tracing::error!("session state channel broke unexpectedly");
                     break;
                   },
                 }
               },
+              // CANCEL SAFETY: `Interval::tick` is cancel safe.
+              _ = reap_interval.tick() => {
+                Self::reap(&sessions, &multicast_sessions, &clients).await;
+              },
               // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
               _ = task_context.wait_for_stop() => {
                 tracing::trace!("stopping session manager");
@@ -1,69 +1,137 @@
 use std::error;
 use std::fmt;
+use std::io;
 use std::net::SocketAddr;
 
+use futures::future;
+
+use tokio::net::UdpSocket;
+
 use oddity_rtsp_protocol as rtsp;
 use video_rs as video;
 
-use crate::media::video::rtp_muxer;
+use crate::media::video::rtp_muxer::{self, RtpMuxer};
 use crate::media::MediaInfo;
-use crate::net::connection::ResponseSenderTx;
+use crate::net::connection::ConnectionInfo;
+use crate::net::send_queue::ResponseSenderTx;
+use crate::net::udp::{self, MulticastGroup};
 use crate::session::transport;
 
+/// Setup of a single track of a session.
 pub struct SessionSetup {
+    pub track: usize,
+    pub stream_index: usize,
+    pub control: String,
     pub rtsp_transport: rtsp::Transport,
-    pub rtp_muxer: video::RtpMuxer,
+    /// Only video-on-demand sessions mux by themselves. Live sessions get
+    /// packets that were already muxed by the source.
+    pub rtp_muxer: Option<RtpMuxer>,
     pub rtp_target: SessionSetupTarget,
 }
 
 impl SessionSetup {
+    #[allow(clippy::too_many_arguments)]
     pub async fn from_rtsp_candidate_transports(
         candidate_transports: impl IntoIterator<Item = rtsp::Transport>,
         media_info: MediaInfo,
+        track: usize,
+        control: String,
+        vod: bool,
+        multicast_group: Option<&MulticastGroup>,
+        connection: &ConnectionInfo,
         sender: ResponseSenderTx,
     ) -> Result<Self, SessionSetupError> {
+        let stream_info = media_info
+            .streams
+            .get(track)
+            .cloned()
+            .ok_or(SessionSetupError::TrackNotFound)?;
+
+        // Multicast transports are only acceptable if the source is actually
+        // distributed on a multicast group. Record transports are meant for
+        // publishing, not for playing. Clients that connected over TLS only
+        // get the media interleaved, since anything sent over UDP would not be
+        // encrypted.
         let transport = candidate_transports
             .into_iter()
-            .find(transport::is_supported)
+            .find(|transport| {
+                transport::is_supported(transport)
+                    && !transport::is_record(transport)
+                    && (!transport::is_multicast(transport) || multicast_group.is_some())
+                    && (!connection.secure || transport::is_interleaved(transport))
+            })
             .ok_or(SessionSetupError::TransportNotSupported)?;
         tracing::trace!(%transport, "selected transport");
 
-        tracing::trace!("initializing muxer");
-        rtp_muxer::make_rtp_muxer()
-            .await
-            .map_err(SessionSetupError::Media)
-            .and_then(|mut rtp_muxer| {
-                let resolved_transport = transport::resolve_transport(&transport);
+        let (resolved_transport, rtp_target) = match multicast_group {
+            Some(group) if transport::is_multicast(&transport) => {
+                let group = group
+                    .for_track(track)
+                    .ok_or(SessionSetupError::DestinationInvalid)?;
+                let resolved_transport = transport::resolve_multicast_transport(&group);
+                tracing::trace!(%resolved_transport, "resolved multicast transport");
+                let rtp_target = SessionSetupTarget::from_multicast_group(&group).await?;
+                (resolved_transport, rtp_target)
+            }
+            _ => {
+                let resolved_transport = transport::resolve_transport(&transport, track);
                 tracing::trace!(%resolved_transport, "resolved transport");
-                let rtp_target =
-                    SessionSetupTarget::from_rtsp_transport(&resolved_transport, sender)
-                        .ok_or(SessionSetupError::DestinationInvalid)?;
-                tracing::debug!(?rtp_target, "calculated target");
-
-                for stream_info in media_info.streams {
-                    tracing::trace!(stream_index = stream_info.index, "adding stream to muxer");
-                    rtp_muxer = rtp_muxer
-                        .with_stream(stream_info)
-                        .map_err(SessionSetupError::Media)?;
-                }
+                let rtp_target = SessionSetupTarget::from_rtsp_transport(
+                    &resolved_transport,
+                    connection,
+                    sender,
+                )
+                .await?;
+                (resolved_transport, rtp_target)
+            }
+        };
+        tracing::debug!(?rtp_target, "calculated target");
 
-                Ok(Self {
-                    rtsp_transport: resolved_transport,
-                    rtp_muxer,
-                    rtp_target,
-                })
-            })
+        // If we bound sockets for the client, it needs to know where the
+        // packets will be coming from.
+        let resolved_transport = match rtp_target.server_port() {
+            Some(server_port) => {
+                resolved_transport.with_parameter(rtsp::Parameter::ServerPort(server_port))
+            }
+            None => resolved_transport,
+        };
+
+        let stream_index = stream_info.index;
+        let rtp_muxer = if vod {
+            tracing::trace!(track, stream_index, "initializing muxer");
+            Some(
+                rtp_muxer::make_rtp_muxer()
+                    .await
+                    .and_then(|rtp_muxer| rtp_muxer.with_stream(stream_info))
+                    .map_err(SessionSetupError::Media)?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Self {
+            track,
+            stream_index,
+            control,
+            rtsp_transport: resolved_transport,
+            rtp_muxer,
+            rtp_target,
+        })
     }
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum SessionSetupTarget {
     RtpUdp(SendOverSocket),
     RtpTcp(SendInterleaved),
+    RtpMulticast(SendMulticast),
 }
 
 #[derive(Debug)]
 pub struct SendOverSocket {
+    pub rtp_socket: UdpSocket,
+    pub rtcp_socket: UdpSocket,
     pub rtp_remote: SocketAddr,
     pub rtcp_remote: SocketAddr,
 }
@@ -75,26 +143,71 @@ pub struct SendInterleaved {
     pub rtcp_channel: u8,
 }
 
+#[derive(Debug)]
+pub struct SendMulticast {
+    pub socket: UdpSocket,
+    pub group: MulticastGroup,
+}
+
 impl SessionSetupTarget {
-    pub fn from_rtsp_transport(
+    /// Large enough to hold any RTCP packet a client would reasonably send.
+    const MAX_RTCP_PACKET_SIZE: usize = 1500;
+
+    pub async fn from_multicast_group(group: &MulticastGroup) -> Result<Self, SessionSetupError> {
+        let socket = udp::bind_multicast(group)
+            .await
+            .map_err(SessionSetupError::Io)?;
+        Ok(SessionSetupTarget::RtpMulticast(SendMulticast {
+            socket,
+            group: *group,
+        }))
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        matches!(self, SessionSetupTarget::RtpMulticast(_))
+    }
+
+    pub async fn from_rtsp_transport(
         rtsp_transport: &rtsp::Transport,
+        connection: &ConnectionInfo,
         sender: ResponseSenderTx,
-    ) -> Option<Self> {
-        Some(match rtsp_transport.lower_protocol()? {
+    ) -> Result<Self, SessionSetupError> {
+        Ok(match transport::lower_protocol(rtsp_transport) {
             rtsp::Lower::Udp => {
-                let client_ip_addr = rtsp_transport.destination()?;
-                let (client_rtp_port, client_rtcp_port) = match rtsp_transport.client_port()? {
+                // We only ever send to the client that set up the session. Allowing
+                // arbitrary destinations would turn the server into a traffic
+                // amplifier.
+                let client_ip_addr = connection.peer_addr.ip();
+                if let Some(destination) = rtsp_transport.destination() {
+                    if *destination != client_ip_addr {
+                        return Err(SessionSetupError::DestinationInvalid);
+                    }
+                }
+
+                let (client_rtp_port, client_rtcp_port) = match rtsp_transport
+                    .client_port()
+                    .ok_or(SessionSetupError::DestinationInvalid)?
+                {
                     rtsp::Port::Single(rtp_port) => (*rtp_port, rtp_port + 1),
                     rtsp::Port::Range(rtp_port, rtcp_port) => (*rtp_port, *rtcp_port),
                 };
 
+                let (rtp_socket, rtcp_socket) = udp::bind_rtp_and_rtcp(connection.local_addr.ip())
+                    .await
+                    .map_err(SessionSetupError::Io)?;
+
                 SessionSetupTarget::RtpUdp(SendOverSocket {
-                    rtp_remote: (*client_ip_addr, client_rtp_port).into(),
-                    rtcp_remote: (*client_ip_addr, client_rtcp_port).into(),
+                    rtp_socket,
+                    rtcp_socket,
+                    rtp_remote: (client_ip_addr, client_rtp_port).into(),
+                    rtcp_remote: (client_ip_addr, client_rtcp_port).into(),
                 })
             }
             rtsp::Lower::Tcp => {
-                let (rtp_channel, rtcp_channel) = match rtsp_transport.interleaved_channel()? {
+                let (rtp_channel, rtcp_channel) = match rtsp_transport
+                    .interleaved_channel()
+                    .ok_or(SessionSetupError::DestinationInvalid)?
+                {
                     rtsp::Channel::Single(rtp_channel) => (*rtp_channel, rtp_channel + 1),
                     rtsp::Channel::Range(rtp_channel, rtcp_channel) => {
                         (*rtp_channel, *rtcp_channel)
@@ -109,20 +222,121 @@ impl SessionSetupTarget {
             }
         })
     }
+
+    /// Server side RTP and RTCP port (only if sending over UDP).
+    pub fn server_port(&self) -> Option<rtsp::Port> {
+        match self {
+            SessionSetupTarget::RtpUdp(target) => {
+                let rtp_port = target.rtp_socket.local_addr().ok()?.port();
+                let rtcp_port = target.rtcp_socket.local_addr().ok()?.port();
+                Some(rtsp::Port::Range(rtp_port, rtcp_port))
+            }
+            SessionSetupTarget::RtpTcp(_) => None,
+            SessionSetupTarget::RtpMulticast(_) => None,
+        }
+    }
+
+    /// Channel on which the client sends RTCP (only if interleaved).
+    pub fn rtcp_channel(&self) -> Option<u8> {
+        match self {
+            SessionSetupTarget::RtpTcp(target) => Some(target.rtcp_channel),
+            SessionSetupTarget::RtpUdp(_) | SessionSetupTarget::RtpMulticast(_) => None,
+        }
+    }
+
+    /// Receive an RTCP packet from the client. Only clients that receive RTP
+    /// over UDP send RTCP to us directly, for other targets this never
+    /// completes. Interleaved RTCP arrives through the connection instead.
+    pub async fn recv_rtcp(&self) -> Result<Vec<u8>, io::Error> {
+        match self {
+            SessionSetupTarget::RtpUdp(target) => {
+                let mut buf = vec![0; Self::MAX_RTCP_PACKET_SIZE];
+                loop {
+                    let (len, addr) = target.rtcp_socket.recv_from(&mut buf).await?;
+                    // Anyone can send packets to the socket, but only the client
+                    // matters.
+                    if addr.ip() == target.rtcp_remote.ip() {
+                        buf.truncate(len);
+                        return Ok(buf);
+                    }
+                }
+            }
+            SessionSetupTarget::RtpTcp(_) | SessionSetupTarget::RtpMulticast(_) => {
+                future::pending().await
+            }
+        }
+    }
+
+    /// Send a single muxed RTP or RTCP buffer to the client. `sync_point`
+    /// tells whether the client can pick up the stream from this buffer on,
+    /// which matters when interleaved media has to be dropped.
+    pub async fn send(&self, buf: video::RtpBuf, sync_point: bool) -> Result<(), io::Error> {
+        match self {
+            SessionSetupTarget::RtpUdp(target) => {
+                match buf {
+                    video::RtpBuf::Rtp(payload) => {
+                        target
+                            .rtp_socket
+                            .send_to(&payload, target.rtp_remote)
+                            .await?
+                    }
+                    video::RtpBuf::Rtcp(payload) => {
+                        target
+                            .rtcp_socket
+                            .send_to(&payload, target.rtcp_remote)
+                            .await?
+                    }
+                };
+                Ok(())
+            }
+            SessionSetupTarget::RtpTcp(target) => {
+                let (channel, payload) = match buf {
+                    video::RtpBuf::Rtp(payload) => (target.rtp_channel, payload),
+                    video::RtpBuf::Rtcp(payload) => (target.rtcp_channel, payload),
+                };
+                target
+                    .sender
+                    .send(channel, payload, sync_point)
+                    .await
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+            }
+            SessionSetupTarget::RtpMulticast(target) => {
+                match buf {
+                    video::RtpBuf::Rtp(payload) => {
+                        target
+                            .socket
+                            .send_to(&payload, target.group.rtp_addr())
+                            .await?
+                    }
+                    video::RtpBuf::Rtcp(payload) => {
+                        target
+                            .socket
+                            .send_to(&payload, target.group.rtcp_addr())
+                            .await?
+                    }
+                };
+                Ok(())
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum SessionSetupError {
+    TrackNotFound,
     TransportNotSupported,
     DestinationInvalid,
+    Io(io::Error),
     Media(video::Error),
 }
 
 impl fmt::Display for SessionSetupError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            SessionSetupError::TrackNotFound => write!(f, "track not found"),
             SessionSetupError::TransportNotSupported => write!(f, "transport not supported"),
             SessionSetupError::DestinationInvalid => write!(f, "destination invalid"),
+            SessionSetupError::Io(error) => write!(f, "io error: {}", error),
             SessionSetupError::Media(error) => write!(f, "media error: {}", error),
         }
     }
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::metrics::PathMetrics;
+
+/// Counters for what a session sent to its client, and how well the client
+/// received it. These are updated by the session worker and can be read from
+/// anywhere. Everything sent is counted for the path of the session as well.
+pub struct SessionStats {
+    started: Instant,
+    packets_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    /// Quality of every track the client reported on, by track.
+    quality: Mutex<BTreeMap<usize, Quality>>,
+    path_metrics: Arc<PathMetrics>,
+}
+
+/// Reception quality as reported by the client in its last receiver report.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Quality {
+    /// Fraction of packets lost since the previous report, out of 256.
+    pub fraction_lost: u8,
+    /// Total number of packets lost.
+    pub packets_lost: u64,
+    /// Interarrival jitter, in RTP timestamp units.
+    pub jitter: u32,
+    pub round_trip_time: Option<Duration>,
+}
+
+impl SessionStats {
+    pub fn new(path_metrics: Arc<PathMetrics>) -> Self {
+        Self {
+            started: Instant::now(),
+            packets_sent: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            quality: Mutex::new(BTreeMap::new()),
+            path_metrics,
+        }
+    }
+
+    /// Count a single RTP packet sent to the client.
+    pub fn record_sent(&self, len: usize) {
+        self.packets_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(len as u64, Ordering::Relaxed);
+        self.path_metrics.packets_sent.inc();
+        self.path_metrics.bytes_sent.add(len as u64);
+    }
+
+    pub fn record_mux_failure(&self) {
+        self.path_metrics.mux_failures.inc();
+    }
+
+    pub fn record_quality(&self, track: usize, quality: Quality) {
+        self.quality
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(track, quality);
+    }
+
+    /// Reception quality of the session as a whole, or `None` if the client
+    /// has not reported on any of its tracks yet. Losses are added up, for
+    /// everything else the worst track counts.
+    pub fn quality(&self) -> Option<Quality> {
+        self.quality
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .values()
+            .copied()
+            .reduce(|total, quality| Quality {
+                fraction_lost: total.fraction_lost.max(quality.fraction_lost),
+                packets_lost: total.packets_lost + quality.packets_lost,
+                jitter: total.jitter.max(quality.jitter),
+                round_trip_time: total.round_trip_time.max(quality.round_trip_time),
+            })
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn packets_sent(&self) -> u64 {
+        self.packets_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Average bitrate (in bits per second) since the session started.
+    pub fn bitrate(&self) -> u64 {
+        let uptime = self.uptime().as_secs_f64();
+        if uptime > 0.0 {
+            ((self.bytes_sent() * 8) as f64 / uptime) as u64
+        } else {
+            0
+        }
+    }
+}
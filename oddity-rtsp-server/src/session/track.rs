@@ -0,0 +1,119 @@
+use std::io;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use video_rs as video;
+
+use crate::media;
+use crate::media::rtcp;
+use crate::media::rtp::{self, Rewriter, SharedRtpBuf};
+use crate::media::video::packet::Packet;
+use crate::media::video::rtp_muxer::{self, RtpMuxer};
+use crate::session::setup::{SessionSetup, SessionSetupTarget};
+use crate::session::stats::{Quality, SessionStats};
+
+/// A single track of a session, with its own target. Packets are muxed by
+/// the source and rewritten for the client of the track, except for
+/// video-on-demand sessions which have a muxer per track since they read the
+/// file by themselves.
+pub struct Track {
+    pub id: usize,
+    pub stream_index: usize,
+    pub control: String,
+    pub target: SessionSetupTarget,
+    pub need_stream_state: bool,
+    rewriter: Rewriter,
+    // This is only `None` while a packet is being muxed, see `Track::mux`, or
+    // if the session is not a video-on-demand session.
+    muxer: Option<RtpMuxer>,
+    stats: Arc<SessionStats>,
+}
+
+impl Track {
+    pub fn new(setup: SessionSetup, stats: Arc<SessionStats>) -> Self {
+        Self {
+            id: setup.track,
+            stream_index: setup.stream_index,
+            control: setup.control,
+            target: setup.rtp_target,
+            need_stream_state: false,
+            rewriter: Rewriter::new(),
+            muxer: setup.rtp_muxer,
+            stats,
+        }
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        self.rewriter.ssrc()
+    }
+
+    /// Send a single RTP or RTCP buffer of the given epoch to the client,
+    /// rewritten for this track.
+    pub async fn send(&mut self, epoch: u64, buf: &SharedRtpBuf) -> Result<(), io::Error> {
+        let sync_point = match buf {
+            // Audio can be picked up again at any packet, video only at a
+            // keyframe.
+            SharedRtpBuf::Rtp(payload) => {
+                !media::track::is_video(self.id) || rtp::starts_h264_keyframe(payload)
+            }
+            SharedRtpBuf::Rtcp(_) => true,
+        };
+        let buf = match self.rewriter.rewrite(epoch, buf) {
+            Some(buf) => buf,
+            None => return Ok(()),
+        };
+        let rtp_len = match &buf {
+            video::RtpBuf::Rtp(payload) => Some(payload.len()),
+            video::RtpBuf::Rtcp(_) => None,
+        };
+        self.target.send(buf, sync_point).await?;
+        if let Some(rtp_len) = rtp_len {
+            self.stats.record_sent(rtp_len);
+        }
+        Ok(())
+    }
+
+    /// Take note of a reception report of the client about this track.
+    pub fn record_report(&self, block: &rtcp::ReportBlock, arrival: SystemTime) {
+        self.stats.record_quality(
+            self.id,
+            Quality {
+                fraction_lost: block.fraction_lost,
+                packets_lost: block.packets_lost.max(0) as u64,
+                jitter: block.jitter,
+                round_trip_time: block.round_trip_time(arrival),
+            },
+        );
+    }
+
+    /// Mux a packet read by the session itself. Only tracks of
+    /// video-on-demand sessions have a muxer.
+    pub async fn mux(&mut self, packet: Packet) -> Result<Vec<SharedRtpBuf>, video::Error> {
+        let muxer = self.muxer.take().expect(
+            "only video-on-demand tracks mux, and the muxer is put back after every packet",
+        );
+        let (muxer, bufs) = rtp_muxer::muxed(muxer, packet).await;
+        self.muxer = Some(muxer);
+        match bufs {
+            Ok(bufs) => Ok(bufs.into_iter().map(SharedRtpBuf::from).collect()),
+            Err(err) => {
+                self.stats.record_mux_failure();
+                Err(err)
+            }
+        }
+    }
+
+    /// Stream state of the track, if the client were to be sent the given
+    /// packets of the given epoch next.
+    pub fn stream_state(&self, epoch: u64, bufs: &[SharedRtpBuf]) -> media::StreamState {
+        self.rewriter.stream_state(epoch, bufs)
+    }
+
+    pub async fn finish(mut self) {
+        if let Some(muxer) = self.muxer.take() {
+            // Throw away possible last RTP buffer (we don't care about
+            // it since this is real-time and there's no "trailer".
+            let _ = rtp_muxer::finish(muxer).await;
+        }
+    }
+}
@@ -1,28 +1,81 @@
 use oddity_rtsp_protocol as rtsp;
 
-pub fn resolve_transport(rtsp_transport: &rtsp::Transport) -> rtsp::Transport {
-    if rtsp_transport.interleaved_channel().is_some() {
-        rtsp_transport.clone()
-    } else {
-        // Use default channels 0 and 1 if client did not specify preferred
-        // interleaved channels.
-        rtsp_transport
-            .clone()
-            .with_parameter(rtsp::Parameter::Interleaved(rtsp::Channel::Range(0, 1)))
+use crate::net::udp::MulticastGroup;
+
+/// Determine the lower protocol of the transport. RFC 2326 Section 12.39
+/// specifies that the default lower protocol is UDP. We make one exception:
+/// if the client did specify interleaved channels, we assume it meant TCP.
+pub fn lower_protocol(rtsp_transport: &rtsp::Transport) -> rtsp::Lower {
+    match rtsp_transport.lower_protocol() {
+        Some(lower) => lower.clone(),
+        None if rtsp_transport.interleaved_channel().is_some() => rtsp::Lower::Tcp,
+        None => rtsp::Lower::Udp,
+    }
+}
+
+/// Whether or not the client asked for multicast delivery.
+pub fn is_multicast(rtsp_transport: &rtsp::Transport) -> bool {
+    rtsp_transport
+        .parameters_iter()
+        .any(|parameter| matches!(parameter, rtsp::Parameter::Multicast))
+}
+
+/// Whether or not the client wants the media interleaved on the RTSP
+/// connection.
+pub fn is_interleaved(rtsp_transport: &rtsp::Transport) -> bool {
+    matches!(lower_protocol(rtsp_transport), rtsp::Lower::Tcp) && !is_multicast(rtsp_transport)
+}
+
+/// Whether or not the client wants to push media to the server rather than
+/// receive it, see [`crate::publish`].
+pub fn is_record(rtsp_transport: &rtsp::Transport) -> bool {
+    rtsp_transport
+        .parameters_iter()
+        .any(|parameter| matches!(parameter, rtsp::Parameter::Mode(rtsp::Method::Record)))
+}
+
+pub fn resolve_transport(rtsp_transport: &rtsp::Transport, track: usize) -> rtsp::Transport {
+    match lower_protocol(rtsp_transport) {
+        rtsp::Lower::Tcp if rtsp_transport.interleaved_channel().is_none() => {
+            // Use default channels if client did not specify preferred interleaved
+            // channels. Each track gets its own pair: 0 and 1 for the first track,
+            // 2 and 3 for the second and so on.
+            let rtp_channel = (track * 2) as u8;
+            rtsp_transport
+                .clone()
+                .with_parameter(rtsp::Parameter::Interleaved(rtsp::Channel::Range(
+                    rtp_channel,
+                    rtp_channel + 1,
+                )))
+        }
+        _ => rtsp_transport.clone(),
     }
 }
 
+/// Multicast transport parameters are dictated by the server, so whatever the
+/// client proposed is replaced with the group the source is distributed on.
+pub fn resolve_multicast_transport(group: &MulticastGroup) -> rtsp::Transport {
+    rtsp::Transport::new().with_parameters([
+        rtsp::Parameter::Multicast,
+        rtsp::Parameter::Destination(group.addr),
+        rtsp::Parameter::Port(rtsp::Port::Range(group.port, group.port + 1)),
+        rtsp::Parameter::Ttl(group.ttl as usize),
+    ])
+}
+
 pub fn is_supported(transport: &rtsp::Transport) -> bool {
     return transport
         .lower_protocol()
         .map(is_lower_protocol_supported)
         .unwrap_or(true)
-        && transport.parameters_iter().all(is_parameter_supported);
+        && transport.parameters_iter().all(is_parameter_supported)
+        // Multicast can only be delivered over UDP.
+        && !(is_multicast(transport) && lower_protocol(transport) == rtsp::Lower::Tcp);
 }
 
 fn is_lower_protocol_supported(lower: &rtsp::Lower) -> bool {
     match lower {
-        rtsp::Lower::Udp => false,
+        rtsp::Lower::Udp => true,
         rtsp::Lower::Tcp => true,
     }
 }
@@ -31,22 +84,28 @@ fn is_parameter_supported(parameter: &rtsp::Parameter) -> bool {
     /*
       Supported parameters are:
       - `unicast`
+      - `multicast` (if the source has a multicast group)
+      - `destination` (if it matches the client address)
       - `interleaved`
-      - `mode` (if value is "PLAY")
+      - `ttl`, `layers` and `port` (ignored, the server picks these)
+      - `client_port`
+      - `append` (only used when publishing, which we always do live)
+      - `mode` (if value is "PLAY" or "RECORD")
     */
     match parameter {
         rtsp::Parameter::Unicast => true,
-        rtsp::Parameter::Multicast => false, // Multicast not supported
-        rtsp::Parameter::Destination(_) => false, // UDP not supported
+        rtsp::Parameter::Multicast => true, // Checked against source during setup
+        rtsp::Parameter::Destination(_) => true, // Checked against client address during setup
         rtsp::Parameter::Interleaved(_) => true,
-        rtsp::Parameter::Append => false,    // RECORD not supported
-        rtsp::Parameter::Ttl(_) => false,    // Multicast not supported
-        rtsp::Parameter::Layers(_) => false, // Multicast not supported
-        rtsp::Parameter::Port(_) => false,   // Multicast not supported
-        rtsp::Parameter::ClientPort(_) => false, // UDP not supported
+        rtsp::Parameter::Append => true,
+        rtsp::Parameter::Ttl(_) => true,
+        rtsp::Parameter::Layers(_) => true,
+        rtsp::Parameter::Port(_) => true,
+        rtsp::Parameter::ClientPort(_) => true,
         rtsp::Parameter::ServerPort(_) => false, // Client cannot choose server ports
-        rtsp::Parameter::Ssrc(_) => false,   // Client cannot choose ssrc
+        rtsp::Parameter::Ssrc(_) => false,       // Client cannot choose ssrc
         rtsp::Parameter::Mode(rtsp::Method::Play) => true,
-        rtsp::Parameter::Mode(_) => false, // Only PLAY is supported for session.
+        rtsp::Parameter::Mode(rtsp::Method::Record) => true, // Only for publishing
+        rtsp::Parameter::Mode(_) => false,
     }
 }
@@ -0,0 +1,171 @@
+use std::sync::{Arc, Mutex};
+
+use crate::media::video::reader::StreamPacket;
+
+pub type SharedGopCache = Arc<Mutex<GopCache>>;
+
+/// Packets of all streams since the last keyframe of the video. Sessions that
+/// start playing get these first, so that the client can show a picture right
+/// away instead of waiting for the next keyframe.
+///
+/// The source numbers its packets as it pushes them, and broadcasts them
+/// after letting go of the lock. This way a session can tell which of the
+/// packets it receives from the source it already got from the cache, see
+/// [`GopCache::start`].
+pub struct GopCache {
+    packets: Vec<StreamPacket>,
+    /// Whether or not the packets since the last keyframe are all there.
+    complete: bool,
+    /// Number of the next packet that is pushed.
+    next_number: u64,
+}
+
+impl GopCache {
+    /// Sources with very long keyframe intervals are not cached, so that the
+    /// cache cannot grow without limit.
+    const MAX_PACKETS: usize = 2048;
+
+    pub fn new() -> Self {
+        Self {
+            packets: Vec::new(),
+            complete: false,
+            next_number: 0,
+        }
+    }
+
+    /// Number the packet and cache it if it is part of the current group of
+    /// pictures.
+    pub fn push(&mut self, packet: &mut StreamPacket) {
+        packet.number = self.next_number;
+        self.next_number += 1;
+        if packet.keyframe {
+            self.packets.clear();
+            self.complete = true;
+        }
+        if !self.complete {
+            return;
+        }
+        if self.packets.len() >= Self::MAX_PACKETS {
+            tracing::debug!("keyframe interval too long, not caching until next keyframe");
+            self.clear();
+            return;
+        }
+        self.packets.push(packet.clone());
+    }
+
+    /// Throw away the cache, for when the source is restarted. Packets keep
+    /// being numbered where they left off.
+    pub fn clear(&mut self) {
+        self.packets.clear();
+        self.complete = false;
+    }
+
+    /// Packets since the last keyframe, for a session that starts playing,
+    /// and the number of the first packet that comes after them. The session
+    /// skips any packet it receives from the source with a lower number, since
+    /// it is either cached or came before the keyframe. Returns no packets if
+    /// there is no keyframe in the cache.
+    pub fn start(&self) -> (Vec<StreamPacket>, u64) {
+        if !self.complete {
+            return (Vec::new(), self.next_number);
+        }
+        (self.packets.clone(), self.next_number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::media::video::reader::StreamPacket;
+
+    use super::GopCache;
+
+    fn packet(keyframe: bool) -> StreamPacket {
+        StreamPacket {
+            track: 0,
+            bufs: Vec::new(),
+            keyframe,
+            epoch: 0,
+            number: 0,
+        }
+    }
+
+    fn push(cache: &mut GopCache, keyframe: bool) -> u64 {
+        let mut packet = packet(keyframe);
+        cache.push(&mut packet);
+        packet.number
+    }
+
+    fn numbers(packets: &[StreamPacket]) -> Vec<u64> {
+        packets.iter().map(|packet| packet.number).collect()
+    }
+
+    #[test]
+    fn packets_are_numbered() {
+        let mut cache = GopCache::new();
+        assert_eq!(push(&mut cache, false), 0);
+        assert_eq!(push(&mut cache, true), 1);
+        assert_eq!(push(&mut cache, false), 2);
+    }
+
+    #[test]
+    fn nothing_cached_without_keyframe() {
+        let mut cache = GopCache::new();
+        push(&mut cache, false);
+        push(&mut cache, false);
+        let (packets, next_number) = cache.start();
+        assert!(packets.is_empty());
+        assert_eq!(next_number, 2);
+    }
+
+    #[test]
+    fn starts_at_keyframe() {
+        let mut cache = GopCache::new();
+        push(&mut cache, false);
+        push(&mut cache, true);
+        push(&mut cache, false);
+        let (packets, next_number) = cache.start();
+        assert_eq!(numbers(&packets), vec![1, 2]);
+        assert_eq!(next_number, 3);
+    }
+
+    #[test]
+    fn keyframe_resets_cache() {
+        let mut cache = GopCache::new();
+        push(&mut cache, true);
+        push(&mut cache, false);
+        push(&mut cache, true);
+        push(&mut cache, false);
+        let (packets, next_number) = cache.start();
+        assert_eq!(numbers(&packets), vec![2, 3]);
+        assert_eq!(next_number, 4);
+    }
+
+    #[test]
+    fn clear_keeps_numbering() {
+        let mut cache = GopCache::new();
+        push(&mut cache, true);
+        push(&mut cache, false);
+        cache.clear();
+        let (packets, next_number) = cache.start();
+        assert!(packets.is_empty());
+        assert_eq!(next_number, 2);
+        // After a restart, the cache waits for the next keyframe.
+        push(&mut cache, false);
+        assert!(cache.start().0.is_empty());
+        push(&mut cache, true);
+        assert_eq!(numbers(&cache.start().0), vec![3]);
+    }
+
+    #[test]
+    fn long_keyframe_interval_not_cached() {
+        let mut cache = GopCache::new();
+        push(&mut cache, true);
+        for _ in 0..GopCache::MAX_PACKETS {
+            push(&mut cache, false);
+        }
+        assert!(cache.start().0.is_empty());
+        push(&mut cache, true);
+        assert_eq!(cache.start().0.len(), 1);
+    }
+}
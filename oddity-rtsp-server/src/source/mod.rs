@@ -1,50 +1,141 @@
+pub mod gop_cache;
+pub mod reconnect;
 pub mod source_manager;
+pub mod watchdog;
 
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time;
 
+use futures::future::{BoxFuture, FutureExt};
+
 use tokio::select;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc;
-use tokio::time::timeout;
+use tokio::sync::watch;
+use tokio::time::{interval, interval_at, sleep, Instant};
 
 use video_rs as video;
 
-use crate::media::video::reader::StreamReader;
+use crate::media::video::reader::{self, StreamPacket, StreamReader};
 use crate::media::{self, MediaDescriptor};
+use crate::metrics::{self, PathMetrics};
+use crate::net::udp::MulticastGroup;
 use crate::runtime::task_manager::{Task, TaskContext};
 use crate::runtime::Runtime;
+use crate::source::gop_cache::{GopCache, SharedGopCache};
+use crate::source::reconnect::ReconnectPolicy;
+use crate::source::watchdog::Watchdog;
 
+/// Something that happened to a source. The source is identified by its id
+/// as well, since a source that is on its way out may still send these after
+/// another source took its path.
 pub enum SourceState {
-    Stopped(SourcePath),
+    /// The stream started over, possibly with different media.
+    Reset(SourcePath, SourceId, media::MediaInfo),
+    Stopped(SourcePath, SourceId),
+}
+
+/// Identifies a single source for as long as the server runs, unlike its
+/// path, which can be taken by another source once it is removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceId(u64);
+
+impl SourceId {
+    fn generate() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for SourceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
 pub type SourceStateTx = mpsc::UnboundedSender<SourceState>;
 pub type SourceStateRx = mpsc::UnboundedReceiver<SourceState>;
 
-pub type SourceMediaInfoTx = broadcast::Sender<media::MediaInfo>;
-pub type SourceMediaInfoRx = broadcast::Receiver<media::MediaInfo>;
+/// Media information of the source, or nothing if the source is not live
+/// and cannot tell.
+pub type SourceMediaInfoTx = broadcast::Sender<Option<media::MediaInfo>>;
+pub type SourceMediaInfoRx = broadcast::Receiver<Option<media::MediaInfo>>;
 
 pub type SourceResetTx = broadcast::Sender<media::MediaInfo>;
 pub type SourceResetRx = broadcast::Receiver<media::MediaInfo>;
 
-pub type SourcePacketTx = broadcast::Sender<media::Packet>;
-pub type SourcePacketRx = broadcast::Receiver<media::Packet>;
+pub type SourcePacketTx = broadcast::Sender<StreamPacket>;
+pub type SourcePacketRx = broadcast::Receiver<StreamPacket>;
+
+/// What the source is currently doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceStatus {
+    Connecting,
+    /// Connected and sending packets.
+    Live,
+    /// Connected, but not sending packets.
+    Stalled,
+    /// Waiting before trying to connect again.
+    Retrying,
+    /// Gave up connecting after too many attempts. The source tries again
+    /// when someone asks for it.
+    Failed,
+    /// On-demand source that is not connected since nobody is watching.
+    Idle,
+}
+
+impl fmt::Display for SourceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceStatus::Connecting => write!(f, "connecting"),
+            SourceStatus::Live => write!(f, "live"),
+            SourceStatus::Stalled => write!(f, "stalled"),
+            SourceStatus::Retrying => write!(f, "retrying"),
+            SourceStatus::Failed => write!(f, "failed"),
+            SourceStatus::Idle => write!(f, "idle"),
+        }
+    }
+}
+
+pub type SourceStatusTx = watch::Sender<SourceStatus>;
+pub type SourceStatusRx = watch::Receiver<SourceStatus>;
 
 pub enum SourceControlMessage {
     StreamInfo,
+    /// Someone is interested in the source, so it should connect if it is
+    /// idle or gave up before.
+    Wake,
 }
 
 pub type SourceControlTx = mpsc::UnboundedSender<SourceControlMessage>;
 pub type SourceControlRx = mpsc::UnboundedReceiver<SourceControlMessage>;
 
 pub struct Source {
+    pub id: SourceId,
     pub name: String,
     pub path: SourcePath,
     pub descriptor: MediaDescriptor,
+    /// Used in order whenever `descriptor` is unavailable, until it recovers.
+    pub fallbacks: Vec<MediaDescriptor>,
+    /// How often `descriptor` is checked while one of the fallbacks is used.
+    pub failback_interval: time::Duration,
+    pub multicast_group: Option<MulticastGroup>,
+    pub vod: bool,
+    pub reconnect: ReconnectPolicy,
+    /// If set, the stream is restarted when it stalls for this long, see
+    /// [`Watchdog`].
+    pub stall_timeout: Option<time::Duration>,
+    /// If set, the source only connects while someone is watching, and
+    /// disconnects after being idle for this long.
+    pub on_demand: Option<time::Duration>,
     control_tx: SourceControlTx,
     media_info_tx: SourceMediaInfoTx,
     reset_tx: SourceResetTx,
     packet_tx: SourcePacketTx,
+    gop_cache: SharedGopCache,
+    status_rx: SourceStatusRx,
     worker: Task,
 }
 
@@ -57,140 +148,309 @@ impl Source {
     /// terribly overloaded/broken.
     const MAX_QUEUED_PACKETS: usize = 1024;
 
-    /// Number of seconds between retries.
-    const RETRY_DELAY_SECS: u64 = 60;
+    /// How often a source checks whether it stalled, and if it is on-demand,
+    /// whether anyone is still watching.
+    const CHECK_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+    /// Sources that are connected but have not sent anything for this long
+    /// are reported as stalled.
+    const STALLED_AFTER: time::Duration = time::Duration::from_secs(5);
+
+    /// Checking the primary descriptor every minute switches back soon enough,
+    /// without opening a broken stream over and over.
+    pub const DEFAULT_FAILBACK_INTERVAL: time::Duration = time::Duration::from_secs(60);
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn start(
         name: &str,
         path: SourcePath,
         descriptor: MediaDescriptor,
+        fallbacks: Vec<MediaDescriptor>,
+        failback_interval: time::Duration,
+        multicast_group: Option<MulticastGroup>,
+        vod: bool,
+        reconnect: ReconnectPolicy,
+        stall_timeout: Option<time::Duration>,
+        on_demand: Option<time::Duration>,
         state_tx: SourceStateTx,
         runtime: &Runtime,
     ) -> Result<Self, video::Error> {
         let path = normalize_path(path);
+        let id = SourceId::generate();
 
         let (control_tx, control_rx) = mpsc::unbounded_channel();
         let (media_info_tx, _) = broadcast::channel(Self::MAX_QUEUED_INFO);
         let (reset_tx, _) = broadcast::channel(Self::MAX_QUEUED_INFO);
         let (packet_tx, _) = broadcast::channel(Self::MAX_QUEUED_PACKETS);
-
-        tracing::trace!(name, %path, "starting source");
-        let worker = runtime
-            .task()
-            .spawn({
-                let path = path.clone();
-                let descriptor = descriptor.clone();
-                let media_info_tx = media_info_tx.clone();
-                let reset_tx = reset_tx.clone();
-                let packet_tx = packet_tx.clone();
-                move |task_context| {
-                    Self::run(
-                        path,
-                        descriptor,
-                        control_rx,
-                        state_tx,
-                        media_info_tx,
-                        reset_tx,
-                        packet_tx,
-                        task_context,
-                    )
-                }
-            })
-            .await;
+        let (status_tx, status_rx) = watch::channel(SourceStatus::Connecting);
+        let gop_cache = Arc::new(Mutex::new(GopCache::new()));
+
+        tracing::trace!(name, %path, vod, on_demand = on_demand.is_some(), "starting source");
+        let worker = if vod {
+            runtime
+                .task()
+                .spawn({
+                    let path = path.clone();
+                    let descriptor = descriptor.clone();
+                    let media_info_tx = media_info_tx.clone();
+                    move |task_context| {
+                        Self::run_vod(
+                            path,
+                            id,
+                            descriptor,
+                            control_rx,
+                            state_tx,
+                            status_tx,
+                            media_info_tx,
+                            task_context,
+                        )
+                    }
+                })
+                .await
+        } else {
+            runtime
+                .task()
+                .spawn({
+                    let path = path.clone();
+                    let descriptors = std::iter::once(descriptor.clone())
+                        .chain(fallbacks.iter().cloned())
+                        .collect();
+                    let media_info_tx = media_info_tx.clone();
+                    let reset_tx = reset_tx.clone();
+                    let packet_tx = packet_tx.clone();
+                    let gop_cache = gop_cache.clone();
+                    move |task_context| {
+                        Self::run(
+                            path,
+                            id,
+                            descriptors,
+                            failback_interval,
+                            reconnect,
+                            stall_timeout,
+                            on_demand,
+                            control_rx,
+                            state_tx,
+                            status_tx,
+                            media_info_tx,
+                            reset_tx,
+                            packet_tx,
+                            gop_cache,
+                            task_context,
+                        )
+                    }
+                })
+                .await
+        };
         tracing::trace!(name, %path, "started source");
 
         Ok(Self {
+            id,
             name: name.to_string(),
             path,
             descriptor,
+            fallbacks,
+            failback_interval,
+            multicast_group,
+            vod,
+            reconnect,
+            stall_timeout,
+            on_demand,
             control_tx,
             media_info_tx,
             reset_tx,
             packet_tx,
+            gop_cache,
+            status_rx,
             worker,
         })
     }
 
+    pub fn status(&self) -> SourceStatus {
+        *self.status_rx.borrow()
+    }
+
     pub async fn stop(&mut self) {
         tracing::trace!("sending stop signal to source");
         self.worker.stop().await;
         tracing::trace!("stopped source");
     }
 
+    /// Make the source connect if it is idle or gave up before. An on-demand
+    /// source disconnects again if nobody subscribes to it in time.
+    pub fn wake(&self) {
+        let _ = self.control_tx.send(SourceControlMessage::Wake);
+    }
+
+    /// Subscribe to the source. This wakes up the source, see
+    /// [`Source::wake`].
     pub fn delegate(&mut self) -> SourceDelegate {
+        self.wake();
         SourceDelegate {
+            path: self.path.clone(),
+            multicast_group: self.multicast_group,
+            descriptor: self.descriptor.clone(),
+            vod: self.vod,
             control_tx: self.control_tx.clone(),
             media_info_rx: self.media_info_tx.subscribe(),
             reset_rx: self.reset_tx.subscribe(),
             packet_rx: self.packet_tx.subscribe(),
+            gop_cache: self.gop_cache.clone(),
         }
     }
 
+    /// Reads the first of `descriptors` that works, and moves on to the next
+    /// one whenever it breaks. The first descriptor is the primary one: while
+    /// any other is used, the source switches back as soon as the primary one
+    /// recovers. If none of them works, the source retries according to its
+    /// reconnect policy.
     #[allow(clippy::too_many_arguments)]
     async fn run(
         path: SourcePath,
-        descriptor: MediaDescriptor,
+        id: SourceId,
+        descriptors: Vec<MediaDescriptor>,
+        failback_interval: time::Duration,
+        reconnect: ReconnectPolicy,
+        stall_timeout: Option<time::Duration>,
+        on_demand: Option<time::Duration>,
         mut control_rx: SourceControlRx,
         state_tx: SourceStateTx,
+        status_tx: SourceStatusTx,
         media_info_tx: SourceMediaInfoTx,
         reset_tx: SourceResetTx,
         packet_tx: SourcePacketTx,
+        gop_cache: SharedGopCache,
         mut task_context: TaskContext,
     ) {
-        let mut outer_stream_reader = match StreamReader::new(&descriptor).await {
-            Ok(stream_reader) => Some(stream_reader),
-            Err(err) => {
-                tracing::error!(
-                  %err, %descriptor,
-                  "failed to start stream",
-                );
-                None
-            }
-        };
+        let path_metrics = metrics::global().path(&path);
+        // On-demand sources do not connect until someone asks for them.
+        let mut idle = on_demand.is_some();
+        // Whether or not the stream broke, in which case listeners need to know
+        // that it started over once it is back.
+        let mut restarting = false;
+        // Number of times the stream started over, see `StreamPacket::epoch`.
+        let mut epoch = 0;
+        // Whether or not the media information was queried while the source was
+        // not connected. The query is answered once the stream is up.
+        let mut media_info_requested = false;
+        let mut check = interval(Self::CHECK_INTERVAL);
 
         'outer: loop {
-            let mut stream_reader = match outer_stream_reader {
-                Some(stream_reader) => stream_reader,
-                None => {
-                    'restart: loop {
-                        match StreamReader::new(&descriptor).await {
-                            Ok(new_stream_reader) => {
-                                // Send reset with new media information to listeners so they can
-                                // reset their muxers and continue playing.
-                                let _ = reset_tx.send(new_stream_reader.info.clone());
-
-                                tracing::info!(%path, "restarted stream");
-                                break new_stream_reader;
-                            }
-                            Err(err) => {
-                                tracing::error!(
-                                  %err, %descriptor, retry_delay=Self::RETRY_DELAY_SECS,
-                                  "failed to restart stream (waiting before retrying)",
-                                );
-                                // We want to wait some time before retrying. We wrap `wait_for_stop` in
-                                // a timeout to achieve this ...
-                                match timeout(
-                                    time::Duration::from_secs(Self::RETRY_DELAY_SECS),
-                                    task_context.wait_for_stop(),
-                                )
-                                .await
-                                {
-                                    Ok(()) => {
-                                        tracing::trace!(%path, "stopping source (during stream restart)");
-                                        // If `wait_for_stop` returns, we break out of the outer loop and stop ...
-                                        break 'outer;
-                                    }
-                                    Err(_) => {
-                                        // But if the timeout is reached, we simply restart this loop to try and
-                                        // see if we can get the stream reader to work this time.
-                                        continue 'restart;
-                                    }
-                                }
-                            }
+            if idle {
+                let _ = status_tx.send(SourceStatus::Idle);
+                tracing::trace!(%path, "source idle");
+                if !Self::wait_for_wake(
+                    &path,
+                    &mut control_rx,
+                    &mut task_context,
+                    &mut media_info_requested,
+                )
+                .await
+                {
+                    break 'outer;
+                }
+                idle = false;
+                tracing::info!(%path, "starting on-demand stream");
+            }
+
+            let mut failed_attempts = 0;
+            // Position of the descriptor that is read.
+            let (mut active, mut stream_reader) = loop {
+                let _ = status_tx.send(SourceStatus::Connecting);
+                if let Some(opened) = Self::open(&descriptors, &path_metrics).await {
+                    break opened;
+                }
+
+                failed_attempts += 1;
+                if media_info_requested {
+                    media_info_requested = false;
+                    let _ = media_info_tx.send(None);
+                }
+                if reconnect.is_exhausted(failed_attempts) {
+                    let _ = status_tx.send(SourceStatus::Failed);
+                    tracing::error!(
+                      %path, failed_attempts,
+                      "giving up on source (until someone asks for it again)",
+                    );
+                    if !Self::wait_for_wake(
+                        &path,
+                        &mut control_rx,
+                        &mut task_context,
+                        &mut media_info_requested,
+                    )
+                    .await
+                    {
+                        break 'outer;
+                    }
+                    failed_attempts = 0;
+                    continue;
+                }
+
+                let delay = reconnect.delay(failed_attempts);
+                let _ = status_tx.send(SourceStatus::Retrying);
+                tracing::error!(
+                  %path, failed_attempts, retry_delay = delay.as_secs_f64(),
+                  "failed to connect to source (waiting before retrying)",
+                );
+                // We want to wait some time before retrying, unless the source is
+                // stopped in the meantime. Whoever asks for the media information
+                // meanwhile is told that there is none, instead of having to wait.
+                let retry = sleep(delay);
+                tokio::pin!(retry);
+                loop {
+                    select! {
+                      // CANCEL SAFETY: The future is pinned outside of the loop, so it
+                      // is picked up again in the next iteration.
+                      _ = &mut retry => break,
+                      // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
+                      message = control_rx.recv() => {
+                        match message {
+                          Some(SourceControlMessage::StreamInfo) => {
+                            let _ = media_info_tx.send(None);
+                          },
+                          Some(SourceControlMessage::Wake) => {},
+                          None => {
+                            tracing::error!(%path, "source control channel broke unexpectedly");
+                            break 'outer;
+                          },
                         }
+                      },
+                      // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+                      _ = task_context.wait_for_stop() => {
+                        tracing::trace!(%path, "stopping source (while retrying)");
+                        break 'outer;
+                      },
                     }
                 }
             };
+            let _ = status_tx.send(SourceStatus::Live);
+
+            if restarting {
+                // Let listeners know that the stream started over, with the new
+                // media information.
+                epoch += 1;
+                let _ = reset_tx.send(stream_reader.info.clone());
+                let _ = state_tx.send(SourceState::Reset(
+                    path.clone(),
+                    id,
+                    stream_reader.info.clone(),
+                ));
+                path_metrics.source_restarts.inc();
+                tracing::info!(%path, "restarted stream");
+            }
+            if media_info_requested {
+                media_info_requested = false;
+                let _ = media_info_tx.send(Some(stream_reader.info.clone()));
+            }
+
+            let mut watchdog = Watchdog::new();
+            let mut stalled = false;
+            // Since when nobody is subscribed to the on-demand source.
+            let mut idle_since = None;
+            // While a fallback is used, the primary descriptor is opened every now and
+            // then in the background to see if it recovered.
+            let mut failback_check =
+                interval_at(Instant::now() + failback_interval, failback_interval);
+            let mut failback: Option<BoxFuture<'static, Result<StreamReader, video::Error>>> = None;
 
             'read: loop {
                 select! {
@@ -198,8 +458,19 @@ impl Source {
                   // internally which is cancel safe.
                   packet = stream_reader.read() => {
                     match packet {
-                      Some(Ok(packet)) => {
-                        let _ = packet_tx.send(packet.clone());
+                      Some(Ok(mut packet)) => {
+                        packet.epoch = epoch;
+                        watchdog.feed(&packet);
+                        if stalled && watchdog.stall(Self::STALLED_AFTER).is_none() {
+                          stalled = false;
+                          let _ = status_tx.send(SourceStatus::Live);
+                          tracing::info!(%path, "source is sending packets again");
+                        }
+                        gop_cache
+                          .lock()
+                          .unwrap_or_else(|poisoned| poisoned.into_inner())
+                          .push(&mut packet);
+                        let _ = packet_tx.send(packet);
                       },
                       Some(Err(err)) => {
                         tracing::error!(%path, %err, "failed to read video stream");
@@ -215,7 +486,10 @@ impl Source {
                   message = control_rx.recv() => {
                     match message {
                       Some(SourceControlMessage::StreamInfo) => {
-                        let _ = media_info_tx.send(stream_reader.info.clone());
+                        let _ = media_info_tx.send(Some(stream_reader.info.clone()));
+                      },
+                      Some(SourceControlMessage::Wake) => {
+                        idle_since = None;
                       },
                       None => {
                         tracing::error!(%path, "source control channel broke unexpectedly");
@@ -224,6 +498,70 @@ impl Source {
                       },
                     };
                   },
+                  // CANCEL SAFETY: `Interval::tick` is cancel safe.
+                  _ = check.tick() => {
+                    if !stalled {
+                      if let Some(stall) = watchdog.stall(Self::STALLED_AFTER) {
+                        stalled = true;
+                        let _ = status_tx.send(SourceStatus::Stalled);
+                        tracing::warn!(%path, %stall, "source stalled");
+                      }
+                    }
+                    if let Some(stall) = stall_timeout.and_then(|after| watchdog.stall(after)) {
+                      tracing::error!(%path, %stall, "source stalled for too long, restarting stream");
+                      path_metrics.source_stalls.inc();
+                      break 'read;
+                    }
+
+                    if let Some(idle_timeout) = on_demand {
+                      // Sessions hold on to a receiver for as long as they exist, and so
+                      // does anyone that is about to set one up.
+                      if packet_tx.receiver_count() > 0 {
+                        idle_since = None;
+                      } else {
+                        let since = *idle_since.get_or_insert_with(Instant::now);
+                        if since.elapsed() >= idle_timeout {
+                          tracing::info!(%path, "nobody watching, stopping on-demand stream");
+                          idle = true;
+                          break 'read;
+                        }
+                      }
+                    }
+                  },
+                  // CANCEL SAFETY: `Interval::tick` is cancel safe.
+                  _ = failback_check.tick(), if active > 0 && failback.is_none() => {
+                    tracing::trace!(%path, "checking if primary source recovered");
+                    let descriptor = descriptors[0].clone();
+                    let path_metrics = path_metrics.clone();
+                    failback = Some(
+                      async move { StreamReader::new(&descriptor, path_metrics).await }.boxed()
+                    );
+                  },
+                  // CANCEL SAFETY: The future is kept in `failback` until it completes, so
+                  // nothing is lost if this branch is cancelled.
+                  result = async { failback.as_mut().expect("checked by precondition").await },
+                    if failback.is_some() => {
+                    failback = None;
+                    match result {
+                      Ok(new_stream_reader) => {
+                        tracing::info!(%path, "primary source recovered, switching back");
+                        stream_reader.stop().await;
+                        stream_reader = new_stream_reader;
+                        active = 0;
+                        epoch += 1;
+                        watchdog = Watchdog::new();
+                        gop_cache
+                          .lock()
+                          .unwrap_or_else(|poisoned| poisoned.into_inner())
+                          .clear();
+                        let _ = reset_tx.send(stream_reader.info.clone());
+                        let _ = state_tx.send(SourceState::Reset(path.clone(), id, stream_reader.info.clone()));
+                      },
+                      Err(err) => {
+                        tracing::debug!(%path, %err, "primary source still unavailable");
+                      },
+                    }
+                  },
                   // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
                   _ = task_context.wait_for_stop() => {
                     tracing::trace!(%path, "stopping source");
@@ -237,33 +575,171 @@ impl Source {
             // one to stop and wait for it to do so.
             stream_reader.stop().await;
 
-            // Reset the outer stream reader so that it will be reinitialized during
-            // the next outer loop cycle.
-            outer_stream_reader = None;
+            // Packets of the old stream are of no use to sessions of the new one.
+            gop_cache
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clear();
+
+            // Unless it was stopped on purpose, the stream is restarted in the next
+            // outer loop cycle.
+            restarting = !idle;
+        }
+
+        let _ = state_tx.send(SourceState::Stopped(path, id));
+    }
+
+    /// Wait until someone asks for the source. Returns `false` if the source
+    /// is to stop instead.
+    async fn wait_for_wake(
+        path: &SourcePathRef,
+        control_rx: &mut SourceControlRx,
+        task_context: &mut TaskContext,
+        media_info_requested: &mut bool,
+    ) -> bool {
+        select! {
+          // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
+          message = control_rx.recv() => {
+            match message {
+              Some(SourceControlMessage::Wake) => true,
+              Some(SourceControlMessage::StreamInfo) => {
+                *media_info_requested = true;
+                true
+              },
+              None => {
+                tracing::error!(%path, "source control channel broke unexpectedly");
+                false
+              },
+            }
+          },
+          // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+          _ = task_context.wait_for_stop() => {
+            tracing::trace!(%path, "stopping source (while waiting)");
+            false
+          },
+        }
+    }
+
+    /// Open the first of the descriptors that works. Returns its position
+    /// along with the reader.
+    async fn open(
+        descriptors: &[MediaDescriptor],
+        path_metrics: &Arc<PathMetrics>,
+    ) -> Option<(usize, StreamReader)> {
+        for (index, descriptor) in descriptors.iter().enumerate() {
+            match StreamReader::new(descriptor, path_metrics.clone()).await {
+                Ok(stream_reader) => {
+                    if index > 0 {
+                        tracing::warn!(%descriptor, fallback = index, "using fallback source");
+                    }
+                    return Some((index, stream_reader));
+                }
+                Err(err) => {
+                    tracing::error!(%err, %descriptor, "failed to start stream");
+                }
+            }
+        }
+        None
+    }
+
+    /// Sessions of video-on-demand sources each read the file by themselves,
+    /// so the source itself only needs to provide the media information.
+    #[allow(clippy::too_many_arguments)]
+    async fn run_vod(
+        path: SourcePath,
+        id: SourceId,
+        descriptor: MediaDescriptor,
+        mut control_rx: SourceControlRx,
+        state_tx: SourceStateTx,
+        status_tx: SourceStatusTx,
+        media_info_tx: SourceMediaInfoTx,
+        mut task_context: TaskContext,
+    ) {
+        let media_info = reader::backend::make_reader_with_sane_settings(descriptor.clone())
+            .await
+            .and_then(|reader| media::MediaInfo::from_reader_best_streams(&reader));
+
+        let _ = status_tx.send(if media_info.is_ok() {
+            SourceStatus::Live
+        } else {
+            SourceStatus::Failed
+        });
+
+        match media_info {
+            Ok(media_info) => loop {
+                select! {
+                  // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
+                  message = control_rx.recv() => {
+                    match message {
+                      Some(SourceControlMessage::StreamInfo) => {
+                        let _ = media_info_tx.send(Some(media_info.clone()));
+                      },
+                      Some(SourceControlMessage::Wake) => {},
+                      None => {
+                        tracing::error!(%path, "source control channel broke unexpectedly");
+                        break;
+                      },
+                    };
+                  },
+                  // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+                  _ = task_context.wait_for_stop() => {
+                    tracing::trace!(%path, "stopping source");
+                    break;
+                  },
+                }
+            },
+            Err(err) => {
+                tracing::error!(%err, %descriptor, "failed to read file");
+            }
         }
 
-        let _ = state_tx.send(SourceState::Stopped(path));
+        let _ = state_tx.send(SourceState::Stopped(path, id));
     }
 }
 
 pub struct SourceDelegate {
+    path: SourcePath,
+    multicast_group: Option<MulticastGroup>,
+    descriptor: MediaDescriptor,
+    vod: bool,
     control_tx: SourceControlTx,
     media_info_rx: SourceMediaInfoRx,
     reset_rx: SourceResetRx,
     packet_rx: SourcePacketRx,
+    gop_cache: SharedGopCache,
 }
 
 impl SourceDelegate {
+    pub fn path(&self) -> &SourcePathRef {
+        &self.path
+    }
+
+    /// Multicast group the source is distributed on, if it was configured
+    /// to be.
+    pub fn multicast_group(&self) -> Option<&MulticastGroup> {
+        self.multicast_group.as_ref()
+    }
+
+    /// Whether or not sessions should read the source by themselves, see
+    /// [`crate::media::video::reader::VodReader`].
+    pub fn is_vod(&self) -> bool {
+        self.vod
+    }
+
+    pub fn descriptor(&self) -> &MediaDescriptor {
+        &self.descriptor
+    }
+
     pub async fn query_media_info(&mut self) -> Option<media::MediaInfo> {
         if let Ok(()) = self.control_tx.send(SourceControlMessage::StreamInfo) {
-            self.media_info_rx.recv().await.ok()
+            self.media_info_rx.recv().await.ok().flatten()
         } else {
             None
         }
     }
 
-    pub fn into_parts(self) -> (SourceResetRx, SourcePacketRx) {
-        (self.reset_rx, self.packet_rx)
+    pub fn into_parts(self) -> (SourceResetRx, SourcePacketRx, SharedGopCache) {
+        (self.reset_rx, self.packet_rx, self.gop_cache)
     }
 }
 
@@ -277,3 +753,12 @@ pub fn normalize_path(path: SourcePath) -> SourcePath {
         format!("/{}", &path)
     }
 }
+
+/// Whether or not the path is the given presentation path, or the path of
+/// one of its tracks.
+pub fn is_path_below(path: &SourcePathRef, presentation_path: &SourcePathRef) -> bool {
+    match path.strip_prefix(presentation_path) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
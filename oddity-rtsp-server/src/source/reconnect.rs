@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// How a source reconnects after its stream broke. The delay between
+/// attempts starts at `initial_delay` and is multiplied by `backoff` after
+/// every failed attempt, up to `max_delay`. Each delay is randomly made up to
+/// `jitter` (a fraction) shorter or longer, so that sources that broke at the
+/// same time do not all reconnect at the same time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub backoff: f64,
+    pub jitter: f64,
+    /// Number of failed attempts after which the source gives up, if any.
+    pub max_attempts: Option<u32>,
+}
+
+impl ReconnectPolicy {
+    /// Delay before the attempt that follows the given number of failed
+    /// attempts.
+    pub fn delay(&self, failed_attempts: u32) -> Duration {
+        let exponent = failed_attempts.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = (self.initial_delay.as_secs_f64() * self.backoff.powi(exponent))
+            .min(self.max_delay.as_secs_f64());
+        let jitter = if self.jitter > 0.0 {
+            rand::thread_rng().gen_range(-self.jitter..=self.jitter)
+        } else {
+            0.0
+        };
+        Duration::from_secs_f64((delay * (1.0 + jitter)).max(0.0))
+    }
+
+    /// Whether or not the source should give up after the given number of
+    /// failed attempts.
+    pub fn is_exhausted(&self, failed_attempts: u32) -> bool {
+        self.max_attempts
+            .map(|max_attempts| failed_attempts >= max_attempts)
+            .unwrap_or(false)
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            backoff: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::ReconnectPolicy;
+
+    const POLICY: ReconnectPolicy = ReconnectPolicy {
+        initial_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(10),
+        backoff: 2.0,
+        jitter: 0.0,
+        max_attempts: Some(5),
+    };
+
+    #[test]
+    fn delay_grows_by_backoff() {
+        assert_eq!(POLICY.delay(1), Duration::from_secs(1));
+        assert_eq!(POLICY.delay(2), Duration::from_secs(2));
+        assert_eq!(POLICY.delay(3), Duration::from_secs(4));
+        assert_eq!(POLICY.delay(4), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn delay_capped_at_max_delay() {
+        assert_eq!(POLICY.delay(5), Duration::from_secs(10));
+        assert_eq!(POLICY.delay(100), Duration::from_secs(10));
+        assert_eq!(POLICY.delay(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn delay_without_backoff() {
+        let policy = ReconnectPolicy {
+            backoff: 1.0,
+            ..POLICY
+        };
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(50), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn delay_jitter_within_bounds() {
+        let policy = ReconnectPolicy {
+            jitter: 0.5,
+            ..POLICY
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(2);
+            assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(3));
+        }
+    }
+
+    #[test]
+    fn exhausted_after_max_attempts() {
+        assert!(!POLICY.is_exhausted(0));
+        assert!(!POLICY.is_exhausted(4));
+        assert!(POLICY.is_exhausted(5));
+        assert!(POLICY.is_exhausted(6));
+    }
+
+    #[test]
+    fn never_exhausted_without_max_attempts() {
+        let policy = ReconnectPolicy {
+            max_attempts: None,
+            ..POLICY
+        };
+        assert!(!policy.is_exhausted(u32::MAX));
+    }
+}
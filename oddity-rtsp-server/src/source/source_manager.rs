@@ -2,6 +2,7 @@ use std::collections::{hash_map::Entry, HashMap};
 use std::error;
 use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::select;
 use tokio::sync::mpsc;
@@ -10,18 +11,104 @@ use tokio::sync::{Mutex, RwLock};
 use video_rs::Error as MediaError;
 
 use crate::media::sdp::{self, Sdp, SdpError};
-use crate::media::MediaDescriptor;
+use crate::media::{MediaDescriptor, MediaInfo};
+use crate::net::udp::MulticastGroup;
 use crate::runtime::task_manager::{Task, TaskContext};
 use crate::runtime::Runtime;
+use crate::source::reconnect::ReconnectPolicy;
 use crate::source::{
-    self, Source, SourceDelegate, SourcePath, SourcePathRef, SourceState, SourceStateRx,
-    SourceStateTx,
+    self, Source, SourceDelegate, SourceId, SourcePath, SourcePathRef, SourceState, SourceStateRx,
+    SourceStateTx, SourceStatus,
 };
 
 type SourceShared = Arc<Mutex<Source>>;
 type SourceMap = Arc<RwLock<HashMap<SourcePath, SourceShared>>>;
 
-type SourceDescriptionsCache = Arc<RwLock<HashMap<SourcePath, Sdp>>>;
+type SourceDescriptionsCache = Arc<RwLock<SourceDescriptions>>;
+
+/// Cached SDP of a source. Whenever the source starts over, the SDP is
+/// replaced and its version bumped, since the media may have changed.
+struct SourceDescription {
+    /// Only `None` if the SDP could not be created after the source started
+    /// over, in which case it is created on the next request.
+    sdp: Option<Sdp>,
+    version: usize,
+}
+
+/// Cached SDP of every source, by path.
+///
+/// Creating an SDP takes a while, and the source may start over in the
+/// meantime. Whoever creates one therefore remembers the version it saw
+/// first, and the SDP is only stored if that is still the current version.
+#[derive(Default)]
+struct SourceDescriptions(HashMap<SourcePath, SourceDescription>);
+
+impl SourceDescriptions {
+    /// Get the cached SDP, if any, and the current version, which is `None`
+    /// if the source was never described.
+    fn get(&self, path: &SourcePathRef) -> (Option<Sdp>, Option<usize>) {
+        match self.0.get(path) {
+            Some(description) => (description.sdp.clone(), Some(description.version)),
+            None => (None, None),
+        }
+    }
+
+    /// Store the SDP if the version is still the one that was seen before
+    /// it was created. Returns the stored SDP, which carries the version.
+    fn insert_if_current(
+        &mut self,
+        path: &SourcePathRef,
+        seen_version: Option<usize>,
+        sdp: Sdp,
+    ) -> Option<Sdp> {
+        let current_version = self.0.get(path).map(|description| description.version);
+        if current_version != seen_version {
+            return None;
+        }
+        let version = seen_version.unwrap_or(0);
+        let sdp = sdp.with_session_version(version);
+        self.0.insert(
+            path.into(),
+            SourceDescription {
+                sdp: Some(sdp.clone()),
+                version,
+            },
+        );
+        Some(sdp)
+    }
+
+    /// Forget the cached SDP of a source that started over and bump its
+    /// version. Returns the new version.
+    fn invalidate(&mut self, path: &SourcePathRef) -> usize {
+        let version = self
+            .0
+            .get(path)
+            .map(|description| description.version + 1)
+            .unwrap_or(0);
+        self.0
+            .insert(path.into(), SourceDescription { sdp: None, version });
+        version
+    }
+
+    fn remove(&mut self, path: &SourcePathRef) {
+        self.0.remove(path);
+    }
+}
+
+/// Snapshot of a registered source.
+pub struct SourceInfo {
+    pub name: String,
+    pub path: SourcePath,
+    pub descriptor: MediaDescriptor,
+    pub fallbacks: Vec<MediaDescriptor>,
+    pub failback_interval: Duration,
+    pub multicast_group: Option<MulticastGroup>,
+    pub vod: bool,
+    pub reconnect: ReconnectPolicy,
+    pub stall_timeout: Option<Duration>,
+    pub on_demand: Option<Duration>,
+    pub status: SourceStatus,
+}
 
 pub struct SourceManager {
     sources: SourceMap,
@@ -36,14 +123,22 @@ impl SourceManager {
         let sources = Arc::new(RwLock::new(HashMap::new()));
         let (source_state_tx, source_state_rx) = mpsc::unbounded_channel();
 
-        let source_descriptions_cache = Arc::new(RwLock::new(HashMap::new()));
+        let source_descriptions_cache = Arc::new(RwLock::new(SourceDescriptions::default()));
 
         tracing::trace!("starting source manager");
         let worker = runtime
             .task()
             .spawn({
                 let sources = sources.clone();
-                move |task_context| Self::run(sources.clone(), source_state_rx, task_context)
+                let source_descriptions_cache = source_descriptions_cache.clone();
+                move |task_context| {
+                    Self::run(
+                        sources.clone(),
+                        source_descriptions_cache.clone(),
+                        source_state_rx,
+                        task_context,
+                    )
+                }
             })
             .await;
         tracing::trace!("started source manager");
@@ -66,17 +161,40 @@ impl SourceManager {
         }
     }
 
+    /// Register the source and start it. The `fallbacks` are used in order
+    /// while `descriptor` is unavailable, which is checked every
+    /// `failback_interval` in the meantime. When none of them is available,
+    /// the source keeps trying according to `reconnect`. A stream that stalls for
+    /// `stall_timeout` is restarted. An `on_demand` source only starts
+    /// reading its stream once someone asks for it, and stops again after it
+    /// has been idle for the given time.
+    #[allow(clippy::too_many_arguments)]
     pub async fn register_and_start(
         &self,
         name: &str,
         path: SourcePath,
         descriptor: MediaDescriptor,
+        fallbacks: Vec<MediaDescriptor>,
+        failback_interval: Duration,
+        multicast_group: Option<MulticastGroup>,
+        vod: bool,
+        reconnect: ReconnectPolicy,
+        stall_timeout: Option<Duration>,
+        on_demand: Option<Duration>,
     ) -> Result<(), RegisterSourceError> {
         let path = source::normalize_path(path);
+        let published = descriptor.is_published();
         let source = Source::start(
             name,
             path.clone(),
             descriptor,
+            fallbacks,
+            failback_interval,
+            multicast_group,
+            vod,
+            reconnect,
+            stall_timeout,
+            on_demand,
             self.source_state_tx.clone(),
             self.runtime.as_ref(),
         )
@@ -86,26 +204,92 @@ impl SourceManager {
         if let Entry::Vacant(entry) = self.sources.write().await.entry(path.clone()) {
             let _ = entry.insert(Arc::new(Mutex::new(source)));
             tracing::trace!(name, %path, "registered and started source");
-            tracing::trace!("requesting SDP for source to prime cache");
         } else {
             tracing::error!(name, %path, "source with given path already registered");
             return Err(RegisterSourceError::AlreadyRegistered);
         }
 
-        self.describe(&path)
-            .await
-            .unwrap()
-            .map_err(RegisterSourceError::Sdp)?;
+        // A published source only knows its media once the publisher starts
+        // pushing, so its SDP is created on first request instead. The SDP of
+        // an on-demand source is kept while it is idle, so that describing it
+        // stays fast.
+        if !published {
+            tracing::trace!("requesting SDP for source to prime cache");
+            if let Some(Err(err)) = self.describe_cached(&path).await {
+                // A source that cannot be described is of no use to anyone.
+                self.remove(&path).await;
+                return Err(RegisterSourceError::Sdp(err));
+            }
+        }
         Ok(())
     }
 
-    pub async fn describe(&self, path: &SourcePathRef) -> Option<Result<Sdp, SdpError>> {
-        let cached_description = self
-            .source_descriptions_cache
+    /// Stop the source and forget about it. Sessions that are subscribed to
+    /// the source end when it stops.
+    pub async fn remove(&self, path: &SourcePathRef) -> bool {
+        let source = self.sources.write().await.remove(path);
+        self.source_descriptions_cache.write().await.remove(path);
+        if let Some(source) = source {
+            source.lock().await.stop().await;
+            tracing::trace!(path, "removed source");
+            true
+        } else {
+            tracing::trace!(path, "tried to remove source that does not exist");
+            false
+        }
+    }
+
+    pub async fn info(&self, path: &SourcePathRef) -> Option<SourceInfo> {
+        let source = self.sources.read().await.get(path).cloned()?;
+        let source = source.lock().await;
+        Some(SourceInfo {
+            name: source.name.clone(),
+            path: source.path.clone(),
+            descriptor: source.descriptor.clone(),
+            fallbacks: source.fallbacks.clone(),
+            failback_interval: source.failback_interval,
+            multicast_group: source.multicast_group,
+            vod: source.vod,
+            reconnect: source.reconnect,
+            stall_timeout: source.stall_timeout,
+            on_demand: source.on_demand,
+            status: source.status(),
+        })
+    }
+
+    /// Produce a snapshot of every registered source, ordered by path.
+    pub async fn list(&self) -> Vec<SourceInfo> {
+        let mut paths = self
+            .sources
             .read()
             .await
-            .get(path)
-            .cloned();
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut sources = Vec::with_capacity(paths.len());
+        for path in paths {
+            // The source may have been removed in the meantime.
+            if let Some(info) = self.info(&path).await {
+                sources.push(info);
+            }
+        }
+        sources
+    }
+
+    /// Get the SDP of the source. Whoever asks for it is likely to play the
+    /// source soon, so an idle on-demand source is woken up.
+    pub async fn describe(&self, path: &SourcePathRef) -> Option<Result<Sdp, SdpError>> {
+        let source = self.sources.read().await.get(path).cloned();
+        if let Some(source) = source {
+            source.lock().await.wake();
+        }
+        self.describe_cached(path).await
+    }
+
+    async fn describe_cached(&self, path: &SourcePathRef) -> Option<Result<Sdp, SdpError>> {
+        let (cached_description, version) = self.source_descriptions_cache.read().await.get(path);
         if let Some(description) = cached_description {
             tracing::trace!(%path, "pulled SDP from cache");
             Some(Ok(description))
@@ -114,14 +298,52 @@ impl SourceManager {
             if let Some(source) = source {
                 let source_name = source.lock().await.name.clone();
                 let source_descriptor = source.lock().await.descriptor.clone();
-                let description = sdp::create(&source_name, &source_descriptor).await;
-                if let Ok(description) = description.as_ref() {
-                    self.source_descriptions_cache
-                        .write()
-                        .await
-                        .insert(path.into(), description.clone());
-                    tracing::trace!(%path, "cached SDP");
-                }
+                let source_fallbacks = source.lock().await.fallbacks.clone();
+                let source_vod = source.lock().await.vod;
+                let description = if source_descriptor.is_published() {
+                    // The demuxer of a published source holds on to the ports that
+                    // media arrives on, so we ask the running source instead.
+                    let mut source_delegate = source.lock().await.delegate();
+                    match source_delegate.query_media_info().await {
+                        Some(media_info) => {
+                            sdp::create_from_media_info(&source_name, media_info).await
+                        }
+                        None => Err(SdpError::SourceUnavailable),
+                    }
+                } else {
+                    // If the primary source is unavailable, the fallback that would be
+                    // played instead describes the source.
+                    let mut description =
+                        sdp::create(&source_name, &source_descriptor, source_vod).await;
+                    for fallback in source_fallbacks.iter() {
+                        if description.is_ok() {
+                            break;
+                        }
+                        description = sdp::create(&source_name, fallback, source_vod).await;
+                    }
+                    description
+                };
+                let description = match description {
+                    Ok(description) => {
+                        let mut cache = self.source_descriptions_cache.write().await;
+                        match cache.insert_if_current(path, version, description.clone()) {
+                            Some(description) => {
+                                tracing::trace!(%path, ?version, "cached SDP");
+                                Ok(description)
+                            }
+                            // The source started over while the SDP was being created,
+                            // so it may describe the old media.
+                            None => match cache.get(path) {
+                                (Some(description), _) => Ok(description),
+                                (None, _) => {
+                                    tracing::trace!(%path, "source started over while describing");
+                                    Ok(description.with_session_version(version.unwrap_or(0)))
+                                }
+                            },
+                        }
+                    }
+                    Err(err) => Err(err),
+                };
                 Some(description)
             } else {
                 tracing::trace!(path, "tried to query SDP for source that does not exist");
@@ -130,6 +352,85 @@ impl SourceManager {
         }
     }
 
+    /// Replace the cached SDP of a source that started over, for instance
+    /// because the camera rebooted with another resolution. The new SDP is
+    /// created from the media of the new stream, so that the source is not
+    /// opened another time just to describe it.
+    async fn redescribe(
+        source: &SourceShared,
+        source_descriptions_cache: &SourceDescriptionsCache,
+        path: &SourcePathRef,
+        media_info: MediaInfo,
+    ) {
+        let (source_name, source_vod) = {
+            let source = source.lock().await;
+            (source.name.clone(), source.vod)
+        };
+        if source_vod {
+            // Video-on-demand sources never start over.
+            return;
+        }
+
+        // Whoever asks for the SDP in the meantime should not get the old one.
+        let version = source_descriptions_cache.write().await.invalidate(path);
+
+        match sdp::create_from_media_info(&source_name, media_info).await {
+            Ok(description) => {
+                // The source may have been removed or reset again in the meantime.
+                if source_descriptions_cache
+                    .write()
+                    .await
+                    .insert_if_current(path, Some(version), description)
+                    .is_some()
+                {
+                    tracing::debug!(%path, version, "replaced SDP after source reset");
+                }
+            }
+            Err(err) => {
+                tracing::warn!(%path, %err, "failed to create SDP after source reset");
+            }
+        }
+    }
+
+    /// Get the source that is registered at the path, if it is the one with
+    /// the given id.
+    async fn find(sources: &SourceMap, path: &SourcePathRef, id: SourceId) -> Option<SourceShared> {
+        let source = sources.read().await.get(path).cloned()?;
+        let is_same = source.lock().await.id == id;
+        is_same.then_some(source)
+    }
+
+    pub async fn status(&self, path: &SourcePathRef) -> Option<SourceStatus> {
+        let source = self.sources.read().await.get(path).cloned();
+        if let Some(source) = source {
+            Some(source.lock().await.status())
+        } else {
+            tracing::trace!(path, "tried to query status of source that does not exist");
+            None
+        }
+    }
+
+    /// If the source is not live, get the time after which clients should
+    /// try again. A source that gave up connecting is woken up to try again in
+    /// the meantime.
+    pub async fn retry_after(&self, path: &SourcePathRef) -> Option<Duration> {
+        let source = self.sources.read().await.get(path).cloned()?;
+        let source = source.lock().await;
+        match source.status() {
+            SourceStatus::Failed => {
+                source.wake();
+                Some(source.reconnect.initial_delay)
+            }
+            SourceStatus::Retrying | SourceStatus::Stalled => Some(source.reconnect.initial_delay),
+            // On-demand sources only connect once someone asks for them, and
+            // that client is meant to wait.
+            SourceStatus::Connecting if source.on_demand.is_none() => {
+                Some(source.reconnect.initial_delay)
+            }
+            _ => None,
+        }
+    }
+
     pub async fn subscribe(&self, path: &SourcePathRef) -> Option<SourceDelegate> {
         let source = self.sources.read().await.get(path).cloned();
         if let Some(source) = source {
@@ -143,6 +444,7 @@ impl SourceManager {
 
     async fn run(
         sources: SourceMap,
+        source_descriptions_cache: SourceDescriptionsCache,
         mut source_state_rx: SourceStateRx,
         mut task_context: TaskContext,
     ) {
@@ -151,9 +453,23 @@ impl SourceManager {
               // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
               state = source_state_rx.recv() => {
                 match state {
-                  Some(SourceState::Stopped(source_id)) => {
-                    tracing::trace!(%source_id, "source manager: received stopped");
-                    let _ = sources.write().await.remove(&source_id);
+                  Some(SourceState::Reset(path, id, media_info)) => {
+                    tracing::trace!(%path, %id, "source manager: received reset");
+                    if let Some(source) = Self::find(&sources, &path, id).await {
+                      Self::redescribe(&source, &source_descriptions_cache, &path, media_info).await;
+                    }
+                  },
+                  Some(SourceState::Stopped(path, id)) => {
+                    tracing::trace!(%path, %id, "source manager: received stopped");
+                    if let Some(source) = Self::find(&sources, &path, id).await {
+                      let mut sources = sources.write().await;
+                      // The source may have been removed in the meantime, and another
+                      // one registered in its place.
+                      if sources.get(&path).map(|current| Arc::ptr_eq(current, &source)) == Some(true) {
+                        sources.remove(&path);
+                        source_descriptions_cache.write().await.remove(&path);
+                      }
+                    }
                   },
                   None => {
                     tracing::error!("source state channel broke unexpectedly");
@@ -189,3 +505,77 @@ impl fmt::Display for RegisterSourceError {
 }
 
 impl error::Error for RegisterSourceError {}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use oddity_sdp_protocol::TimeRange;
+
+    use super::*;
+
+    fn sdp() -> Sdp {
+        let addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        Sdp::new(addr, "test".to_string(), addr, TimeRange::Live)
+    }
+
+    #[test]
+    fn first_description_has_version_zero() {
+        let mut descriptions = SourceDescriptions::default();
+        let (cached, version) = descriptions.get("/test");
+        assert!(cached.is_none());
+        assert_eq!(version, None);
+        let stored = descriptions
+            .insert_if_current("/test", version, sdp())
+            .unwrap();
+        assert_eq!(stored.origin_session_version, "0");
+        let (cached, version) = descriptions.get("/test");
+        assert_eq!(cached.unwrap().origin_session_version, "0");
+        assert_eq!(version, Some(0));
+    }
+
+    #[test]
+    fn version_bumps_when_source_starts_over() {
+        let mut descriptions = SourceDescriptions::default();
+        descriptions
+            .insert_if_current("/test", None, sdp())
+            .unwrap();
+        assert_eq!(descriptions.invalidate("/test"), 1);
+        let (cached, version) = descriptions.get("/test");
+        assert!(cached.is_none());
+        assert_eq!(version, Some(1));
+        let stored = descriptions
+            .insert_if_current("/test", Some(1), sdp())
+            .unwrap();
+        assert_eq!(stored.origin_session_version, "1");
+        assert_eq!(descriptions.invalidate("/test"), 2);
+    }
+
+    #[test]
+    fn stale_description_is_not_stored() {
+        let mut descriptions = SourceDescriptions::default();
+        // Someone starts describing the source, which then starts over and
+        // is described again before the first one is done.
+        let (_, seen_version) = descriptions.get("/test");
+        let version = descriptions.invalidate("/test");
+        descriptions
+            .insert_if_current("/test", Some(version), sdp())
+            .unwrap();
+        assert!(descriptions
+            .insert_if_current("/test", seen_version, sdp())
+            .is_none());
+        let (cached, _) = descriptions.get("/test");
+        assert_eq!(cached.unwrap().origin_session_version, "0");
+    }
+
+    #[test]
+    fn description_of_removed_source_is_not_stored() {
+        let mut descriptions = SourceDescriptions::default();
+        let version = descriptions.invalidate("/test");
+        descriptions.remove("/test");
+        assert!(descriptions
+            .insert_if_current("/test", Some(version), sdp())
+            .is_none());
+        assert_eq!(descriptions.get("/test").1, None);
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::media::video::reader::StreamPacket;
+
+/// Keeps an eye on the packets of a stream, to tell when it stalled. Some
+/// cameras keep the connection open but stop sending frames, or keep sending
+/// the same frame over and over, and the reader never notices.
+pub struct Watchdog {
+    last_packet: Instant,
+    /// Last time the timestamp of any track moved.
+    last_progress: Instant,
+    /// Last RTP timestamp per track.
+    timestamps: HashMap<usize, u32>,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            last_packet: now,
+            last_progress: now,
+            timestamps: HashMap::new(),
+        }
+    }
+
+    pub fn feed(&mut self, packet: &StreamPacket) {
+        let now = Instant::now();
+        self.last_packet = now;
+        if let Some(timestamp) = packet.rtp_timestamp() {
+            if self.timestamps.insert(packet.track, timestamp) != Some(timestamp) {
+                self.last_progress = now;
+            }
+        }
+    }
+
+    /// Whether or not the stream has not made progress for the given time,
+    /// and why.
+    pub fn stall(&self, after: Duration) -> Option<Stall> {
+        if self.last_packet.elapsed() >= after {
+            Some(Stall::NoPackets)
+        } else if self.last_progress.elapsed() >= after {
+            Some(Stall::TimestampsStuck)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stall {
+    NoPackets,
+    TimestampsStuck,
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stall::NoPackets => write!(f, "no packets"),
+            Stall::TimestampsStuck => write!(f, "timestamps stopped advancing"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use tokio::time::advance;
+
+    use crate::media::rtp::SharedRtpBuf;
+    use crate::media::video::reader::StreamPacket;
+    use crate::source::Source;
+
+    use super::{Stall, Watchdog};
+
+    /// Default of the `stall_timeout` setting, after which the stream is
+    /// restarted.
+    const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn packet(timestamp: u32) -> StreamPacket {
+        let mut packet = vec![2 << 6, 96, 0, 0];
+        packet.extend(timestamp.to_be_bytes());
+        packet.extend([0; 4]);
+        StreamPacket {
+            track: 0,
+            bufs: vec![SharedRtpBuf::Rtp(packet.into())],
+            keyframe: false,
+            epoch: 0,
+            number: 0,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalls_without_packets() {
+        let mut watchdog = Watchdog::new();
+        watchdog.feed(&packet(0));
+        advance(Source::STALLED_AFTER - Duration::from_millis(1)).await;
+        assert_eq!(watchdog.stall(Source::STALLED_AFTER), None);
+        advance(Duration::from_millis(1)).await;
+        assert_eq!(
+            watchdog.stall(Source::STALLED_AFTER),
+            Some(Stall::NoPackets)
+        );
+        // Stalled, but not for long enough to restart.
+        assert_eq!(watchdog.stall(STALL_TIMEOUT), None);
+        advance(STALL_TIMEOUT - Source::STALLED_AFTER).await;
+        assert_eq!(watchdog.stall(STALL_TIMEOUT), Some(Stall::NoPackets));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalls_when_timestamps_stop_advancing() {
+        let mut watchdog = Watchdog::new();
+        for _ in 0..STALL_TIMEOUT.as_secs() {
+            watchdog.feed(&packet(3000));
+            advance(Duration::from_secs(1)).await;
+        }
+        watchdog.feed(&packet(3000));
+        assert_eq!(
+            watchdog.stall(Source::STALLED_AFTER),
+            Some(Stall::TimestampsStuck)
+        );
+        assert_eq!(watchdog.stall(STALL_TIMEOUT), Some(Stall::TimestampsStuck));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_stall_while_timestamps_advance() {
+        let mut watchdog = Watchdog::new();
+        for second in 0..STALL_TIMEOUT.as_secs() as u32 * 2 {
+            watchdog.feed(&packet(second * 90_000));
+            advance(Duration::from_secs(1)).await;
+            assert_eq!(watchdog.stall(Source::STALLED_AFTER), None);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recovers_once_timestamps_advance_again() {
+        let mut watchdog = Watchdog::new();
+        watchdog.feed(&packet(0));
+        advance(Source::STALLED_AFTER).await;
+        assert!(watchdog.stall(Source::STALLED_AFTER).is_some());
+        watchdog.feed(&packet(90_000));
+        assert_eq!(watchdog.stall(Source::STALLED_AFTER), None);
+    }
+}
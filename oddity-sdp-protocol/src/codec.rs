@@ -1,6 +1,9 @@
 use std::fmt::Write;
 
-pub use super::{fmt::FMT_RTP_PAYLOAD_DYNAMIC, Tag};
+pub use super::{
+    fmt::{FMT_RTP_PAYLOAD_DYNAMIC, FMT_RTP_PAYLOAD_DYNAMIC_AUDIO},
+    Tag,
+};
 
 pub trait MediaAttributes {
     fn media_attributes(&self) -> Vec<Tag>;
@@ -8,6 +11,7 @@ pub trait MediaAttributes {
 
 pub enum CodecInfo<'params> {
     H264(H264CodecParameters<'params>),
+    Aac(AacCodecParameters<'params>),
 }
 
 impl<'params> CodecInfo<'params> {
@@ -22,6 +26,21 @@ impl<'params> CodecInfo<'params> {
             packetization_mode,
         })
     }
+
+    pub fn aac(sample_rate: u32, channels: u32, config: &'params [u8]) -> Self {
+        Self::Aac(AacCodecParameters {
+            sample_rate,
+            channels,
+            config,
+        })
+    }
+
+    pub fn payload_type(&self) -> usize {
+        match self {
+            CodecInfo::H264(_) => FMT_RTP_PAYLOAD_DYNAMIC,
+            CodecInfo::Aac(_) => FMT_RTP_PAYLOAD_DYNAMIC_AUDIO,
+        }
+    }
 }
 
 pub struct H264CodecParameters<'params> {
@@ -30,6 +49,12 @@ pub struct H264CodecParameters<'params> {
     packetization_mode: usize,
 }
 
+pub struct AacCodecParameters<'params> {
+    sample_rate: u32,
+    channels: u32,
+    config: &'params [u8],
+}
+
 impl MediaAttributes for CodecInfo<'_> {
     fn media_attributes(&self) -> Vec<Tag> {
         match self {
@@ -37,6 +62,10 @@ impl MediaAttributes for CodecInfo<'_> {
                 h264_rtpmap(),
                 h264_fmtp(params.packetization_mode, params.sps, params.pps),
             ],
+            CodecInfo::Aac(params) => vec![
+                aac_rtpmap(params.sample_rate, params.channels),
+                aac_fmtp(params.config),
+            ],
         }
     }
 }
@@ -70,3 +99,31 @@ fn h264_fmtp(packetization_mode: usize, sps: &[u8], pps: &[&[u8]]) -> Tag {
         ),
     )
 }
+
+fn aac_rtpmap(sample_rate: u32, channels: u32) -> Tag {
+    Tag::Value(
+        "rtpmap".to_string(),
+        format!(
+            "{} MPEG4-GENERIC/{}/{}",
+            FMT_RTP_PAYLOAD_DYNAMIC_AUDIO, sample_rate, channels
+        ),
+    )
+}
+
+/// Parameters for the AAC-hbr mode of RFC 3640, which is what the ffmpeg RTP
+/// muxer produces.
+fn aac_fmtp(config: &[u8]) -> Tag {
+    let config = config.iter().fold(String::new(), |mut output, b| {
+        let _ = write!(output, "{b:02X}");
+        output
+    });
+
+    Tag::Value(
+        "fmtp".to_string(),
+        format!(
+            "{} streamtype=5; profile-level-id=1; mode=AAC-hbr; sizelength=13; indexlength=3; \
+             indexdeltalength=3; config={}",
+            FMT_RTP_PAYLOAD_DYNAMIC_AUDIO, config,
+        ),
+    )
+}
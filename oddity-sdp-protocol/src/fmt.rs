@@ -1 +1,4 @@
 pub const FMT_RTP_PAYLOAD_DYNAMIC: usize = 96;
+/// The ffmpeg RTP muxer uses the first dynamic payload type for video and
+/// the next one for audio.
+pub const FMT_RTP_PAYLOAD_DYNAMIC_AUDIO: usize = 97;
@@ -3,7 +3,6 @@ use std::net::IpAddr;
 
 use super::{
     codec::{CodecInfo, MediaAttributes},
-    fmt::FMT_RTP_PAYLOAD_DYNAMIC,
     ip::ip_addr_type,
     time::unix_epoch_timestamp,
     timing::TimeRange,
@@ -97,11 +96,19 @@ impl Sdp {
             kind,
             port,
             protocol,
-            format: FMT_RTP_PAYLOAD_DYNAMIC,
+            format: codec_info.payload_type(),
             tags,
         });
         self
     }
+
+    /// Add a tag to the media description that was added last.
+    pub fn with_media_tag(mut self, tag: Tag) -> Self {
+        if let Some(media) = self.media.last_mut() {
+            media.tags.push(tag);
+        }
+        self
+    }
 }
 
 impl fmt::Display for Sdp {